@@ -3,38 +3,169 @@ extern crate rlox_scanner;
 extern crate rlox_parser;
 
 
-use rlox_interpreter::Interpreter;
-use clap::{App,};
+use rlox_contract::Stmt;
+use rlox_contract::diagnostics::Diagnostic;
+use rlox_interpreter::{Interpreter, ResolveError, Resolver};
+use rlox_parser::{ast_printer, formatter, ParseError, Parser};
+use rlox_scanner::Scanner;
+use rlox_vm::Vm;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::io::Write;
 use simplelog::{TermLogger,LevelFilter,Config,TerminalMode,ColorChoice};
 
+/// Exit code for input that doesn't scan, parse or resolve.
+const EXIT_DATA_ERROR: i32 = 65;
+/// Exit code for a script that stopped with a runtime error.
+const EXIT_SOFTWARE: i32 = 70;
+
 fn main() -> std::io::Result<()> {
+    let file = || Arg::with_name("FILE").required(true).help("the Lox source file");
     let matches = App::new("rlox")
                         .version("0.1")
-                        .args_from_usage(
-                            "[FILE]         'the input file to use (if none, enter REPL)'
-                             -d --debug     'if true, will print intermediate parse trees'
-                            "
-                        )
+                        .setting(AppSettings::VersionlessSubcommands)
+                        .arg(Arg::with_name("debug")
+                            .short("d")
+                            .long("debug")
+                            .global(true)
+                            .help("raises the log level from error to trace"))
+                        .subcommand(SubCommand::with_name("run")
+                            .about("runs a script")
+                            .arg(file())
+                            .arg(Arg::with_name("backend")
+                                .short("b")
+                                .long("backend")
+                                .takes_value(true)
+                                .possible_values(&["tree", "vm"])
+                                .default_value("tree")
+                                .help("the backend used to run the script: the tree-walking interpreter or the bytecode vm")))
+                        .subcommand(SubCommand::with_name("repl")
+                            .about("starts an interactive session (the default)"))
+                        .subcommand(SubCommand::with_name("tokens")
+                            .about("prints the tokens a file is scanned into")
+                            .arg(file()))
+                        .subcommand(SubCommand::with_name("ast")
+                            .about("prints the syntax tree of a file")
+                            .arg(file()))
+                        .subcommand(SubCommand::with_name("check")
+                            .about("reports every error in a file found without running it")
+                            .arg(file()))
+                        .subcommand(SubCommand::with_name("fmt")
+                            .about("prints a file laid out in the standard style")
+                            .arg(file()))
                         .get_matches();
-    
-    if 0 == matches.occurrences_of("debug") {
-        TermLogger::init(LevelFilter::Error, Config::default(), TerminalMode::Mixed, ColorChoice::Auto).expect("Unable to construct logger");
+
+    let debug = match matches.subcommand() {
+        (_, Some(sub)) => sub.is_present("debug"),
+        _ => matches.is_present("debug")
+    };
+    let level = if debug { LevelFilter::Trace } else { LevelFilter::Error };
+    TermLogger::init(level, Config::default(), TerminalMode::Mixed, ColorChoice::Auto).expect("Unable to construct logger");
+
+    match matches.subcommand() {
+        ("run", Some(args)) => run(args),
+        ("tokens", Some(args)) => tokens(args),
+        ("ast", Some(args)) => ast(args),
+        ("check", Some(args)) => check(args),
+        ("fmt", Some(args)) => fmt(args),
+        _ => repl()
+    }
+}
+
+fn run(args: &ArgMatches) -> std::io::Result<()> {
+    let (path, source) = read_file(args)?;
+    let (result, exit_code) = if args.value_of("backend") == Some("vm") {
+        let mut vm = Vm::default();
+        vm.set_source_name(path);
+        (vm.execute_source(source), vm.exit_code())
     } else {
-        TermLogger::init(LevelFilter::Trace, Config::default(), TerminalMode::Mixed, ColorChoice::Auto).expect("Unable to construct logger");
+        let mut interpreter = Interpreter::default();
+        interpreter.set_script_path(path);
+        (interpreter.execute_source(source), interpreter.exit_code())
     };
-    let mut interpreter = Interpreter::default();      
+    // the errors have already been rendered to stderr
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => std::process::exit(EXIT_DATA_ERROR),
+        Err(_) => std::process::exit(EXIT_SOFTWARE),
+        Ok(()) => ()
+    }
+    if let Some(code) = exit_code {
+        std::process::exit(code);
+    }
+    Ok(())
+}
+
+fn repl() -> std::io::Result<()> {
+    let mut interpreter = Interpreter::default();
     let stdin = std::io::stdin();
-    if let Some(f) = matches.value_of("FILE") {
-        let source = std::fs::read_to_string(std::path::Path::new(f))?;
-        interpreter.execute_source(source)?;
+    let mut stdout = std::io::stdout();
+    interpreter.start_repl(&stdin, &mut stdout)?;
+    if let Some(code) = interpreter.exit_code() {
+        std::process::exit(code);
+    }
+    Ok(())
+}
 
-    } else {
-        let mut stdout = std::io::stdout();
-        interpreter.start_repl(&stdin, &mut stdout)?;
+fn tokens(args: &ArgMatches) -> std::io::Result<()> {
+    let (path, source) = read_file(args)?;
+    match Scanner::new().scan(&source) {
+        Ok(tokens) => {
+            let mut out = std::io::stdout().lock();
+            for t in tokens.iter() {
+                writeln!(out, "{}", t)?;
+            }
+            Ok(())
+        },
+        Err(e) => fail(path, &source, &[e.diagnostic()])
+    }
+}
 
+fn ast(args: &ArgMatches) -> std::io::Result<()> {
+    let (path, source) = read_file(args)?;
+    let mut out = std::io::stdout().lock();
+    for s in parse(path, &source) {
+        writeln!(out, "{}", ast_printer::print(&s).trim())?;
     }
+    Ok(())
+}
 
+fn check(args: &ArgMatches) -> std::io::Result<()> {
+    let (path, source) = read_file(args)?;
+    let tokens = Scanner::new().scan(&source).unwrap_or_else(|e| fail(path, &source, &[e.diagnostic()]));
+    let mut parser = Parser::new();
+    parser.add_tokens(*tokens);
+    // resolve whatever the parser recovered too, so one run shows every error it can
+    let (mut stmts, errors) = parser.parse_with_errors();
+    let mut diagnostics: Vec<Diagnostic> = errors.iter().flat_map(ParseError::diagnostics).collect();
+    if let Err(errors) = Resolver::new().resolve(&mut stmts) {
+        diagnostics.extend(errors.iter().map(ResolveError::diagnostic));
+    }
+    if !diagnostics.is_empty() {
+        fail(path, &source, &diagnostics);
+    }
     Ok(())
 }
 
+fn fmt(args: &ArgMatches) -> std::io::Result<()> {
+    let (path, source) = read_file(args)?;
+    write!(std::io::stdout(), "{}", formatter::format(&parse(path, &source)))
+}
+
+fn read_file<'a>(args: &'a ArgMatches) -> std::io::Result<(&'a str, String)> {
+    let path = args.value_of("FILE").expect("FILE is required");
+    Ok((path, std::fs::read_to_string(std::path::Path::new(path))?))
+}
 
+/// Scans and parses `source`, reporting every error found and exiting if there are any.
+fn parse(path: &str, source: &str) -> Vec<Stmt> {
+    let tokens = Scanner::new().scan(source).unwrap_or_else(|e| fail(path, source, &[e.diagnostic()]));
+    let mut parser = Parser::new();
+    parser.add_tokens(*tokens);
+    parser.parse().unwrap_or_else(|e| fail(path, source, &e.diagnostics()))
+}
+
+fn fail(path: &str, source: &str, diagnostics: &[Diagnostic]) -> ! {
+    for d in diagnostics {
+        eprint!("{}", d.render(path, source));
+    }
+    std::process::exit(EXIT_DATA_ERROR);
+}
@@ -0,0 +1,92 @@
+
+#[cfg(test)]
+mod cli_tests {
+    use std::path::PathBuf;
+    use std::process::{Command, Output};
+
+    /// Writes `source` to a fresh file named `name`.
+    fn script(name: &str, source: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rlox-cli-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, source).unwrap();
+        path
+    }
+
+    fn rlox(args: &[&str], path: &PathBuf) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_rlox-cli")).args(args).arg(path).output().expect("failed to run rlox")
+    }
+
+    #[test]
+    fn it_accepts_empty_files_with_every_command() {
+        for (name, source) in [("empty.lox", ""), ("blank.lox", "  \n\n")] {
+            let path = script(name, source);
+            for command in [&["run"][..], &["run", "--backend", "vm"], &["tokens"], &["ast"], &["check"], &["fmt"]] {
+                let output = rlox(command, &path);
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                assert!(output.status.success(), "{:?} on {}: {}", command, name, stderr);
+                assert!(stderr.is_empty(), "{:?} on {}: {}", command, name, stderr);
+            }
+        }
+    }
+
+    #[test]
+    fn it_exits_with_the_code_passed_to_exit_on_every_backend() {
+        let path = script("exit.lox", "print 1;\nexit(3);\nprint 2;\n");
+        for backend in ["tree", "vm"] {
+            let output = rlox(&["run", "--backend", backend], &path);
+            assert_eq!(Some(3), output.status.code(), "{}", backend);
+            assert_eq!("1.00\n", String::from_utf8_lossy(&output.stdout), "{}", backend);
+        }
+    }
+
+    #[test]
+    fn it_exits_with_a_data_error_for_scripts_that_do_not_compile_on_every_backend() {
+        for (name, source) in [("parse.lox", "print 0;\nprint 1 +;\n"), ("resolve.lox", "print 0;\nreturn 1;\n")] {
+            let path = script(name, source);
+            for backend in ["tree", "vm"] {
+                let output = rlox(&["run", "--backend", backend], &path);
+                assert_eq!(Some(65), output.status.code(), "{} on {}", backend, name);
+                assert!(output.stdout.is_empty(), "{} on {}", backend, name);
+                assert!(!output.stderr.is_empty(), "{} on {}", backend, name);
+            }
+        }
+    }
+
+    #[test]
+    fn it_explains_that_the_vm_does_not_run_imports() {
+        script("greet.lox", "fun greet() { print \"hi\"; }\n");
+        let path = script("importer.lox", "import { greet } from \"greet.lox\";\ngreet();\n");
+        let output = rlox(&["run", "--backend", "vm"], &path);
+        assert_eq!(Some(65), output.status.code());
+        assert!(output.stdout.is_empty());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("C0007") && stderr.contains("The bytecode VM doesn't support imports")
+            && stderr.contains("--backend tree"), "{}", stderr);
+
+        let output = rlox(&["run"], &path);
+        assert_eq!(Some(0), output.status.code());
+        assert_eq!("hi\n", String::from_utf8_lossy(&output.stdout));
+    }
+
+    #[test]
+    fn it_exits_with_a_software_error_for_runtime_errors_on_every_backend() {
+        let path = script("runtime.lox", "print 0;\nprint -nil;\n");
+        for backend in ["tree", "vm"] {
+            let output = rlox(&["run", "--backend", backend], &path);
+            assert_eq!(Some(70), output.status.code(), "{}", backend);
+            assert_eq!("0.00\n", String::from_utf8_lossy(&output.stdout), "{}", backend);
+        }
+    }
+
+    #[test]
+    fn it_checks_past_errors_inside_blocks() {
+        let path = script("errors.lox", "fun f() {\n    var x = ;\n    print x;\n}\n{\n    print 1 +;\n    var a = 1;\n    var a = 2;\n}\nprint ];\n");
+        let output = rlox(&["check"], &path);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(Some(65), output.status.code());
+        assert_eq!(3, stderr.matches("error[P0001]").count(), "{}", stderr);
+        assert!(!stderr.contains("RightBrace"), "{}", stderr);
+        assert!(stderr.contains("error[R0004]: Already a variable named a in this scope"), "{}", stderr);
+    }
+}
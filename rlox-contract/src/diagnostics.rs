@@ -0,0 +1,163 @@
+use crate::Span;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note
+}
+
+impl Display for Severity {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note")
+        }
+    }
+}
+
+/// A problem found while scanning, parsing, resolving or running a program, in a form that can be
+/// rendered against the source it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    severity: Severity,
+    code: String,
+    message: String,
+    span: Option<Span>,
+    notes: Vec<String>,
+    help: Option<String>
+}
+
+impl Diagnostic {
+    pub fn new<B, C>(severity: Severity, code: B, message: C) -> Diagnostic where B : ToString, C : ToString {
+        Diagnostic { severity, code: code.to_string(), message: message.to_string(), span: None, notes: Vec::new(), help: None }
+    }
+
+    pub fn error<B, C>(code: B, message: C) -> Diagnostic where B : ToString, C : ToString {
+        Diagnostic::new(Severity::Error, code, message)
+    }
+
+    pub fn with_span(mut self, span: Option<Span>) -> Diagnostic {
+        self.span = span;
+        self
+    }
+
+    pub fn with_note<B>(mut self, note: B) -> Diagnostic where B : ToString {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn with_help<B>(mut self, help: B) -> Diagnostic where B : ToString {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
+    pub fn help(&self) -> Option<&str> {
+        self.help.as_deref()
+    }
+
+    /// Renders the diagnostic in the style of rustc: a header, the location, the offending source
+    /// line with the span underlined, then any notes and help.
+    pub fn render(&self, source_name: &str, source: &str) -> String {
+        let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        let line = self.span.and_then(|s| source.lines().nth(s.start_line.saturating_sub(1)).map(|l| (s, l)));
+        let gutter = match &line {
+            Some((s, _)) => " ".repeat(s.start_line.to_string().len()),
+            None => String::new()
+        };
+
+        match self.span {
+            Some(s) => out.push_str(&format!("{}--> {}:{}\n", gutter, source_name, s)),
+            None => out.push_str(&format!("{}--> {}\n", gutter, source_name))
+        }
+
+        if let Some((s, text)) = line {
+            let chars: Vec<char> = text.chars().collect();
+            let start = s.start_col.saturating_sub(1).min(chars.len());
+            let end = if s.end_line == s.start_line { s.end_col.max(s.start_col) } else { chars.len() };
+            let width = end.min(chars.len()).saturating_sub(start).max(1);
+            let padding: String = chars[..start].iter().map(|c| if *c == '\t' { '\t' } else { ' ' }).collect();
+
+            out.push_str(&format!("{} |\n", gutter));
+            out.push_str(&format!("{} | {}\n", s.start_line, text));
+            out.push_str(&format!("{} | {}{}\n", gutter, padding, "^".repeat(width)));
+        }
+
+        if !self.notes.is_empty() || self.help.is_some() {
+            out.push_str(&format!("{} |\n", gutter));
+        }
+        for n in &self.notes {
+            out.push_str(&format!("{} = note: {}\n", gutter, n));
+        }
+        if let Some(h) = &self.help {
+            out.push_str(&format!("{} = help: {}\n", gutter, h));
+        }
+        out
+    }
+}
+
+impl Display for Diagnostic {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{}[{}]", self.severity, self.code)?;
+        if let Some(span) = self.span {
+            write!(f, " at {}", span)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, Span};
+
+    #[test]
+    fn it_renders_the_source_line_with_a_caret_under_the_span() {
+        let source = "var a = 1;\nprint a - \"x\";\n";
+        let rendered = Diagnostic::error("E0001", "Operator MINUS expects two numbers")
+            .with_span(Some(Span::new(2, 7, 2, 13)))
+            .with_note("left operand is a number")
+            .with_help("convert the string with num()")
+            .render("test.lox", source);
+
+        let expected = "error[E0001]: Operator MINUS expects two numbers
+ --> test.lox:2:7
+  |
+2 | print a - \"x\";
+  |       ^^^^^^^
+  |
+  = note: left operand is a number
+  = help: convert the string with num()
+";
+        assert_eq!(expected, rendered);
+    }
+
+    #[test]
+    fn it_renders_without_a_snippet_when_the_span_is_unknown() {
+        let rendered = Diagnostic::error("E0001", "Stack overflow").render("test.lox", "");
+        assert_eq!("error[E0001]: Stack overflow\n--> test.lox\n", rendered);
+    }
+}
@@ -1,4 +1,5 @@
 use std::fmt::Display;
+pub mod diagnostics;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum LiteralTokenType {
@@ -9,14 +10,17 @@ pub enum LiteralTokenType {
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
-    LeftParen, RightParen, LeftBrace, RightBrace, Comma, Dot, Minus, Plus, Semicolon, Star,
+    LeftParen, RightParen, LeftBrace, RightBrace, LeftBracket, RightBracket, Comma, Colon, Dot, Minus, Plus, Semicolon, Star,
     Slash, 
     Bang, BangEqual, Equal, EqualEqual, Greater, GreaterEqual, Less, LessEqual,
 
     Literal(LiteralTokenType),
+    /// The text of a string literal up to a `${`. The interpolated expression's tokens follow,
+    /// then the rest of the string as another `Interpolation` or a plain string literal.
+    Interpolation(String),
 
     And, Class, Else, False, Fun, For, If, Nil, Or, Print, Return, Super, This,
-    True, Var, While,
+    True, Var, While, Break, Continue, Import,
 
     Eof
 }
@@ -41,6 +45,47 @@ impl TokenContext {
     pub fn token(&self) -> &Token {
         &self.token
     }
+
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    pub fn start_char_offset(&self) -> usize {
+        self.start_char_offset
+    }
+
+    pub fn span(&self) -> Span {
+        let start_col = self.start_char_offset + 1;
+        Span::new(self.line_number, start_col, self.line_number, start_col + self.length().saturating_sub(1))
+    }
+}
+
+/// A region of source code, from the first to the last character of a node.
+/// Lines and columns are both 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start_line: usize,
+    pub start_col: usize,
+    pub end_line: usize,
+    pub end_col: usize
+}
+
+impl Span {
+    pub fn new(start_line: usize, start_col: usize, end_line: usize, end_col: usize) -> Span {
+        Span { start_line, start_col, end_line, end_col }
+    }
+
+    /// Creates a span covering everything from the start of `self` to the end of `other`.
+    pub fn to(&self, other: Span) -> Span {
+        Span::new(self.start_line, self.start_col, other.end_line, other.end_col)
+    }
+}
+
+impl Display for Span {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{}:{}", self.start_line, self.start_col)
+    }
 }
 
 impl Display for TokenContext {
@@ -82,24 +127,47 @@ impl Display for Token {
 
 #[derive(Debug,Clone, PartialEq)]
 pub enum Expr {
-    BinaryExpr { left: Box<Expr>, operator: Token, right: Box<Expr> },
-    GroupingExpr(Box<Expr>),
-    LiteralExpr(ExprLiteralValue),
-    UnaryExpr { operator: Token, right: Box<Expr> },
-    PrintStmt(Box<Expr>),
-    ExprStmt(Box<Expr>),
-    VarDecl {name: Token, initializer: Box<Expr>  },
-    VariableExpr(Token),
-    AssigmentExpr { name: Token, value: Box<Expr> },
-    BlockStmt(Vec<Box<Expr>>),
-    IfStmt { condition: Box<Expr>, then_branch: Box<Expr>, else_branch: Box<Expr>},
-    LogicalExpr { left: Box<Expr>, operator: Token, right: Box<Expr>},
-    WhileLoop { condition: Box<Expr>, body: Box<Expr> },
-    CallExpr { callee: Box<Expr>, paren: Token, arguments: Vec<Box<Expr>>},
-    FunctionExpr { name: Token, params: Vec<Token>, body: Box<Expr>},
-    Return(Token, Box<Expr>)
+    BinaryExpr { left: Box<Expr>, operator: Token, right: Box<Expr>, span: Span },
+    GroupingExpr(Box<Expr>, Span),
+    LiteralExpr(ExprLiteralValue, Span),
+    UnaryExpr { operator: Token, right: Box<Expr>, span: Span },
+    VariableExpr { name: Token, depth: Option<usize>, span: Span },
+    AssigmentExpr { name: Token, value: Box<Expr>, depth: Option<usize>, span: Span },
+    LogicalExpr { left: Box<Expr>, operator: Token, right: Box<Expr>, span: Span },
+    CallExpr { callee: Box<Expr>, paren: Token, arguments: Vec<Box<Expr>>, span: Span },
+    GetExpr { object: Box<Expr>, name: Token, span: Span },
+    SetExpr { object: Box<Expr>, name: Token, value: Box<Expr>, span: Span },
+    ThisExpr { keyword: Token, depth: Option<usize>, span: Span },
+    SuperExpr { keyword: Token, method: Token, depth: Option<usize>, span: Span },
+    ListExpr { elements: Vec<Box<Expr>>, span: Span },
+    MapExpr { entries: Vec<(Box<Expr>, Box<Expr>)>, span: Span },
+    /// An interpolated string, as the pieces whose text is joined to make it.
+    InterpolationExpr { parts: Vec<Box<Expr>>, span: Span },
+    IndexExpr { object: Box<Expr>, index: Box<Expr>, span: Span },
+    IndexSetExpr { object: Box<Expr>, index: Box<Expr>, value: Box<Expr>, span: Span }
 }
 
+#[derive(Debug,Clone, PartialEq)]
+pub enum Stmt {
+    PrintStmt(Box<Expr>, Span),
+    ExprStmt(Box<Expr>, Span),
+    VarDecl { name: Token, initializer: Box<Expr>, span: Span },
+    BlockStmt(Vec<Stmt>, Span),
+    IfStmt { condition: Box<Expr>, then_branch: Box<Stmt>, else_branch: Option<Box<Stmt>>, span: Span },
+    /// `increment` runs after every iteration of the body, including one cut short by `continue`,
+    /// so that `for` loops can be desugared into a while loop.
+    WhileLoop { condition: Box<Expr>, body: Box<Stmt>, increment: Option<Box<Expr>>, span: Span },
+    /// Each parameter comes with the span of its name.
+    FunctionDecl { name: Token, params: Vec<(Token, Span)>, body: Vec<Stmt>, span: Span },
+    /// A bare `return;` has no value.
+    Return(Token, Option<Box<Expr>>, Span),
+    ClassDecl { name: Token, superclass: Option<Box<Expr>>, methods: Vec<Stmt>, span: Span },
+    Break(Span),
+    Continue(Span),
+    /// `import "path";` brings in every global of the module at `path`, while
+    /// `import { a, b } from "path";` brings in only the names listed.
+    Import { path: String, names: Option<Vec<Token>>, span: Span }
+}
 
 #[derive(Debug,Clone, PartialEq)]
 pub enum ExprLiteralValue {
@@ -113,19 +181,47 @@ pub enum ExprLiteralValue {
 
 impl Expr {
     pub fn new_binary_expr(left: Expr, operator: Token, right: Expr) -> Expr {
+        let span = left.span().to(right.span());
         let l = Box::from(left);
         let r = Box::from(right);
 
         let o = operator.clone();
-        Expr::BinaryExpr { left: l, operator: o, right: r}
+        Expr::BinaryExpr { left: l, operator: o, right: r, span }
+    }
+
+    pub fn new_variable_expr(name: Token, span: Span) -> Expr {
+        Expr::VariableExpr { name, depth: None, span }
     }
 
     pub fn new_logical_expr(left: Expr, operator: Token, right: Expr) -> Expr {
+        let span = left.span().to(right.span());
         let l = Box::from(left);
         let r = Box::from(right);
 
         let o = operator.clone();
-        Expr::LogicalExpr { left: l, operator: o, right: r}
+        Expr::LogicalExpr { left: l, operator: o, right: r, span }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::GroupingExpr(_, span) | Expr::LiteralExpr(_, span) => *span,
+            Expr::BinaryExpr { span, .. } | Expr::UnaryExpr { span, .. } | Expr::VariableExpr { span, .. }
+            | Expr::AssigmentExpr { span, .. } | Expr::LogicalExpr { span, .. } | Expr::CallExpr { span, .. }
+            | Expr::GetExpr { span, .. } | Expr::SetExpr { span, .. } | Expr::ThisExpr { span, .. }
+            | Expr::SuperExpr { span, .. } | Expr::ListExpr { span, .. } | Expr::MapExpr { span, .. } | Expr::InterpolationExpr { span, .. } | Expr::IndexExpr { span, .. }
+            | Expr::IndexSetExpr { span, .. } => *span
+        }
+    }
+}
+
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::PrintStmt(_, span) | Stmt::ExprStmt(_, span) | Stmt::BlockStmt(_, span) | Stmt::Return(_, _, span)
+            | Stmt::Break(span) | Stmt::Continue(span) => *span,
+            Stmt::VarDecl { span, .. } | Stmt::IfStmt { span, .. } | Stmt::WhileLoop { span, .. }
+            | Stmt::FunctionDecl { span, .. } | Stmt::ClassDecl { span, .. } | Stmt::Import { span, .. } => *span
+        }
     }
 }
 
@@ -133,10 +229,10 @@ impl Display for Expr {
     
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> { 
         let st = match self {
-            Expr::LiteralExpr(ExprLiteralValue::NilLiteral) => "nil".to_string(),
-            Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(b)) => format!("{}", b),
-            Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)) => format!("{:.2}", n),
-            Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s)) => format!("{}", s.replace('"', "")),
+            Expr::LiteralExpr(ExprLiteralValue::NilLiteral, _) => "nil".to_string(),
+            Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(b), _) => format!("{}", b),
+            Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n), _) => format!("{:.2}", n),
+            Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s), _) => s.clone(),
             _ => "<expression>".to_string()
         };
         write!(f, "{}", st)?;
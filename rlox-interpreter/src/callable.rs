@@ -1,49 +1,135 @@
-use super::{Expr, ExprLiteralValue};
+use super::class::LoxInstance;
+use super::{Interpreter, InterpreterError, LiteralTokenType, ScopeEnvironment, Stmt, Token, Value};
+use std::cell::RefCell;
+use std::rc::Rc;
+
 pub trait Callable {
-    fn arity(&self) -> super::Result<usize>;
-    fn call(&self, global_scope: &super::ScopeEnvironment, args: Vec<Expr>) -> super::Result<Expr>;
+    fn arity(&self) -> usize;
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> super::Result<Value>;
+}
+
+#[derive(Debug, Clone)]
+pub struct LoxFunction {
+    declaration: Rc<Stmt>,
+    closure: ScopeEnvironment,
+    /// The globals of the script or module the function was declared in, which its global
+    /// variable references are looked up in wherever it is called from.
+    globals: ScopeEnvironment,
+    is_initializer: bool
 }
 
-impl Callable for Expr {
+impl LoxFunction {
+    pub fn new(declaration: Rc<Stmt>, closure: ScopeEnvironment, globals: ScopeEnvironment, is_initializer: bool) -> LoxFunction {
+        LoxFunction { declaration, closure, globals, is_initializer }
+    }
+
+    pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> super::Result<LoxFunction> {
+        let env = self.closure.new_child();
+        env.declare("this", Value::Instance(instance))?;
+        Ok(LoxFunction { declaration: self.declaration.clone(), closure: env, globals: self.globals.clone(), is_initializer: self.is_initializer })
+    }
 
-    fn arity(&self) -> super::Result<usize> { 
-        match self {
-            Expr::FunctionExpr { name: _, params, body: _} => Ok(params.len()),
-            _ => Err(super::InterpreterError::new("expected callable expression"))
+    /// Calls `visit` with the scopes the function holds on to, for the cycle collector.
+    pub(crate) fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        self.closure.trace(visit);
+        self.globals.trace(visit);
+    }
+
+    pub fn name(&self) -> String {
+        match self.declaration.as_ref() {
+            Stmt::FunctionDecl { name: Token::Literal(LiteralTokenType::IdentifierLiteral(s)), params: _, body: _, span: _ } => s.clone(),
+            _ => "anonymous".to_string()
         }
-     }
-    fn call(&self, global_scope: &super::ScopeEnvironment, args: std::vec::Vec<Expr>) -> std::result::Result<Expr, super::InterpreterError> { 
-        if let Expr::FunctionExpr {name: _, params, body } = &self {
-            
-            let arity = self.arity()?;
-            if arity != args.len() {
-                Err(super::InterpreterError::new("Arity didn't match arg length"))
-            } else {
+    }
+}
+
+impl PartialEq for LoxFunction {
+    fn eq(&self, other: &LoxFunction) -> bool {
+        self.closure.same_scope(&other.closure) && Rc::ptr_eq(&self.declaration, &other.declaration)
+    }
+}
+
+impl Callable for LoxFunction {
 
-                let mut fun_scope = global_scope.clone();
+    fn arity(&self) -> usize {
+        match self.declaration.as_ref() {
+            Stmt::FunctionDecl { name: _, params, body: _, span: _ } => params.len(),
+            _ => 0
+        }
+    }
 
-                for (i,a) in args.iter().enumerate() {
-                    
-                    if let super::Token::Literal(super::LiteralTokenType::IdentifierLiteral(s)) = &params[i] {
-                        fun_scope.declare(&s, Box::from(a.clone()))?;
-                    } else {
-                        return Err(super::InterpreterError::new("Invalid param type, must be identifier"));
-                    }
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> super::Result<Value> {
+        if let Stmt::FunctionDecl { name: _, params, body, span: _ } = self.declaration.as_ref() {
+            let fun_scope = self.closure.new_child();
+            for ((p, _), a) in params.iter().zip(args) {
+                if let Token::Literal(LiteralTokenType::IdentifierLiteral(s)) = p {
+                    fun_scope.declare(s, a)?;
+                } else {
+                    return Err(InterpreterError::new("Invalid param type, must be identifier"));
                 }
-                
-                let r = fun_scope.clone();
-                let mut i = super::Interpreter::with_env(r);
-                let e = match i.interpret(body.clone()) {
-                    Ok(ev) => ev,
-                    Err(super::InterpreterError { msg:_, returned}) => if returned.is_some() { returned.unwrap() } else { Expr::LiteralExpr(ExprLiteralValue::NilLiteral) }
-                };
-                Ok(e)
             }
-            
-            
-         
+
+            let previous = std::mem::replace(&mut interpreter.globals, self.globals.clone());
+            let result = interpreter.execute_block(body, fun_scope);
+            interpreter.globals = previous;
+            let returned = match result {
+                Ok(_) => Value::Nil,
+                Err(InterpreterError { returned: Some(v), .. }) => *v,
+                Err(e) => return Err(e)
+            };
+
+            if self.is_initializer {
+                Ok(self.closure.get("this").unwrap_or(returned))
+            } else {
+                Ok(returned)
+            }
         } else {
-            Err(super::InterpreterError::new(format!("Tried to call an uncallable expression {:?}", &self)))
+            Err(InterpreterError::new(format!("Tried to call an uncallable expression {:?}", &self.declaration)))
         }
-     }
-}
\ No newline at end of file
+    }
+}
+
+pub type NativeFn = dyn Fn(&mut Interpreter, Vec<Value>) -> super::Result<Value>;
+
+/// A function implemented by the host in Rust and exposed to Lox code as a value.
+#[derive(Clone)]
+pub struct NativeFunction {
+    name: String,
+    arity: usize,
+    function: Rc<NativeFn>
+}
+
+impl NativeFunction {
+    pub fn new<B, F>(name: B, arity: usize, function: F) -> NativeFunction
+        where B : ToString, F : Fn(&mut Interpreter, Vec<Value>) -> super::Result<Value> + 'static {
+        NativeFunction { name: name.to_string(), arity, function: Rc::new(function) }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &NativeFunction) -> bool {
+        Rc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+impl std::fmt::Debug for NativeFunction {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "<native fn {}/{}>", self.name, self.arity)
+    }
+}
+
+impl Callable for NativeFunction {
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> super::Result<Value> {
+        (self.function)(interpreter, args)
+    }
+}
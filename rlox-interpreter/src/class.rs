@@ -0,0 +1,103 @@
+use super::callable::{Callable, LoxFunction};
+use super::gc::{self, Node};
+use super::{Interpreter, InterpreterError, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Debug)]
+pub struct LoxClass {
+    name: String,
+    superclass: Option<Rc<LoxClass>>,
+    methods: HashMap<String, LoxFunction>
+}
+
+impl LoxClass {
+    pub fn new(name: &str, superclass: Option<Rc<LoxClass>>, methods: HashMap<String, LoxFunction>) -> LoxClass {
+        LoxClass { name: name.to_string(), superclass, methods }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Some(superclass) = &self.superclass {
+            visit(gc::address(superclass));
+        }
+        for m in self.methods.values() {
+            m.trace(visit);
+        }
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<&LoxFunction> {
+        match self.methods.get(name) {
+            Some(m) => Some(m),
+            None => self.superclass.as_ref().and_then(|s| s.find_method(name))
+        }
+    }
+}
+
+impl Callable for Rc<LoxClass> {
+
+    fn arity(&self) -> usize {
+        self.find_method("init").map(|init| init.arity()).unwrap_or(0)
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> super::Result<Value> {
+        let instance = Rc::new(RefCell::new(LoxInstance::new(self.clone())));
+        gc::track(Node::Instance(instance.clone()));
+        if let Some(init) = self.find_method("init") {
+            init.bind(instance.clone())?.call(interpreter, args)?;
+        }
+        Ok(Value::Instance(instance))
+    }
+}
+
+pub struct LoxInstance {
+    class: Rc<LoxClass>,
+    fields: HashMap<String, Value>
+}
+
+impl LoxInstance {
+    fn new(class: Rc<LoxClass>) -> LoxInstance {
+        LoxInstance { class, fields: HashMap::new() }
+    }
+
+    pub fn class_name(&self) -> &str {
+        self.class.name()
+    }
+
+    pub fn get(instance: &Rc<RefCell<LoxInstance>>, name: &str) -> super::Result<Value> {
+        let inst = instance.borrow();
+        if let Some(v) = inst.fields.get(name) {
+            Ok(v.clone())
+        } else if let Some(method) = inst.class.find_method(name) {
+            Ok(Value::Function(method.bind(instance.clone())?))
+        } else {
+            Err(InterpreterError::new(format!("Undefined property '{}'", name)))
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.fields.insert(name.to_string(), value);
+    }
+
+    pub(crate) fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        visit(gc::address(&self.class));
+        for v in self.fields.values() {
+            gc::trace_value(v, visit);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.fields.clear();
+    }
+}
+
+impl std::fmt::Debug for LoxInstance {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{} instance {:?}", self.class.name(), self.fields.keys().collect::<Vec<_>>())
+    }
+}
@@ -1,130 +1,147 @@
+use crate::gc::{self, Node};
 use crate::InterpreterError;
-use rlox_contract::Expr;
+use crate::Value;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 
 
-#[derive(Debug,Clone)]
+#[derive(Clone)]
 pub struct ScopeEnvironment {
-    scopes: Vec<Scope>,
-    current_idx: usize,
-    previous_idx: usize,
+    current: Rc<RefCell<Scope>>,
 }
 
 impl ScopeEnvironment {
     pub fn new_root() -> ScopeEnvironment {
-        ScopeEnvironment { scopes: vec![Scope::new(None)], current_idx: 0, previous_idx: 0}
-    }
-
-    pub fn new_child(&mut self) -> () {
-
-        self.scopes.push(Scope::new(Some(self.current_idx)));
-        self.previous_idx = self.current_idx;
-        self.current_idx = self.scopes.len()-1;
-        
-        ()
-    }
-
-    pub fn pop_scope(&mut self) -> super::Result<()> {
-        let cs = &self.scopes[self.current_idx];
-        //dbg!(&cs);
-        if let Some(px) = cs.parent_idx {
-            let nxt_ix = px.clone();
-            for i in self.scopes.iter_mut() {
-                let v = match i {
-                    Scope { parent_idx: Some(pi), variable_context: _} => pi >= dbg!(&mut self.current_idx),
-                    _ => false
-                };
-                if v {
-                    i.parent_idx = Some(i.parent_idx.unwrap() - 1);
-                }
+        ScopeEnvironment::tracked(Scope::new(None))
+    }
+
+    pub fn new_child(&self) -> ScopeEnvironment {
+        ScopeEnvironment::tracked(Scope::new(Some(self.current.clone())))
+    }
+
+    fn tracked(scope: Scope) -> ScopeEnvironment {
+        let current = Rc::new(RefCell::new(scope));
+        gc::track(Node::Scope(current.clone()));
+        ScopeEnvironment { current }
+    }
+
+    /// Calls `visit` with the address of this scope, for the cycle collector.
+    pub(crate) fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        visit(gc::address(&self.current));
+    }
+
+    pub fn get(&self, identifier: &str) -> Option<Value> {
+        let mut cx = Some(self.current.clone());
+        while let Some(s) = cx {
+            let scope = s.borrow();
+            if let Some(v) = scope.variable_context.get(identifier) {
+                return Some(v.clone());
             }
-            self.previous_idx = self.scopes[self.current_idx].parent_idx.unwrap_or(0);
-            self.scopes.remove(self.current_idx);
-            
-            self.current_idx = nxt_ix;
-            Ok(())
-        } else {
-            Err(InterpreterError::new("Tried to pop scope but had no parent index"))
+            cx = scope.parent.clone();
         }
+        None
     }
 
-    pub fn get(&self, identifier: &str) -> Option<&Expr> {
+    /// Looks `identifier` up in this scope alone, ignoring its parents.
+    pub fn get_local(&self, identifier: &str) -> Option<Value> {
+        self.current.borrow().variable_context.get(identifier).cloned()
+    }
 
-        let mut cx = Some(self.current_idx);
-        let mut found_expr = None;
-        while cx.is_some() {
-            let v = cx.unwrap();
-            let s = &self.scopes[v];
-            if s.variable_context.contains_key(identifier) {
-                found_expr = Some(s.variable_context.get(identifier).unwrap().as_ref());
-                break;
-            } else {
-                cx = s.parent_idx;
-            }
+    /// Every variable declared directly in this scope, sorted by name.
+    pub fn variables(&self) -> Vec<(String, Value)> {
+        let mut variables: Vec<_> = self.current.borrow().variable_context.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
+    }
 
+    /// The names of every variable visible from this scope, sorted and without duplicates.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut cx = Some(self.current.clone());
+        while let Some(s) = cx {
+            names.extend(s.borrow().variable_context.keys().cloned());
+            cx = s.borrow().parent.clone();
         }
-        found_expr
+        names.sort();
+        names.dedup();
+        names
     }
 
-    pub fn declare(&mut self, identifier: &str, value: Box<Expr>) -> super::Result<()> {
-        if let Some(_) = &self.scopes[self.current_idx].variable_context.get(identifier) {
-            Err(InterpreterError::new(format!("Variable {} already declared", identifier)))
-        } else {
-            let s = &mut self.scopes[self.current_idx];
-            s.declare(identifier, value)?;
-            Ok(())
+    pub fn get_at(&self, distance: usize, identifier: &str) -> Option<Value> {
+        self.ancestor(distance).and_then(|s| s.borrow().variable_context.get(identifier).cloned())
+    }
+
+    pub fn assign_at(&self, distance: usize, identifier: &str, value: Value) -> super::Result<()> {
+        match self.ancestor(distance) {
+            Some(s) => s.borrow_mut().assign(identifier, value),
+            None => Err(InterpreterError::new(format!("No scope at distance {} for variable {}", distance, identifier)))
         }
     }
 
-    pub fn assign(&mut self, identifier: &str, value: Box<Expr>) -> super::Result<()> {
-        if let Some(_) = self.get(identifier) {
-            let mut cx = Some(self.current_idx);
-            while cx.is_some() {
-                let v = cx.unwrap();
-                let s = &self.scopes[v];
-                if s.variable_context.contains_key(identifier) {
-                    break;
-                } else {
-                    cx = s.parent_idx;
-                }
-            }
-            let scope_to_update = cx.unwrap();
-            self.scopes[scope_to_update].assign(identifier, value)?;
-            Ok(())
-        } else {
-            Err(InterpreterError::new(format!("Variable {} assigned but never declared", identifier)))
+    fn ancestor(&self, distance: usize) -> Option<Rc<RefCell<Scope>>> {
+        let mut cx = Some(self.current.clone());
+        for _ in 0..distance {
+            cx = cx.and_then(|s| s.borrow().parent.clone());
         }
+        cx
     }
 
-    pub fn set_to_root(&mut self) -> () {
-        self.previous_idx = self.current_idx;
-        self.current_idx = 0;
-        ()
+    pub fn declare(&self, identifier: &str, value: Value) -> super::Result<()> {
+        self.current.borrow_mut().declare(identifier, value)
     }
 
-    pub fn set_to_previous(&mut self) -> () {
-        let tmp = self.previous_idx;
-        self.previous_idx = self.current_idx;
-        self.current_idx = tmp;
-        ()
+    /// Declares `identifier` in this scope, replacing any variable already declared there.
+    pub fn redeclare(&self, identifier: &str, value: Value) {
+        self.current.borrow_mut().variable_context.insert(identifier.to_string(), value);
+    }
+
+    pub fn assign(&self, identifier: &str, value: Value) -> super::Result<()> {
+        let mut cx = Some(self.current.clone());
+        while let Some(s) = cx {
+            if s.borrow().variable_context.contains_key(identifier) {
+                return s.borrow_mut().assign(identifier, value);
+            }
+            cx = s.borrow().parent.clone();
+        }
+        Err(InterpreterError::new(format!("Variable {} assigned but never declared", identifier)))
+    }
+
+    pub fn same_scope(&self, other: &ScopeEnvironment) -> bool {
+        Rc::ptr_eq(&self.current, &other.current)
     }
 }
 
 #[derive(Debug,Clone)]
 pub struct Scope {
-    variable_context: HashMap<String,Box<Expr>>,
-    parent_idx: Option<usize>
+    variable_context: HashMap<String,Value>,
+    parent: Option<Rc<RefCell<Scope>>>
 }
 
 impl Scope {
-    fn new(parent_idx: Option<usize>) -> Scope {
-        Scope {variable_context: HashMap::new(), parent_idx} 
+    fn new(parent: Option<Rc<RefCell<Scope>>>) -> Scope {
+        Scope {variable_context: HashMap::new(), parent}
     }
 
+    pub(crate) fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Some(parent) = &self.parent {
+            visit(gc::address(parent));
+        }
+        for v in self.variable_context.values() {
+            gc::trace_value(v, visit);
+        }
+    }
 
+    pub(crate) fn clear(&mut self) {
+        self.variable_context.clear();
+    }
+
+    fn depth(&self) -> usize {
+        self.parent.as_ref().map(|p| p.borrow().depth() + 1).unwrap_or(0)
+    }
 
-    fn declare(&mut self, identifier: &str, value: Box<Expr>) -> super::Result<()> {
+    fn declare(&mut self, identifier: &str, value: Value) -> super::Result<()> {
 
         if self.variable_context.contains_key(identifier) {
             Err(InterpreterError::new(format!("Variable {} already defined", identifier)))
@@ -134,7 +151,7 @@ impl Scope {
         }
     }
 
-    fn assign(&mut self, identifier: &str, value: Box<Expr>) -> super::Result<()> {
+    fn assign(&mut self, identifier: &str, value: Value) -> super::Result<()> {
         if !self.variable_context.contains_key(identifier) {
             Err(InterpreterError::new(format!("Variable {} assigned but never declared", identifier)))
         } else {
@@ -145,9 +162,9 @@ impl Scope {
 }
 
 impl std::fmt::Display for Scope {
-    
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> { 
-        write!(f,"Scope~{} [", self.parent_idx.map(|e| format!("{}", e)).unwrap_or("Root".to_string()))?;
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f,"Scope~{} [", if self.parent.is_some() { format!("{}", self.depth()) } else { "Root".to_string() })?;
         for i in &self.variable_context {
             write!(f, "({}, {}) ", i.0, i.1)?;
         }
@@ -157,36 +174,53 @@ impl std::fmt::Display for Scope {
 }
 
 impl std::fmt::Display for ScopeEnvironment {
-    
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> { 
-        writeln!(f, "ScopeEnv@{} [", &self.current_idx)?;
-        for c in &self.scopes {
-            writeln!(f, "{}", c)?;
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        writeln!(f, "ScopeEnv@{} [", self.current.borrow().depth())?;
+        let mut cx = Some(self.current.clone());
+        while let Some(s) = cx {
+            writeln!(f, "{}", s.borrow())?;
+            cx = s.borrow().parent.clone();
         }
         Ok(())
     }
 }
 
+impl std::fmt::Debug for ScopeEnvironment {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "ScopeEnv@{}", self.current.borrow().depth())
+    }
+}
+
 
 #[cfg(test)]
 mod test {
-    use crate::ExprLiteralValue;
     use super::*;
 
     #[test]
     fn it_puts_and_returns_variable_on_single_scope() {
-        let mut s = ScopeEnvironment::new_root();
-        s.declare("test", Box::from(Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(1.1)))).expect("failed to declare");
+        let s = ScopeEnvironment::new_root();
+        s.declare("test", Value::Number(1.1)).expect("failed to declare");
         let r = s.get("test");
-        assert_eq!(Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(1.1)), *r.unwrap());
+        assert_eq!(Value::Number(1.1), r.unwrap());
     }
 
     #[test]
     fn it_puts_on_root_and_gets_from_child() {
-        let mut s = ScopeEnvironment::new_root();
-        s.declare("test", Box::from(Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(1.1)))).expect("failed to declare");
-        s.new_child();
-        let r = s.get("test");
-        assert_eq!(Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(1.1)), *r.unwrap());
+        let s = ScopeEnvironment::new_root();
+        s.declare("test", Value::Number(1.1)).expect("failed to declare");
+        let c = s.new_child();
+        let r = c.get("test");
+        assert_eq!(Value::Number(1.1), r.unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn it_shares_assignments_between_child_and_parent() {
+        let s = ScopeEnvironment::new_root();
+        s.declare("test", Value::Number(1.1)).expect("failed to declare");
+        let c = s.new_child();
+        c.assign("test", Value::Number(2.2)).expect("failed to assign");
+        assert_eq!(Value::Number(2.2), s.get("test").unwrap());
+    }
+}
@@ -0,0 +1,357 @@
+use super::class::{LoxClass, LoxInstance};
+use super::environment::Scope;
+use super::map::LoxMap;
+use super::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+/// Controls when an interpreter looks for reference cycles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GcConfig {
+    /// Objects created before the first collection.
+    pub initial_threshold: usize,
+    /// After a collection, the next one happens once there are this many times as many objects
+    /// as survived.
+    pub growth_factor: usize,
+    /// Collect on every allocation, to shake out objects freed while still in use.
+    pub stress: bool
+}
+
+impl Default for GcConfig {
+    fn default() -> GcConfig {
+        GcConfig { initial_threshold: 10_000, growth_factor: 2, stress: false }
+    }
+}
+
+impl GcConfig {
+    pub fn stress() -> GcConfig {
+        GcConfig { stress: true, ..GcConfig::default() }
+    }
+}
+
+/// Running totals over every collection an interpreter has done.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    pub collections: usize,
+    pub objects_freed: usize
+}
+
+/// A scope, instance, list, map or class: everything a reference cycle can run through.
+pub(crate) enum Node {
+    Scope(Rc<RefCell<Scope>>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    Class(Rc<LoxClass>)
+}
+
+enum Tracked {
+    Scope(Weak<RefCell<Scope>>),
+    Instance(Weak<RefCell<LoxInstance>>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<LoxMap>>),
+    Class(Weak<LoxClass>)
+}
+
+/// The nodes one interpreter's programs have created, and when to next look for cycles among
+/// them.
+pub(crate) struct Collector {
+    tracked: Vec<Tracked>,
+    config: GcConfig,
+    /// The number of tracked nodes that triggers the next collection.
+    next_gc: usize,
+    stats: GcStats
+}
+
+thread_local! {
+    /// The collector of the interpreter running on this thread, if any. Nodes created while
+    /// none is, such as values built by a host between runs, aren't tracked.
+    static ACTIVE: RefCell<Option<Rc<RefCell<Collector>>>> = const { RefCell::new(None) };
+}
+
+/// Makes a collector track the nodes created until it is dropped, then restores the one that
+/// was active before, so interpreters may run one inside another.
+pub(crate) struct Activation {
+    previous: Option<Rc<RefCell<Collector>>>
+}
+
+impl Drop for Activation {
+    fn drop(&mut self) {
+        ACTIVE.with(|a| *a.borrow_mut() = self.previous.take());
+    }
+}
+
+/// Registers a newly created node with the active collector, which may then collect.
+pub(crate) fn track(node: Node) {
+    if let Some(collector) = ACTIVE.with(|a| a.borrow().clone()) {
+        collector.borrow_mut().track(node);
+    }
+}
+
+impl Collector {
+    pub(crate) fn new(config: GcConfig) -> Collector {
+        Collector { tracked: Vec::new(), config, next_gc: config.initial_threshold, stats: GcStats::default() }
+    }
+
+    pub(crate) fn activate(collector: &Rc<RefCell<Collector>>) -> Activation {
+        Activation { previous: ACTIVE.with(|a| a.replace(Some(collector.clone()))) }
+    }
+
+    pub(crate) fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// Number of nodes registered since the last collection, or that survived it.
+    #[cfg(test)]
+    pub(crate) fn tracked(&self) -> usize {
+        self.tracked.len()
+    }
+
+    fn track(&mut self, node: Node) {
+        self.tracked.push(node.downgrade());
+        // `node` is still held by whoever created it, so it survives
+        if self.config.stress || self.tracked.len() > self.next_gc {
+            self.collect();
+        }
+    }
+
+    /// Frees every tracked node that is only kept alive by references from other nodes, and
+    /// returns how many there were.
+    ///
+    /// `Rc` counts every reference to a node, so subtracting the references nodes hold to each
+    /// other leaves those from outside: the interpreter, the Rust stack, the host or nodes
+    /// another interpreter tracks. Nodes with any are live, along with everything reachable from
+    /// them, and the rest are garbage. Clearing their contents breaks the cycles, and `Rc` frees
+    /// them once the last reference is dropped.
+    pub(crate) fn collect(&mut self) -> usize {
+        let nodes: Vec<Node> = std::mem::take(&mut self.tracked).into_iter()
+            .filter_map(|t| t.upgrade())
+            .collect();
+        let index: HashMap<usize, usize> = nodes.iter().enumerate().map(|(i, n)| (n.address(), i)).collect();
+
+        // one reference to each node is the one in `nodes`
+        let mut external: Vec<usize> = nodes.iter().map(|n| n.strong_count() - 1).collect();
+        let mut in_use = vec![false; nodes.len()];
+        for (i, node) in nodes.iter().enumerate() {
+            // a node that is borrowed can't be looked into, so what it refers to counts as external
+            in_use[i] = !node.trace(&mut |address| {
+                if let Some(&j) = index.get(&address) {
+                    external[j] = external[j].saturating_sub(1);
+                }
+            });
+        }
+
+        let mut live = vec![false; nodes.len()];
+        let mut gray: Vec<usize> = (0..nodes.len()).filter(|&i| external[i] > 0 || in_use[i]).collect();
+        for &i in &gray {
+            live[i] = true;
+        }
+        while let Some(i) = gray.pop() {
+            nodes[i].trace(&mut |address| {
+                if let Some(&j) = index.get(&address) {
+                    if !live[j] {
+                        live[j] = true;
+                        gray.push(j);
+                    }
+                }
+            });
+        }
+
+        let mut freed = 0;
+        for (node, live) in nodes.iter().zip(&live) {
+            if *live {
+                self.tracked.push(node.downgrade());
+            } else {
+                node.clear();
+                freed += 1;
+            }
+        }
+        // dropping the last references to the garbage frees it
+        drop(nodes);
+
+        let left = self.tracked.len();
+        self.next_gc = (left * self.config.growth_factor).max(self.config.initial_threshold);
+        self.stats.collections += 1;
+        self.stats.objects_freed += freed;
+        log::debug!("gc: freed {} objects, {} left, next collection at {}", freed, left, self.next_gc);
+        freed
+    }
+}
+
+/// Calls `visit` with the address of every node `value` refers to.
+pub(crate) fn trace_value(value: &Value, visit: &mut dyn FnMut(usize)) {
+    match value {
+        Value::Function(f) => f.trace(visit),
+        Value::Class(c) => visit(address(c)),
+        Value::Instance(i) => visit(address(i)),
+        Value::List(l) => visit(address(l)),
+        Value::Map(m) => visit(address(m)),
+        Value::Nil | Value::Bool(_) | Value::Number(_) | Value::String(_) | Value::NativeFunction(_) => ()
+    }
+}
+
+pub(crate) fn address<T>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as usize
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            Tracked::Scope(s) => s.upgrade().map(Node::Scope),
+            Tracked::Instance(i) => i.upgrade().map(Node::Instance),
+            Tracked::List(l) => l.upgrade().map(Node::List),
+            Tracked::Map(m) => m.upgrade().map(Node::Map),
+            Tracked::Class(c) => c.upgrade().map(Node::Class)
+        }
+    }
+}
+
+impl Node {
+    fn downgrade(&self) -> Tracked {
+        match self {
+            Node::Scope(s) => Tracked::Scope(Rc::downgrade(s)),
+            Node::Instance(i) => Tracked::Instance(Rc::downgrade(i)),
+            Node::List(l) => Tracked::List(Rc::downgrade(l)),
+            Node::Map(m) => Tracked::Map(Rc::downgrade(m)),
+            Node::Class(c) => Tracked::Class(Rc::downgrade(c))
+        }
+    }
+
+    fn address(&self) -> usize {
+        match self {
+            Node::Scope(s) => address(s),
+            Node::Instance(i) => address(i),
+            Node::List(l) => address(l),
+            Node::Map(m) => address(m),
+            Node::Class(c) => address(c)
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Scope(s) => Rc::strong_count(s),
+            Node::Instance(i) => Rc::strong_count(i),
+            Node::List(l) => Rc::strong_count(l),
+            Node::Map(m) => Rc::strong_count(m),
+            Node::Class(c) => Rc::strong_count(c)
+        }
+    }
+
+    /// Calls `visit` with the address of every node this one refers to, or returns `false` if it
+    /// is borrowed and can't be looked into.
+    fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+        match self {
+            Node::Scope(s) => s.try_borrow().map(|s| s.trace(visit)).is_ok(),
+            Node::Instance(i) => i.try_borrow().map(|i| i.trace(visit)).is_ok(),
+            Node::List(l) => l.try_borrow().map(|l| l.iter().for_each(|v| trace_value(v, visit))).is_ok(),
+            Node::Map(m) => m.try_borrow().map(|m| m.iter().for_each(|(_, v)| trace_value(v, visit))).is_ok(),
+            Node::Class(c) => {
+                c.trace(visit);
+                true
+            }
+        }
+    }
+
+    /// Drops everything this node holds. Classes never change after they are declared, so any
+    /// cycle through one also runs through a scope or instance that is cleared instead.
+    fn clear(&self) {
+        match self {
+            Node::Scope(s) => if let Ok(mut s) = s.try_borrow_mut() { s.clear() },
+            Node::Instance(i) => if let Ok(mut i) = i.try_borrow_mut() { i.clear() },
+            Node::List(l) => if let Ok(mut l) = l.try_borrow_mut() { l.clear() },
+            Node::Map(m) => if let Ok(mut m) = m.try_borrow_mut() { m.clear() },
+            Node::Class(_) => ()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GcConfig, GcStats, Value};
+    use crate::{BufferSink, Interpreter};
+    use std::rc::Rc;
+
+    const NODE: &str = "class Node { init() { var node = this; fun get() { return node; } this.get = get; } }";
+
+    #[test]
+    fn it_frees_instances_that_reference_themselves_through_closures() {
+        let mut interpreter = Interpreter::with_output(BufferSink::new(), BufferSink::new());
+        let node = interpreter.eval(format!("{} Node();", NODE)).unwrap();
+        let weak = match &node {
+            Value::Instance(i) => Rc::downgrade(i),
+            v => panic!("expected an instance, got {}", v)
+        };
+        drop(node);
+        assert!(weak.upgrade().is_some(), "the cycle should keep the instance alive until a collection");
+
+        assert!(interpreter.collect_cycles() > 0);
+        assert!(weak.upgrade().is_none(), "the instance leaked");
+        assert_eq!(1, interpreter.gc_stats().collections);
+    }
+
+    #[test]
+    fn it_keeps_everything_still_reachable() {
+        let out = BufferSink::new();
+        let mut interpreter = Interpreter::with_output(out.clone(), BufferSink::new());
+        interpreter.eval(format!("{} var n = Node(); var xs = [1]; push(xs, xs); var m = {{\"m\": nil}}; m[\"m\"] = m;
+            fun counter() {{ var i = 0; fun inc() {{ i = i + 1; return i; }} return inc; }} var next = counter(); next();", NODE)).unwrap();
+        interpreter.collect_cycles();
+        interpreter.eval("print n.get() == n; print len(xs[1]); print m[\"m\"][\"m\"] == m; print next();").unwrap();
+        assert_eq!("true\n2.00\ntrue\n2.00\n", out.contents());
+    }
+
+    #[test]
+    fn it_collects_as_the_program_runs() {
+        let config = GcConfig { initial_threshold: 500, ..GcConfig::default() };
+        let mut interpreter = Interpreter::with_gc_config(config);
+        interpreter.eval(format!("{} for (var i = 0; i < 2000; i = i + 1) {{ Node(); }}", NODE)).unwrap();
+        let stats = interpreter.gc_stats();
+        assert!(stats.collections > 0 && stats.objects_freed > 2000, "{:?}", stats);
+        let tracked = interpreter.gc.borrow().tracked();
+        assert!(tracked < 1000, "{} objects tracked", tracked);
+    }
+
+    #[test]
+    fn it_keeps_each_interpreters_objects_and_stats_apart() {
+        let config = GcConfig { initial_threshold: 100, ..GcConfig::default() };
+        let mut busy = Interpreter::with_gc_config(config);
+        let mut idle = Interpreter::with_gc_config(config);
+        idle.eval("var xs = [1, 2];").unwrap();
+        busy.eval(format!("{} for (var i = 0; i < 500; i = i + 1) {{ Node(); }}", NODE)).unwrap();
+        assert!(busy.gc_stats().collections > 0);
+        assert_eq!(GcStats::default(), idle.gc_stats());
+        assert_eq!(1, idle.gc.borrow().tracked());
+
+        // a collection only looks at its own interpreter's objects
+        idle.collect_cycles();
+        assert_eq!(1, idle.gc.borrow().tracked());
+        assert_eq!(0, idle.gc_stats().objects_freed);
+    }
+
+    #[test]
+    fn it_collects_on_every_allocation_in_stress_mode() {
+        let mut interpreter = Interpreter::with_gc_config(GcConfig::stress());
+        interpreter.eval("var xs = [[1], [2], {\"k\": [3]}];").unwrap();
+        // one statement, five lists and maps
+        assert_eq!(5, interpreter.gc_stats().collections);
+        assert_eq!(Value::Number(3.0), interpreter.eval("xs[2][\"k\"][0];").unwrap());
+    }
+
+    #[test]
+    fn it_runs_programs_unchanged_in_stress_mode() {
+        let out = BufferSink::new();
+        let mut interpreter = Interpreter { out: Box::new(out.clone()), ..Interpreter::with_gc_config(GcConfig::stress()) };
+        interpreter.eval(r#"
+            class A { init(n) { this.n = n; } get() { return this.n; } }
+            class B < A { get() { return super.get() * 2; } }
+            fun wrap(x) { fun get() { return x; } return get; }
+            var xs = [];
+            for (var i = 0; i < 3; i = i + 1) { push(xs, wrap(B(i))); }
+            fun show(f) { print f().get(); }
+            each(xs, show);
+        "#).unwrap();
+        assert_eq!("0.00\n2.00\n4.00\n", out.contents());
+        assert!(interpreter.gc_stats().collections > 10);
+    }
+}
@@ -3,11 +3,14 @@ extern crate rlox_scanner;
 extern crate rlox_parser;
 use std::error::Error;
 use std::fmt::Display;
-use log::{error};
 use rlox_contract::Token;
-use rlox_contract::{Expr,ExprLiteralValue, LiteralTokenType};
+use rlox_contract::{Expr, LiteralTokenType, Span, Stmt};
+use rlox_contract::diagnostics::Diagnostic;
 use std::io::Write;
-use std::io::BufRead;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::cell::RefCell;
+use std::rc::Rc;
 use rlox_scanner::Scanner;
 use rlox_parser::Parser;
 
@@ -15,296 +18,556 @@ pub type Result<B> = std::result::Result<B, InterpreterError>;
 mod environment;
 use environment::ScopeEnvironment;
 mod callable;
-use callable::Callable;
+use callable::LoxFunction;
+pub use callable::{Callable, NativeFunction};
+mod class;
+use class::{LoxClass, LoxInstance};
+mod map;
+use map::{LoxMap, MapKey};
+mod value;
+pub use value::Value;
+mod resolver;
+pub use resolver::{Resolver, ResolveError};
+mod modules;
+use modules::ModuleCache;
+mod natives;
+mod gc;
+use gc::Collector;
+pub use gc::{GcConfig, GcStats};
+mod output;
+mod repl;
+pub use repl::needs_more_input;
+pub use output::BufferSink;
 
 pub struct Interpreter {
     scanner : Scanner,
     parser : Parser,
     pub scope: ScopeEnvironment,
+    globals: ScopeEnvironment,
+    /// Where the natives live; every module's globals sit directly below it.
+    prelude: ScopeEnvironment,
+    modules: ModuleCache,
+    source_name: String,
+    /// The directory imports are resolved against, or the working directory when `None`.
+    script_dir: Option<PathBuf>,
+    exit_code: Option<i32>,
+    /// Tracks the objects this interpreter's programs create, while it is running them.
+    gc: Rc<RefCell<Collector>>,
+    out: Box<dyn Write>,
+    err: Box<dyn Write>,
 }
 
-impl Interpreter {
-    pub fn default() -> Interpreter {
-        let scanner = Scanner::new();
-        let parser = Parser::new();
-        let scope = ScopeEnvironment::new_root();
-        Interpreter { scanner, parser, scope}
+impl Default for Interpreter {
+    /// Creates an interpreter whose globals sit below a prelude holding the core library, so
+    /// scripts may still declare their own `str` or `len`.
+    fn default() -> Interpreter {
+        let prelude = ScopeEnvironment::new_root();
+        for f in natives::core_library() {
+            let name = f.name().to_string();
+            prelude.declare(&name, Value::NativeFunction(f)).expect("duplicate native function");
+        }
+        Interpreter { prelude: prelude.clone(), ..Interpreter::with_env(prelude.new_child()) }
     }
+}
+
+impl Interpreter {
 
     pub fn with_env(env: ScopeEnvironment) -> Interpreter {
         let scanner = Scanner::new();
         let parser = Parser::new();
+        let globals = env.clone();
         let scope = env;
-        Interpreter {scanner, parser, scope }
+        let out = Box::new(std::io::stdout());
+        let err = Box::new(std::io::stderr());
+        // modules imported by an interpreter with a custom environment start out empty
+        let prelude = ScopeEnvironment::new_root();
+        Interpreter {scanner, parser, scope, globals, prelude, modules: ModuleCache::default(), source_name: "<input>".to_string(),
+            script_dir: None, exit_code: None, gc: Rc::new(RefCell::new(Collector::new(GcConfig::default()))),
+            out, err }
+    }
+
+    /// Creates an interpreter with the core library that looks for reference cycles as `config`
+    /// says, rather than with the defaults.
+    pub fn with_gc_config(config: GcConfig) -> Interpreter {
+        Interpreter { gc: Rc::new(RefCell::new(Collector::new(config))), ..Interpreter::default() }
+    }
+
+    /// Frees the scopes, instances, lists and maps only kept alive by cycles between them, which
+    /// reference counting alone never frees, and returns how many there were. This happens on
+    /// its own as a program creates objects. Only objects created by this interpreter's programs
+    /// are looked at; values a host builds between runs are left to reference counting.
+    pub fn collect_cycles(&mut self) -> usize {
+        self.gc.borrow_mut().collect()
+    }
+
+    /// Totals over every cycle collection so far.
+    pub fn gc_stats(&self) -> GcStats {
+        self.gc.borrow().stats()
+    }
+
+    /// Creates an interpreter with the core library that writes `print` output to `out` and
+    /// rendered diagnostics to `err`, rather than to the process's stdout and stderr.
+    pub fn with_output<O, E>(out: O, err: E) -> Interpreter where O : Write + 'static, E : Write + 'static {
+        Interpreter { out: Box::new(out), err: Box::new(err), ..Interpreter::default() }
     }
 
+    /// The sink `print` writes to, for natives that produce output of their own.
+    pub fn output(&mut self) -> &mut dyn Write {
+        self.out.as_mut()
+    }
+
+    /// Makes a host function callable from Lox as a global.
+    pub fn define_native(&mut self, function: NativeFunction) -> Result<()> {
+        let name = function.name().to_string();
+        self.globals.declare(&name, Value::NativeFunction(function))
+    }
+
+    /// The code passed to `exit()`, once a program has called it.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Sets the name errors are reported against, usually the path of the script being run.
+    pub fn set_source_name<B>(&mut self, name: B) where B : ToString {
+        self.source_name = name.to_string();
+    }
+
+    /// Names errors after the script at `path` and resolves its imports relative to it.
+    pub fn set_script_path<P>(&mut self, path: P) where P : AsRef<Path> {
+        let path = path.as_ref();
+        self.set_source_name(path.display());
+        self.script_dir = path.parent().map(Path::to_path_buf);
+        self.modules.set_script(path);
+    }
+
+    /// Runs `source`, rendering any errors to the error sink. Scan, parse and resolve errors
+    /// come back as `ErrorKind::InvalidData` and stop anything from running; a runtime error
+    /// comes back as `ErrorKind::InvalidInput`.
     pub fn execute_source<B>(&mut self, source: B) -> std::io::Result<()> where B : ToString {
-        let sres = self.scanner.scan(&source.to_string());
-        if let Ok(tokens) = sres {
-            self.parser.add_tokens(*tokens);
-            let parse_res = self.parser.parse();
-            if let Ok(exprs) = parse_res {
-                for e in exprs {
-                    let ie = self.interpret(Box::from(e));
-                    match &ie {
-                        Ok(cv) => if cv == &Expr::LiteralExpr(ExprLiteralValue::NilLiteral) { continue; } else { println!("{:?}", cv)},
-                        Err(e) => {return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e.msg.clone()));}
-                    }
+        let source = source.to_string();
+        let stmts = match self.prepare(&source) {
+            Ok(stmts) => stmts,
+            Err(diagnostics) => {
+                for d in &diagnostics {
+                    self.report(d, &source);
+                }
+                let msg = format!("{}: {} error(s) found before running", self.source_name, diagnostics.len());
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg));
+            }
+        };
+        for e in stmts {
+            let ie = self.interpret(&e);
+            match &ie {
+                Ok(_) => (),
+                Err(_) if self.exit_code.is_some() => return Ok(()),
+                Err(e) => {
+                    self.report(&e.diagnostic(), &source);
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, self.located(e)));
                 }
-            }  else {
-                error!("{:?}", parse_res);
             }
-        } else {
-            error!("{:?}", sres);
         }
 
         Ok(())
     }
 
-    pub fn start_repl(&mut self, stdin: &std::io::Stdin, stdout: &mut std::io::Stdout) -> std::io::Result<()> {
-        loop {
-            print!("rlox] ");
-            stdout.flush()?;
-            let nxt = read_line_from_stdin(stdin)?;
-            if nxt.clone() == "quit" {
-                break;
-            }
-            let sres = self.scanner.scan(&nxt);
-            match sres {
-                Ok(ts) => {
-                    self.parser.add_tokens(ts.to_vec());
-                    let pres = self.parser.parse();
-                    match pres {
-                        Ok(exprs) => { 
-                            for expr in exprs {
-                                print!("\n");
-                                match self.interpret(Box::from(expr)) {
-                                    Err(e) => error!("{}", e.msg),
-                                    Ok(v) => {
-                                        if v == Expr::LiteralExpr(ExprLiteralValue::NilLiteral) { continue;} else {println!("{}",v)};
-                                        println!("OK.")
-                                    }
+    /// Runs `source` and returns the value of its last statement when that is an expression,
+    /// or `nil` otherwise. Nothing is reported; every error comes back as a diagnostic.
+    pub fn eval<B>(&mut self, source: B) -> std::result::Result<Value, Vec<Diagnostic>> where B : ToString {
+        let stmts = self.prepare(&source.to_string())?;
+        let mut last = Value::Nil;
+        for stmt in &stmts {
+            last = match self.interpret(stmt) {
+                Ok(v) => v,
+                Err(_) if self.exit_code.is_some() => return Ok(Value::Nil),
+                Err(e) => return Err(vec![e.diagnostic()])
+            };
+        }
+        Ok(last)
+    }
+
+    /// Scans, parses and resolves `source`, collecting every error found along the way.
+    fn prepare(&mut self, source: &str) -> std::result::Result<Vec<Stmt>, Vec<Diagnostic>> {
+        let tokens = self.scanner.scan(source).map_err(|le| vec![le.diagnostic()])?;
+        self.parser.add_tokens(*tokens);
+        let mut stmts = self.parser.parse().map_err(|pe| pe.diagnostics())?;
+        Resolver::new().resolve(&mut stmts)
+            .map_err(|errors| errors.iter().map(ResolveError::diagnostic).collect::<Vec<_>>())?;
+        Ok(stmts)
+    }
+
+    /// Declares a global visible to every script run afterwards.
+    pub fn define_global<B, V>(&mut self, name: B, value: V) -> Result<()> where B : ToString, V : Into<Value> {
+        self.globals.declare(&name.to_string(), value.into())
+    }
+
+    /// Looks up a global, including the natives of the core library.
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.globals.get(name)
+    }
+
+    /// Calls any callable value, such as a Lox function fetched with [`Interpreter::global`].
+    pub fn call(&mut self, callee: &Value, args: Vec<Value>) -> Result<Value> {
+        match callee.as_callable() {
+            Some(f) => {
+                if f.arity() != args.len() {
+                    return Err(InterpreterError::new(format!("Expected {} arguments but got {}", f.arity(), args.len())));
+                }
+                let _active = Collector::activate(&self.gc);
+                f.call(self, args)
+            },
+            None => Err(InterpreterError::new(format!("Can only call functions and classes, got {}", callee)))
+        }
+    }
+
+    /// Calls the global function `name` with `args`.
+    pub fn call_function(&mut self, name: &str, args: Vec<Value>) -> Result<Value> {
+        let callee = self.global(name).ok_or_else(|| InterpreterError::new(format!("Undefined variable {}", name)))?;
+        self.call(&callee, args)
+    }
+
+    fn report(&mut self, diagnostic: &Diagnostic, source: &str) {
+        // there is nowhere left to report a failure to write a diagnostic
+        let _ = write!(self.err, "{}", diagnostic.render(&self.source_name, source));
+    }
+
+    fn located(&self, e: &InterpreterError) -> String {
+        match e.span {
+            Some(span) => format!("{}:{}: {}", self.source_name, span, e.msg),
+            None => format!("{}: {}", self.source_name, e.msg)
+        }
+    }
+
+    fn interpret(&mut self, stmt: &Stmt) -> Result<Value> {
+        let _active = Collector::activate(&self.gc);
+        self.execute(stmt).map_err(|e| e.with_span(stmt.span()))
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<Value> {
+
+        let v = match stmt {
+            Stmt::PrintStmt(inner, _) => {
+                let res = self.evaluate(inner)?;
+
+                writeln!(self.out, "{}", res).map_err(|e| InterpreterError::new(format!("Failed to write output: {}", e)))?;
+                Value::Nil
+            },
+            Stmt::ExprStmt(inner, _) => {
+                self.evaluate(inner)?
+            },
+            Stmt::VarDecl { name, initializer, span: _ } => {
+                let v = self.evaluate(initializer)?;
+                match name {
+                    Token::Literal(LiteralTokenType::IdentifierLiteral(s)) => {
+                        self.declare(s, v)?;
+                        Value::Nil
+                    },
+                    _ => return Err(InterpreterError::new("var decl requires identifier"))
+                }
+            },
+            Stmt::BlockStmt(decs, _) => {
+                let block_scope = self.scope.new_child();
+                self.execute_block(decs, block_scope)?;
+                Value::Nil
+            },
+            Stmt::IfStmt { condition, then_branch, else_branch, span: _ } => {
+                let condition_result = self.evaluate(condition)?;
+                if condition_result.is_truthy() {
+                    self.interpret(then_branch)?;
+                } else if let Some(else_branch) = else_branch {
+                    self.interpret(else_branch)?;
+                }
+                Value::Nil
+            },
+            Stmt::WhileLoop { condition, body, increment, span: _ } => {
+                while self.evaluate(condition)?.is_truthy() {
+                    match self.interpret(body) {
+                        Err(InterpreterError { control: Some(LoopControl::Break), .. }) => break,
+                        Err(InterpreterError { control: Some(LoopControl::Continue), .. }) | Ok(_) => (),
+                        Err(e) => return Err(e)
+                    }
+                    if let Some(increment) = increment {
+                        self.evaluate(increment)?;
+                    }
+                }
+
+                Value::Nil
+            },
+            Stmt::Break(_) => return Err(InterpreterError::new_loop_control(LoopControl::Break)),
+            Stmt::Continue(_) => return Err(InterpreterError::new_loop_control(LoopControl::Continue)),
+            Stmt::Import { path, names, span: _ } => {
+                self.import(path, names)?;
+                Value::Nil
+            },
+            Stmt::FunctionDecl { name, params: _, body: _, span: _ } => {
+                if let Token::Literal(LiteralTokenType::IdentifierLiteral(s)) = name {
+                    let f = LoxFunction::new(Rc::new(stmt.clone()), self.scope.clone(), self.globals.clone(), false);
+                    self.declare(s, Value::Function(f))?;
+                    Value::Nil
+
+                } else {
+                    return Err(InterpreterError::new("function name not identifier"))
+                }
+            },
+            Stmt::Return(_, val, _) => {
+                let v = match val {
+                    Some(val) => self.evaluate(val)?,
+                    None => Value::Nil
+                };
+                return Err(InterpreterError::new_return(Some(v)));
+            },
+            Stmt::ClassDecl { name, superclass, methods, span: _ } => {
+                if let Token::Literal(LiteralTokenType::IdentifierLiteral(s)) = name {
+                    let superclass = match superclass {
+                        Some(expr) => {
+                            if let Expr::VariableExpr { name: Token::Literal(LiteralTokenType::IdentifierLiteral(super_name)), depth: _, span: _ } = expr.as_ref() {
+                                if super_name == s {
+                                    return Err(InterpreterError::new(format!("Class {} can't inherit from itself", s)));
                                 }
                             }
+                            match self.evaluate(expr)? {
+                                Value::Class(c) => Some(c),
+                                other => return Err(InterpreterError::new(format!("Superclass must be a class, got {}", other)))
+                            }
                         },
-                        Err(pe) => { error!("{}", pe);}
+                        None => None
+                    };
+                    let method_scope = match &superclass {
+                        Some(c) => {
+                            let env = self.scope.new_child();
+                            env.declare("super", Value::Class(c.clone()))?;
+                            env
+                        },
+                        None => self.scope.clone()
+                    };
+                    let mut class_methods = HashMap::new();
+                    for m in methods {
+                        if let Stmt::FunctionDecl { name: Token::Literal(LiteralTokenType::IdentifierLiteral(method_name)), params: _, body: _, span: _ } = m {
+                            let f = LoxFunction::new(Rc::new(m.clone()), method_scope.clone(), self.globals.clone(), method_name == "init");
+                            class_methods.insert(method_name.clone(), f);
+                        } else {
+                            return Err(InterpreterError::new("class methods must be functions"))
+                        }
                     }
-                },
-                Err(le) => {
-                    error!("{}", le)
-
+                    let class = Rc::new(LoxClass::new(s, superclass, class_methods));
+                    gc::track(gc::Node::Class(class.clone()));
+                    self.declare(s, Value::Class(class))?;
+                    Value::Nil
+                } else {
+                    return Err(InterpreterError::new("class name not identifier"))
                 }
             }
+        };
+        Ok(v)
+    }
+
+    /// Declares a variable, function or class in the current scope. Globals may be declared
+    /// again, replacing the old value, as the resolver only rejects that for locals.
+    fn declare(&mut self, name: &str, value: Value) -> Result<()> {
+        if self.scope.same_scope(&self.globals) {
+            self.globals.redeclare(name, value);
+            Ok(())
+        } else {
+            self.scope.declare(name, value)
         }
-        println!("Exiting...");
-        Ok(())
     }
 
-    fn interpret(&mut self, expr: Box<Expr>) -> Result<Expr> {
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value> {
+        self.evaluate_expr(expr).map_err(|e| e.with_span(expr.span()))
+    }
+
+    fn evaluate_expr(&mut self, expr: &Expr) -> Result<Value> {
 
-        let v = match *expr {
-            Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(b)) => bool_literal(b),
-            Expr::LiteralExpr(ExprLiteralValue::NilLiteral) => Expr::LiteralExpr(ExprLiteralValue::NilLiteral),
-            Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)) => Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)),
-            Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s)) => Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s.clone())),
-            Expr::GroupingExpr(inner) => {
-                return self.interpret(inner)
+        let v = match expr {
+            Expr::LiteralExpr(lit, _) => Value::from(lit),
+            Expr::GroupingExpr(inner, _) => {
+                return self.evaluate(inner)
             },
-            Expr::UnaryExpr { operator, right} => {
-                let r = self.interpret(right)?;
-                match (&operator,&r) {
-                    (Token::Bang, Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(b))) => bool_literal(!b),
-                    (Token::Bang, Expr::LiteralExpr(ExprLiteralValue::NilLiteral)) => bool_literal(true),
-                    (Token::Bang, _) => bool_literal(true),
-                    (Token::Minus, Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n))) => num_literal(-1 as f64 * n),
-                    (Token::Minus, _) => return Err(InterpreterError::new(format!("Expected number, got {}", r))),
+            Expr::UnaryExpr { operator, right, span: _ } => {
+                let r = self.evaluate(right)?;
+                match (operator, &r) {
+                    (Token::Bang, _) => Value::Bool(!r.is_truthy()),
+                    (Token::Minus, Value::Number(n)) => Value::Number(-n),
+                    (Token::Minus, _) => return Err(InterpreterError::new(format!("Expected number, got {}", r.type_name()))),
                     _ => return Err(InterpreterError::new(format!("Expected unary operator, got {:?}", operator)))
                 }
             },
-            Expr::BinaryExpr { left, operator, right } => {
-                let l = self.interpret(left)?;
-                let r = self.interpret(right)?;
+            Expr::BinaryExpr { left, operator, right, span: _ } => {
+                let l = self.evaluate(left)?;
+                let r = self.evaluate(right)?;
 
-                match (&l, &operator, &r) {
-                    (Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)), Token::Minus, Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(m))) => num_literal(n - m),
-                    (_, Token::Minus, _) => return Err(InterpreterError::new(format!("Operator MINUS expects two numbers, got {:?} and {:?}", &l, &r))),
+                match (&l, operator, &r) {
+                    (Value::Number(n), Token::Minus, Value::Number(m)) => Value::Number(n - m),
+                    (_, Token::Minus, _) => return Err(InterpreterError::new(format!("Operator MINUS expects two numbers, got {} and {}", l.type_name(), r.type_name()))),
 
-                    (Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)), Token::Plus, Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(m))) => num_literal(n + m),
-                    (Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s1)), Token::Plus, Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s2))) => {
-                        Expr::LiteralExpr(ExprLiteralValue::StringLiteral(format!(r#"{}{}"#, s1, s2)))
+                    (Value::Number(n), Token::Plus, Value::Number(m)) => Value::Number(n + m),
+                    (Value::String(s1), Token::Plus, Value::String(s2)) => {
+                        Value::String(Rc::from(format!(r#"{}{}"#, s1, s2)))
                     },
-                    (_, Token::Plus, _) => return Err(InterpreterError::new(format!("Operator PLUS expects two numbers or two strings, got {:?} and {:?}", &l, &r))),
+                    (_, Token::Plus, _) => return Err(InterpreterError::new(format!("Operator PLUS expects two numbers or two strings, got {} and {}", l.type_name(), r.type_name()))),
 
-                    (Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)), Token::Slash, Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(m))) => if *m == 0 as f64 { return Err(InterpreterError::new("Divide by zero error")); } else { num_literal(n / m)},
-                    (_, Token::Slash, _) => return Err(InterpreterError::new(format!("Operator SLASH expects two numbers, got {:?} and {:?}", &l, &r))),
+                    (Value::Number(n), Token::Slash, Value::Number(m)) => if *m == 0_f64 { return Err(InterpreterError::new("Divide by zero error")); } else { Value::Number(n / m)},
+                    (_, Token::Slash, _) => return Err(InterpreterError::new(format!("Operator SLASH expects two numbers, got {} and {}", l.type_name(), r.type_name()))),
 
-                    (Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)), Token::Star, Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(m))) => num_literal(n * m),
-                    (_, Token::Star, _) => return Err(InterpreterError::new(format!("Operator STAR expects two numbers, got {:?} and {:?}", &l, &r))),
+                    (Value::Number(n), Token::Star, Value::Number(m)) => Value::Number(n * m),
+                    (_, Token::Star, _) => return Err(InterpreterError::new(format!("Operator STAR expects two numbers, got {} and {}", l.type_name(), r.type_name()))),
 
-                    (Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)), Token::Greater, Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(m))) => bool_literal(n > m),
-                    (_, Token::Greater, _) => return Err(InterpreterError::new(format!("Operator GREATER expects two numbers, got {:?} and {:?}", &l, &r))),
+                    (Value::Number(n), Token::Greater, Value::Number(m)) => Value::Bool(n > m),
+                    (_, Token::Greater, _) => return Err(InterpreterError::new(format!("Operator GREATER expects two numbers, got {} and {}", l.type_name(), r.type_name()))),
 
-                    (Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)), Token::GreaterEqual, Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(m))) => bool_literal(n >= m),
-                    (_, Token::GreaterEqual, _) => return Err(InterpreterError::new(format!("Operator GREATEREQUAL expects two numbers, got {:?} and {:?}", &l, &r))),
+                    (Value::Number(n), Token::GreaterEqual, Value::Number(m)) => Value::Bool(n >= m),
+                    (_, Token::GreaterEqual, _) => return Err(InterpreterError::new(format!("Operator GREATEREQUAL expects two numbers, got {} and {}", l.type_name(), r.type_name()))),
 
-                    (Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)), Token::Less, Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(m))) => bool_literal(n < m),
-                    (_, Token::Less, _) => return Err(InterpreterError::new(format!("Operator LESS expects two numbers, got {:?} and {:?}", &l, &r))),
+                    (Value::Number(n), Token::Less, Value::Number(m)) => Value::Bool(n < m),
+                    (_, Token::Less, _) => return Err(InterpreterError::new(format!("Operator LESS expects two numbers, got {} and {}", l.type_name(), r.type_name()))),
 
-                    (Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)), Token::LessEqual, Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(m))) => bool_literal(n <= m),
-                    (_, Token::LessEqual, _) => return Err(InterpreterError::new(format!("Operator LESSEQUAL expects two numbers, got {:?} and {:?}", &l, &r))),
+                    (Value::Number(n), Token::LessEqual, Value::Number(m)) => Value::Bool(n <= m),
+                    (_, Token::LessEqual, _) => return Err(InterpreterError::new(format!("Operator LESSEQUAL expects two numbers, got {} and {}", l.type_name(), r.type_name()))),
 
-                    (Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)), Token::BangEqual, Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(m))) => bool_literal(n != m),
-                    (Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s1)), Token::BangEqual, Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s2))) => bool_literal(s1 != s2),
-                    (Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(b1)), Token::BangEqual, Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(b2))) => bool_literal(b1 & b2),
-                    (Expr::LiteralExpr(ExprLiteralValue::NilLiteral), Token::BangEqual, Expr::LiteralExpr(ExprLiteralValue::NilLiteral)) => bool_literal(false),
-                    (_, Token::BangEqual, _) => bool_literal(true),
+                    (_, Token::BangEqual, _) => Value::Bool(l != r),
 
-                    (Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)), Token::EqualEqual, Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(m))) => bool_literal(n == m),
-                    (Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s1)), Token::EqualEqual, Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s2))) => bool_literal(s1 == s2),
-                    (Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(b1)), Token::EqualEqual, Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(b2))) => bool_literal(b1 == b2),
-                    (Expr::LiteralExpr(ExprLiteralValue::NilLiteral), Token::EqualEqual, Expr::LiteralExpr(ExprLiteralValue::NilLiteral)) => bool_literal(true),
-                    (_, Token::EqualEqual, _) => bool_literal(false),
+                    (_, Token::EqualEqual, _) => Value::Bool(l == r),
 
                     _ => return Err(InterpreterError::new("not recognized"))
                 }
             },
-            Expr::PrintStmt(inner) => {
-                let res = self.interpret(inner)?;
-
-                println!("{}", res);
-                Expr::LiteralExpr(ExprLiteralValue::NilLiteral)
-            },
-            Expr::ExprStmt(inner) => {
-                let res = self.interpret(inner)?;
-                res
-            },
-            Expr::VarDecl { name, initializer } => {
-                //dbg!(&name, &initializer);
-                let v = self.interpret(initializer)?;
+            Expr::VariableExpr { name, depth, span: _ } => {
                 match name {
                     Token::Literal(LiteralTokenType::IdentifierLiteral(s)) => {
-                        self.scope.declare(&s, Box::from(v))?;
-                        Expr::LiteralExpr(ExprLiteralValue::NilLiteral)
-                    },
-                    _ => return Err(InterpreterError::new("var decl requires identifier"))
-                }
-            },
-            Expr::VariableExpr(identifier) => {
-                //dbg!(&identifier);
-                match identifier {
-                    Token::Literal(LiteralTokenType::IdentifierLiteral(s)) => {
-                        if let Some(v) = self.scope.get(&s) {
-                            v.clone()
-                        } else {
-                            Expr::LiteralExpr(ExprLiteralValue::NilLiteral)
+                        match depth {
+                            Some(d) => self.scope.get_at(*d, s).ok_or_else(|| InterpreterError::new(format!("Undefined variable {}", s)))?,
+                            None => self.globals.get(s).ok_or_else(|| InterpreterError::new(format!("Undefined variable {}", s)))?
                         }
                     },
                     _ => return Err(InterpreterError::new("var lookup requires identifier"))
                 }
             },
-            Expr::AssigmentExpr { name, value } => {
-                //dbg!(&name, &value);
-                let v = self.interpret(value)?;
+            Expr::AssigmentExpr { name, value, depth, span: _ } => {
+                let v = self.evaluate(value)?;
                 match name {
                     Token::Literal(LiteralTokenType::IdentifierLiteral(s)) => {
-                        
-                        self.scope.assign(&s, Box::from(v))?;
-                        Expr::LiteralExpr(ExprLiteralValue::NilLiteral)
+                        match depth {
+                            Some(d) => self.scope.assign_at(*d, s, v.clone())?,
+                            None => self.globals.assign(s, v.clone())?
+                        }
+                        v
                     },
                     _ => return Err(InterpreterError::new("expected assignment, got nothing"))
                 }
             },
-            Expr::BlockStmt(decs) => {
-                self.scope.new_child();
-                for stmt in decs {
-                    //dbg!(&stmt);
-                    self.interpret(stmt)?;
+            Expr::LogicalExpr { left, operator, right, span: _ } => {
+                let l = self.evaluate(left)?;
+                match operator {
+                    Token::And => if l.is_truthy() { self.evaluate(right)? } else { l },
+                    Token::Or => if l.is_truthy() { l } else { self.evaluate(right)? },
+                    _ => return Err(InterpreterError::new("Expected logical operator"))
                 }
-                self.scope.set_to_previous();
-                Expr::LiteralExpr(ExprLiteralValue::NilLiteral)
             },
-            Expr::IfStmt { condition, then_branch, else_branch} => {
-                let condition_result = self.interpret(condition)?;
-                let _ = match condition_result {
-                    Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(false)) => self.interpret(else_branch),
-                    Expr::LiteralExpr(ExprLiteralValue::NilLiteral) => self.interpret(else_branch),
-                    _ => self.interpret(then_branch)
-                }?;
-                Expr::LiteralExpr(ExprLiteralValue::NilLiteral)
+            Expr::CallExpr { callee, paren: _, arguments, span } => {
+                let c = self.evaluate(callee)?;
+                let mut resolved_args = Vec::new();
+                for a in arguments {
+                    let result = self.evaluate(a)?;
+                    resolved_args.push(result);
+                }
+                self.call(&c, resolved_args).map_err(|e| e.with_note(format!("called from {}", span)))?
             },
-            Expr::LogicalExpr { left, operator, right } => {
-                let l = self.interpret(left)?;
-                match operator {
-                    Token::And => {
-                        match l {
-                            Expr::LiteralExpr(ExprLiteralValue::NilLiteral) | Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(false)) => l,
-                            _ => {
-                                let r = self.interpret(right)?;
-                                r
-                            }
-                        }
+            Expr::GetExpr { object, name, span: _ } => {
+                let o = self.evaluate(object)?;
+                match (&o, name) {
+                    (Value::Instance(instance), Token::Literal(LiteralTokenType::IdentifierLiteral(s))) => LoxInstance::get(instance, s)?,
+                    _ => return Err(InterpreterError::new(format!("Only instances have properties, got {}", o)))
+                }
+            },
+            Expr::SetExpr { object, name, value, span: _ } => {
+                let o = self.evaluate(object)?;
+                match (&o, name) {
+                    (Value::Instance(instance), Token::Literal(LiteralTokenType::IdentifierLiteral(s))) => {
+                        let v = self.evaluate(value)?;
+                        instance.borrow_mut().set(s, v.clone());
+                        v
                     },
-                    Token::Or => {
-                        match l {
-                            Expr::LiteralExpr(ExprLiteralValue::NilLiteral) | Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(false)) => {
-                                let r = self.interpret(right)?;
-                                r
-                            },
-                            _ => l
-                        }
+                    _ => return Err(InterpreterError::new(format!("Only instances have fields, got {}", o)))
+                }
+            },
+            Expr::ThisExpr { keyword: _, depth, span: _ } => {
+                match depth.and_then(|d| self.scope.get_at(d, "this")) {
+                    Some(v) => v,
+                    None => return Err(InterpreterError::new("Can't use 'this' outside of a class"))
+                }
+            },
+            Expr::SuperExpr { keyword: _, method, depth, span: _ } => {
+                let d = depth.ok_or_else(|| InterpreterError::new("Can't use 'super' outside of a class"))?;
+                let superclass = match self.scope.get_at(d, "super") {
+                    Some(Value::Class(c)) => c,
+                    _ => return Err(InterpreterError::new("Can't use 'super' in a class with no superclass"))
+                };
+                let instance = match self.scope.get_at(d - 1, "this") {
+                    Some(Value::Instance(i)) => i,
+                    _ => return Err(InterpreterError::new("Can't use 'super' outside of a method"))
+                };
+                match method {
+                    Token::Literal(LiteralTokenType::IdentifierLiteral(m)) => match superclass.find_method(m) {
+                        Some(f) => Value::Function(f.bind(instance)?),
+                        None => return Err(InterpreterError::new(format!("Undefined property '{}'", m)))
                     },
-                    _ => return Err(InterpreterError::new("Expected logical operator"))
+                    _ => return Err(InterpreterError::new("super method must be an identifier"))
                 }
             },
-            Expr::WhileLoop { condition, body } => {
-                let mut cond_val = self.interpret(Box::from(condition.clone()))?;
-                while cond_val != Expr::LiteralExpr(ExprLiteralValue::NilLiteral) && cond_val != bool_literal(false) {
-                    self.interpret(Box::from(body.clone()))?;
-                    cond_val = self.interpret(Box::from(condition.clone()))?;
+            Expr::ListExpr { elements, span: _ } => {
+                let mut values = Vec::with_capacity(elements.len());
+                for e in elements {
+                    values.push(self.evaluate(e)?);
                 }
-
-                Expr::LiteralExpr(ExprLiteralValue::NilLiteral)
+                Value::list(values)
             },
-            Expr::CallExpr { callee, paren: _, arguments } => {
-                //dbg!(&callee, &arguments);
-                let c = self.interpret(callee)?;
-                let mut resolved_args = Vec::new();
-                for a in arguments {
-                    //dbg!(&a);
-                    let result = self.interpret(a)?;
-                    resolved_args.push(result);
+            Expr::MapExpr { entries, span: _ } => {
+                let mut map = LoxMap::new();
+                for (k, v) in entries {
+                    let key = MapKey::from_value(&self.evaluate(k)?).map_err(|e| e.with_span(k.span()))?;
+                    let value = self.evaluate(v)?;
+                    map.insert(key, value);
                 }
-                if let Expr::FunctionExpr{name, params, body} = c {
-                    let f = Expr::FunctionExpr { name,params,body };
-
-                    let v = f.call(self.get_scope(), resolved_args)?;
-                    
-                    v
-                } else {
-                    return Err(InterpreterError::new("uncallable expr"))
+                Value::map(map)
+            },
+            Expr::InterpolationExpr { parts, span: _ } => {
+                let mut s = String::new();
+                for p in parts {
+                    s.push_str(&self.evaluate(p)?.to_string());
                 }
+                Value::string(s)
             },
-            Expr::FunctionExpr { name, params, body } => {
-                //dbg!(&name, &params, &body);
-                if let Token::Literal(LiteralTokenType::IdentifierLiteral(s)) = name.clone() {
-                    self.scope.declare(&s, Box::from(Expr::FunctionExpr { name, params, body}))?;
-                    Expr::LiteralExpr(ExprLiteralValue::NilLiteral)
-
-                } else {
-                    return Err(InterpreterError::new("function name not identifier"))
+            Expr::IndexExpr { object, index, span: _ } => {
+                let o = self.evaluate(object)?;
+                let i = self.evaluate(index)?;
+                match &o {
+                    Value::List(list) => {
+                        let list = list.borrow();
+                        list[i.as_index(list.len())?].clone()
+                    },
+                    Value::Map(map) => match map.borrow().get(&MapKey::from_value(&i)?) {
+                        Some(v) => v.clone(),
+                        None => return Err(InterpreterError::new(format!("Undefined key {}", i)))
+                    },
+                    _ => return Err(InterpreterError::new(format!("Only lists and maps can be indexed, got {}", o.type_name())))
                 }
             },
-            Expr::Return(_, val) => {
-                //println!("returning ... {}", &self.scope);
-                let v = if &Expr::LiteralExpr(ExprLiteralValue::NilLiteral) != val.as_ref() {
-                    let res = self.interpret(val)?;
-                    res
-                } else {
-                    Expr::LiteralExpr(ExprLiteralValue::NilLiteral)
-                };
-
-                //dbg!(&v);
-                return Err(InterpreterError::new_return(Some(v)));
+            Expr::IndexSetExpr { object, index, value, span: _ } => {
+                let o = self.evaluate(object)?;
+                let i = self.evaluate(index)?;
+                let v = self.evaluate(value)?;
+                match &o {
+                    Value::List(list) => {
+                        let mut list = list.borrow_mut();
+                        let i = i.as_index(list.len())?;
+                        list[i] = v.clone();
+                        v
+                    },
+                    Value::Map(map) => {
+                        map.borrow_mut().insert(MapKey::from_value(&i)?, v.clone());
+                        v
+                    },
+                    _ => return Err(InterpreterError::new(format!("Only lists and maps can be indexed, got {}", o.type_name())))
+                }
             }
         };
         Ok(v)
@@ -314,53 +577,474 @@ impl Interpreter {
     pub fn get_scope(&self) -> &ScopeEnvironment {
         &self.scope
     }
-    
-}
-
 
-fn num_literal(n:f64) -> Expr {
-    Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n))
-}
-
-fn bool_literal(b:bool) -> Expr {
-    Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(b))
+    fn execute_block(&mut self, stmts: &[Stmt], scope: ScopeEnvironment) -> Result<()> {
+        let previous = std::mem::replace(&mut self.scope, scope);
+        let mut result = Ok(());
+        for stmt in stmts {
+            result = self.interpret(stmt).map(|_| ());
+            if result.is_err() {
+                break;
+            }
+        }
+        self.scope = previous;
+        result
+    }
+    
 }
 
-fn read_line_from_stdin(stdin: &std::io::Stdin) -> std::io::Result<String> {
-    let mut handle = stdin.lock();
-    let mut buffer = String::new();
 
-    handle.read_line(&mut buffer)?;
-
-    let input = buffer.trim();
-    Ok(input.to_string())
+/// A `break` or `continue` unwinding to the innermost enclosing loop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopControl {
+    Break,
+    Continue
 }
 
-
 #[derive(Debug)]
 pub struct InterpreterError {
     msg: String,
-    returned: Option<Expr>
+    returned: Option<Box<Value>>,
+    control: Option<LoopControl>,
+    span: Option<Span>,
+    notes: Vec<String>
 }
 
 impl InterpreterError {
     pub fn new<B : ToString>(msg:B) -> InterpreterError {
-        InterpreterError { msg: msg.to_string(), returned: None}
+        InterpreterError { msg: msg.to_string(), returned: None, control: None, span: None, notes: Vec::new() }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        self.notes.iter().fold(Diagnostic::error("E0001", &self.msg).with_span(self.span), |d, n| d.with_note(n))
+    }
+
+    /// Adds a note, unless the error is unlocated and will be reported at the node the note is about.
+    fn with_note(mut self, note: String) -> InterpreterError {
+        if !self.is_unwinding() && self.span.is_some() {
+            self.notes.push(note);
+        }
+        self
+    }
+
+    /// Attaches `span` unless the error was already located by a more deeply nested node.
+    fn with_span(mut self, span: Span) -> InterpreterError {
+        if self.span.is_none() && !self.is_unwinding() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    pub fn return_val(&self) -> Option<&Value> {
+        self.returned.as_deref()
+    }
+
+    pub fn new_return(r:Option<Value>) -> InterpreterError{
+        InterpreterError { msg: "".to_string(), returned: r.map(Box::new), control: None, span: None, notes: Vec::new() }
     }
 
-    pub fn return_val(&self) -> Option<&Expr> {
-        self.returned.as_ref()
+    pub fn new_loop_control(control: LoopControl) -> InterpreterError {
+        InterpreterError { msg: "".to_string(), returned: None, control: Some(control), span: None, notes: Vec::new() }
     }
 
-    pub fn new_return(r:Option<Expr>) -> InterpreterError{
-        InterpreterError { msg: "".to_string(), returned: r}
+    /// Whether this is a `return`, `break` or `continue` rather than a real error.
+    fn is_unwinding(&self) -> bool {
+        self.returned.is_some() || self.control.is_some()
     }
 }
 impl Error for InterpreterError {}
 impl Display for InterpreterError {
     
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> { 
+        if let Some(span) = self.span {
+            write!(f, "{}: ", span)?;
+        }
         write!(f, "{}", self.msg)?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::{BufferSink, Interpreter, NativeFunction, Resolver, ResolveError, Span, Value};
+
+    fn run(source: &str) -> Interpreter {
+        let mut interpreter = Interpreter::default();
+        run_in(&mut interpreter, source);
+        interpreter
+    }
+
+    fn run_in(interpreter: &mut Interpreter, source: &str) {
+        let tokens = interpreter.scanner.scan(source).expect("failed to scan");
+        interpreter.parser.add_tokens(*tokens);
+        let mut stmts = interpreter.parser.parse().expect("failed to parse");
+        Resolver::new().resolve(&mut stmts).expect("failed to resolve");
+        for s in stmts {
+            interpreter.interpret(&s).expect("failed to interpret");
+        }
+    }
+
+    #[test]
+    fn it_calls_init_and_methods_bound_to_this() {
+        let interpreter = run(r#"
+            class Counter {
+                init(start) { this.count = start; }
+                inc() { this.count = this.count + 1; return this; }
+            }
+            var c = Counter(10);
+            c.inc().inc();
+            var result = c.count;
+        "#);
+        assert_eq!(Some(Value::Number(12.0)), interpreter.scope.get("result"));
+    }
+
+    #[test]
+    fn it_keeps_this_bound_when_method_is_detached() {
+        let interpreter = run(r#"
+            class Box {
+                set(v) { this.value = v; }
+            }
+            var b = Box();
+            var setter = b.set;
+            setter(3);
+            var result = b.value;
+        "#);
+        assert_eq!(Some(Value::Number(3.0)), interpreter.scope.get("result"));
+    }
+
+    #[test]
+    fn it_errors_on_undefined_property() {
+        let mut interpreter = run("class Empty {} var e = Empty();");
+        let tokens = interpreter.scanner.scan("e.missing;").unwrap();
+        interpreter.parser.add_tokens(*tokens);
+        let stmts = interpreter.parser.parse().unwrap();
+        let res = interpreter.interpret(&stmts[0]);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn it_inherits_methods_and_calls_super_bound_to_instance() {
+        let interpreter = run(r#"
+            class A {
+                name() { return "A"; }
+                describe() { return "I am " + this.name(); }
+            }
+            class B < A {
+                name() { return "B"; }
+                describe() { return super.describe() + "!"; }
+            }
+            class C < B {}
+            var result = C().describe();
+        "#);
+        assert_eq!("I am B!", format!("{}", interpreter.scope.get("result").unwrap()));
+    }
+
+    #[test]
+    fn it_errors_when_superclass_is_not_a_class() {
+        let mut interpreter = run("var NotAClass = 1;");
+        let tokens = interpreter.scanner.scan("class A < NotAClass {} class B < B {}").unwrap();
+        interpreter.parser.add_tokens(*tokens);
+        let stmts = interpreter.parser.parse().unwrap();
+        assert!(interpreter.interpret(&stmts[0]).is_err());
+        assert!(interpreter.interpret(&stmts[1]).is_err());
+    }
+
+    #[test]
+    fn it_captures_the_defining_environment_in_closures() {
+        let interpreter = run(r#"
+            fun makeCounter() {
+                var i = 0;
+                fun count() {
+                    i = i + 1;
+                    return i;
+                }
+                return count;
+            }
+            var counter = makeCounter();
+            counter();
+            var result = counter();
+        "#);
+        assert_eq!(Some(Value::Number(2.0)), interpreter.scope.get("result"));
+    }
+
+    #[test]
+    fn it_shares_closure_mutations_with_the_enclosing_scope() {
+        let interpreter = run(r#"
+            var total = 0;
+            fun add(n) { total = total + n; }
+            add(3);
+            add(4);
+        "#);
+        assert_eq!(Some(Value::Number(7.0)), interpreter.scope.get("total"));
+    }
+
+    fn resolve(source: &str) -> Vec<ResolveError> {
+        let mut interpreter = Interpreter::default();
+        let tokens = interpreter.scanner.scan(source).expect("failed to scan");
+        interpreter.parser.add_tokens(*tokens);
+        let mut stmts = interpreter.parser.parse().expect("failed to parse");
+        Resolver::new().resolve(&mut stmts).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn it_binds_closures_to_the_variable_in_scope_at_definition() {
+        let interpreter = run(r#"
+            var a = 1;
+            var first;
+            var second;
+            {
+                fun showA() { return a; }
+                first = showA();
+                var a = 2;
+                second = showA();
+            }
+        "#);
+        assert_eq!(Some(Value::Number(1.0)), interpreter.scope.get("first"));
+        assert_eq!(Some(Value::Number(1.0)), interpreter.scope.get("second"));
+    }
+
+    #[test]
+    fn it_breaks_out_of_and_continues_loops() {
+        let interpreter = run(r#"
+            var evens = 0;
+            for (var i = 0; i < 10; i = i + 1) {
+                if (i == 7) break;
+                var half = i / 2;
+                if (half != floor(half)) continue;
+                evens = evens + 1;
+            }
+            var n = 0;
+            while (true) {
+                n = n + 1;
+                fun check() { return n; }
+                if (check() < 3) continue;
+                break;
+            }
+        "#);
+        assert_eq!(Some(Value::Number(4.0)), interpreter.scope.get("evens"));
+        assert_eq!(Some(Value::Number(3.0)), interpreter.scope.get("n"));
+    }
+
+    #[test]
+    fn it_reports_static_errors_from_the_resolver() {
+        assert_eq!(1, resolve("{ var a = 1; var a = 2; }").len());
+        assert_eq!(1, resolve("{ var a = a; }").len());
+        assert_eq!(1, resolve("return 1;").len());
+        assert_eq!(1, resolve("print this;").len());
+        assert_eq!(1, resolve("class A { init() { return 1; } }").len());
+        assert_eq!(1, resolve("class A { init() { return nil; } }").len());
+        assert_eq!(0, resolve("class A { init() { return; } }").len());
+        assert_eq!(0, resolve("var a = 1; var a = 2;").len());
+        assert_eq!(1, resolve("break;").len());
+        assert_eq!(1, resolve("while (true) { fun f() { continue; } }").len());
+        assert_eq!(0, resolve("while (true) { { break; } }").len());
+        assert_eq!(1, resolve("fun f() { import \"lib.lox\"; }").len());
+        assert_eq!(0, resolve("import { a } from \"lib.lox\"; print a;").len());
+
+        let diagnostic = resolve("{ var a = 1;\n  var a = 2; }")[0].diagnostic();
+        assert_eq!("R0004", diagnostic.code());
+        assert_eq!(Some(Span::new(2, 3, 2, 12)), diagnostic.span());
+
+        // a duplicate parameter is pointed at, not the whole function
+        let diagnostic = resolve("fun f(a,\n      a) {\n}")[0].diagnostic();
+        assert_eq!("R0004", diagnostic.code());
+        assert_eq!(Some(Span::new(2, 7, 2, 7)), diagnostic.span());
+    }
+
+    #[test]
+    fn it_calls_native_functions_and_checks_their_arity() {
+        let mut interpreter = Interpreter::default();
+        let double = NativeFunction::new("double", 1, |_, args| match &args[0] {
+            Value::Number(n) => Ok(Value::Number(n * 2.0)),
+            _ => Ok(Value::Nil)
+        });
+        interpreter.globals.declare("double", Value::NativeFunction(double)).unwrap();
+        run_in(&mut interpreter, "var result = double(21);");
+        assert_eq!(Some(Value::Number(42.0)), interpreter.scope.get("result"));
+
+        let tokens = interpreter.scanner.scan("double(1, 2);").unwrap();
+        interpreter.parser.add_tokens(*tokens);
+        let stmts = interpreter.parser.parse().unwrap();
+        assert!(interpreter.interpret(&stmts[0]).is_err());
+    }
+
+    #[test]
+    fn it_provides_the_core_library_below_the_globals() {
+        let interpreter = run(r#"
+            var s = str(12) + "!";
+            var same = str(1) == "1.00";
+            var n = num(" 4 ") + 1;
+            var l = len("hello");
+            var t = type(nil) + type("x") + type(clock);
+            var maths = sqrt(16) + floor(2.7) + abs(-1) + pow(2, 3);
+            var ticking = clock() > 0;
+            var len = "shadowed";
+        "#);
+        assert_eq!("12.00!", interpreter.scope.get("s").unwrap().to_string());
+        assert_eq!(Some(Value::Bool(true)), interpreter.scope.get("same"));
+        assert_eq!(Some(Value::Number(5.0)), interpreter.scope.get("n"));
+        assert_eq!(Some(Value::Number(5.0)), interpreter.scope.get("l"));
+        assert_eq!("nilstringfunction", interpreter.scope.get("t").unwrap().to_string());
+        assert_eq!(Some(Value::Number(15.0)), interpreter.scope.get("maths"));
+        assert_eq!(Some(Value::Bool(true)), interpreter.scope.get("ticking"));
+        assert_eq!("shadowed", interpreter.scope.get("len").unwrap().to_string());
+    }
+
+    #[test]
+    fn it_stops_running_when_a_script_calls_exit() {
+        let mut interpreter = Interpreter::default();
+        interpreter.execute_source("var a = 1;\nfun quit() { exit(3); }\nquit();\na = 2;").unwrap();
+        assert_eq!(Some(3), interpreter.exit_code());
+        assert_eq!(Some(Value::Number(1.0)), interpreter.scope.get("a"));
+    }
+
+    #[test]
+    fn it_writes_print_output_and_diagnostics_to_the_given_sinks() {
+        let out = BufferSink::new();
+        let err = BufferSink::new();
+        let mut interpreter = Interpreter::with_output(out.clone(), err.clone());
+        interpreter.execute_source("print 1 + 2;\nprint \"done\";").unwrap();
+        assert_eq!("3.00\ndone\n", out.take());
+        assert_eq!("", err.contents());
+
+        assert!(interpreter.execute_source("print -nil;").is_err());
+        assert_eq!("", out.contents());
+        assert!(err.contents().starts_with("error[E0001]: Expected number, got nil\n --> <input>:1:7"));
+    }
+
+    #[test]
+    fn it_lets_a_session_redefine_globals() {
+        let mut interpreter = Interpreter::with_output(BufferSink::new(), BufferSink::new());
+        interpreter.eval("fun f() { return 1; }").unwrap();
+        interpreter.eval("fun f() { return 2; }").unwrap();
+        assert_eq!(Value::Number(2.0), interpreter.eval("f();").unwrap());
+        assert!(interpreter.eval("{ var a = 1; var a = 2; }").is_err());
+    }
+
+    #[test]
+    fn it_tells_errors_found_before_running_from_runtime_errors() {
+        let out = BufferSink::new();
+        let mut interpreter = Interpreter::with_output(out.clone(), BufferSink::new());
+        assert_eq!(std::io::ErrorKind::InvalidData, interpreter.execute_source("print 0;\nprint 1 +;").unwrap_err().kind());
+        assert_eq!(std::io::ErrorKind::InvalidData, interpreter.execute_source("print 0;\nreturn 1;").unwrap_err().kind());
+        assert_eq!("", out.contents());
+        assert_eq!(std::io::ErrorKind::InvalidInput, interpreter.execute_source("print -nil;").unwrap_err().kind());
+    }
+
+    #[test]
+    fn it_compares_strings_by_value_and_objects_by_identity() {
+        let interpreter = run(r#"
+            class A {}
+            var a = A();
+            var same_string = "ab" == "ab";
+            var same_instance = a == a;
+            var different_instances = A() == A();
+        "#);
+        assert_eq!(Some(Value::Bool(true)), interpreter.scope.get("same_string"));
+        assert_eq!(Some(Value::Bool(true)), interpreter.scope.get("same_instance"));
+        assert_eq!(Some(Value::Bool(false)), interpreter.scope.get("different_instances"));
+    }
+
+    #[test]
+    fn it_indexes_lists_and_changes_them_through_natives() {
+        let interpreter = run(r#"
+            var xs = [1, 2, 3];
+            var alias = xs;
+            xs[0] = "a";
+            push(xs, 4);
+            insert(xs, 1, 9);
+            var removed = remove(xs, 2);
+            var popped = pop(xs);
+            var part = slice(xs, 1, 3);
+            var total = 0;
+            fun add(x) { total = total + x; }
+            each(part, add);
+        "#);
+        assert_eq!("[a, 9.00, 3.00]", interpreter.scope.get("alias").unwrap().to_string());
+        assert_eq!(Some(Value::Number(2.0)), interpreter.scope.get("removed"));
+        assert_eq!(Some(Value::Number(4.0)), interpreter.scope.get("popped"));
+        assert_eq!(Some(Value::Number(12.0)), interpreter.scope.get("total"));
+        assert_eq!("[9.00, 3.00]", interpreter.scope.get("part").unwrap().to_string());
+
+        let cyclic = run("var xs = [1]; push(xs, xs);");
+        assert_eq!("[1.00, [...]]", cyclic.scope.get("xs").unwrap().to_string());
+    }
+
+    #[test]
+    fn it_stores_map_entries_under_keys_compared_by_value() {
+        let interpreter = run(r#"
+            var m = {"a": 1, 2: "two"};
+            m["b"] = m["a"] + 1;
+            m[2.0] = "still two";
+            m[-0] = "zero";
+            var a = m["a"];
+            var had = has(m, "b");
+            var removed = remove(m, "b");
+            var gone = has(m, "b");
+            var ks = keys(m);
+            var vs = values(m);
+        "#);
+        assert_eq!(Some(Value::Number(1.0)), interpreter.scope.get("a"));
+        assert_eq!(Some(Value::Bool(true)), interpreter.scope.get("had"));
+        assert_eq!(Some(Value::Number(2.0)), interpreter.scope.get("removed"));
+        assert_eq!(Some(Value::Bool(false)), interpreter.scope.get("gone"));
+        assert_eq!("[a, 2.00, 0.00]", interpreter.scope.get("ks").unwrap().to_string());
+        assert_eq!("[1.00, still two, zero]", interpreter.scope.get("vs").unwrap().to_string());
+
+        let mut interpreter = Interpreter::default();
+        let errors = interpreter.eval(r#"print {"a": 1}["b"];"#).unwrap_err();
+        assert_eq!("Undefined key b", errors[0].message());
+        let errors = interpreter.eval("var m = {}; m[[]] = 1;").unwrap_err();
+        assert_eq!("Map keys must be nil, booleans, numbers or strings, got list", errors[0].message());
+    }
+
+    #[test]
+    fn it_interpolates_any_value_into_strings() {
+        let mut interpreter = Interpreter::default();
+        let greeting = interpreter.eval(r#"
+            class Person {}
+            var first = "Ada";
+            var age = 36;
+            "Hi ${first}, you are ${age} with ${[nil, true]} and a ${Person()}";
+        "#).unwrap();
+        assert_eq!(Value::string("Hi Ada, you are 36.00 with [nil, true] and a Person instance"), greeting);
+        assert_eq!(Value::string("2"), interpreter.eval(r#""${ "${1 + 1}" == str(2) and "2" }";"#).unwrap());
+    }
+
+    #[test]
+    fn it_reports_out_of_bounds_list_indexes() {
+        let mut interpreter = Interpreter::default();
+        let messages: Vec<String> = ["[1, 2][2];", "[1][-1];", "[1][0.5];", "pop([]);", "slice([1, 2, 3], 0, 10);",
+            "slice([1, 2, 3], 4, 3);", "slice([1, 2, 3], 2, 1);", "insert([1], 5, 2);", "1[0];"].iter()
+            .map(|source| interpreter.eval(source).unwrap_err()[0].message().to_string())
+            .collect();
+        assert_eq!(vec![
+            "Index 2 out of bounds for list of length 2",
+            "Index -1 out of bounds for list of length 1",
+            "List index must be a whole number, got 0.5",
+            "pop: the list is empty",
+            "Index 10 out of bounds for list of length 3",
+            "Index 4 out of bounds for list of length 3",
+            "slice: start 2 is after end 1",
+            "Index 5 out of bounds for list of length 1",
+            "Only lists and maps can be indexed, got number"
+        ], messages);
+    }
+
+    #[test]
+    fn it_reports_the_location_of_runtime_errors() {
+        let mut interpreter = run("var a = 1;");
+        let tokens = interpreter.scanner.scan("fun f() {\n  return a - \"x\";\n}\nf();").unwrap();
+        interpreter.parser.add_tokens(*tokens);
+        let mut stmts = interpreter.parser.parse().unwrap();
+        Resolver::new().resolve(&mut stmts).unwrap();
+        interpreter.interpret(&stmts[0]).unwrap();
+        let err = interpreter.interpret(&stmts[1]).unwrap_err();
+        assert_eq!(Some(Span::new(2, 10, 2, 16)), err.span());
+        assert!(interpreter.located(&err).starts_with("<input>:2:10: Operator MINUS"));
+        assert_eq!(["called from 4:1".to_string()], err.diagnostic().notes());
+    }
+}
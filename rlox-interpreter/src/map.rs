@@ -0,0 +1,126 @@
+use super::{InterpreterError, Value};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A value that can be used as a map key. Only nil, booleans, numbers and strings are keys; they
+/// compare by value, with `0` and `-0` being the same key and NaN never being one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Nil,
+    Bool(bool),
+    Number(u64),
+    String(Rc<str>)
+}
+
+impl MapKey {
+    pub fn from_value(v: &Value) -> super::Result<MapKey> {
+        match v {
+            Value::Nil => Ok(MapKey::Nil),
+            Value::Bool(b) => Ok(MapKey::Bool(*b)),
+            Value::Number(n) if n.is_nan() => Err(InterpreterError::new("Map keys can't be NaN")),
+            // adding zero turns -0 into 0
+            Value::Number(n) => Ok(MapKey::Number((n + 0.0).to_bits())),
+            Value::String(s) => Ok(MapKey::String(s.clone())),
+            v => Err(InterpreterError::new(format!("Map keys must be nil, booleans, numbers or strings, got {}", v.type_name())))
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::Nil => Value::Nil,
+            MapKey::Bool(b) => Value::Bool(*b),
+            MapKey::Number(bits) => Value::Number(f64::from_bits(*bits)),
+            MapKey::String(s) => Value::String(s.clone())
+        }
+    }
+}
+
+/// An associative array that keeps its keys in the order they were first inserted.
+#[derive(Debug, Default)]
+pub struct LoxMap {
+    entries: Vec<(MapKey, Value)>,
+    index: HashMap<MapKey, usize>
+}
+
+impl LoxMap {
+    pub fn new() -> LoxMap {
+        LoxMap::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<&Value> {
+        self.index.get(key).map(|i| &self.entries[*i].1)
+    }
+
+    pub fn contains(&self, key: &MapKey) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Sets the value of `key`, keeping its position if it is already present.
+    pub fn insert(&mut self, key: MapKey, value: Value) {
+        match self.index.get(&key) {
+            Some(i) => self.entries[*i].1 = value,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<Value> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for (k, _) in &self.entries[i..] {
+            if let Some(position) = self.index.get_mut(k) {
+                *position -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.index.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&MapKey, &Value)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoxMap, MapKey, Value};
+
+    #[test]
+    fn it_keeps_insertion_order_across_updates_and_removals() {
+        let mut map = LoxMap::new();
+        for (i, k) in ["a", "b", "c"].iter().enumerate() {
+            map.insert(MapKey::from_value(&Value::string(k)).unwrap(), Value::from(i as i32));
+        }
+        let b = MapKey::from_value(&Value::string("b")).unwrap();
+        map.insert(MapKey::from_value(&Value::string("a")).unwrap(), Value::from(10));
+        assert_eq!(Some(Value::from(1)), map.remove(&b));
+        assert_eq!(None, map.remove(&b));
+
+        let entries: Vec<String> = map.iter().map(|(k, v)| format!("{}={}", k.to_value(), v)).collect();
+        assert_eq!(vec!["a=10.00", "c=2.00"], entries);
+        assert_eq!(Some(&Value::from(2)), map.get(&MapKey::from_value(&Value::string("c")).unwrap()));
+    }
+
+    #[test]
+    fn it_compares_number_keys_by_value() {
+        let zero = MapKey::from_value(&Value::Number(0.0)).unwrap();
+        assert_eq!(zero, MapKey::from_value(&Value::Number(-0.0)).unwrap());
+        assert_ne!(zero, MapKey::from_value(&Value::string("0")).unwrap());
+        assert!(MapKey::from_value(&Value::Number(f64::NAN)).is_err());
+        assert!(MapKey::from_value(&Value::list(vec![])).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use super::{Interpreter, InterpreterError, ScopeEnvironment, Value};
+use rlox_contract::{LiteralTokenType, Token};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The modules an interpreter has imported, keyed by their canonical path, so that each one
+/// runs once however many times it is imported.
+#[derive(Default)]
+pub struct ModuleCache {
+    loaded: HashMap<PathBuf, ScopeEnvironment>,
+    /// The script being run, if it came from a file, followed by the modules still running
+    /// their top-level code. Importing any of them again would never finish.
+    loading: Vec<PathBuf>,
+    /// For each global an import has bound, keyed by the importing module (`None` outside any
+    /// file) and the global's name: the module it came from and the value it was given.
+    bound: HashMap<(Option<PathBuf>, String), (PathBuf, Value)>
+}
+
+impl ModuleCache {
+    pub fn set_script(&mut self, path: &Path) {
+        self.loading = path.canonicalize().into_iter().collect();
+    }
+}
+
+impl Interpreter {
+
+    /// Binds copies of the globals of the module at `path` in the current scope: all of them,
+    /// or only `names` when they are given. A name already bound there is an error, unless an
+    /// earlier import of the same module bound it and it still holds that value, in which case
+    /// it gets the module's current value.
+    pub(crate) fn import(&mut self, path: &str, names: &Option<Vec<Token>>) -> super::Result<()> {
+        let (key, module) = self.load_module(path)?;
+        let imported = match names {
+            None => module.variables(),
+            Some(names) => {
+                let mut imported = Vec::new();
+                for name in names {
+                    if let Token::Literal(LiteralTokenType::IdentifierLiteral(s)) = name {
+                        let value = module.get_local(s)
+                            .ok_or_else(|| InterpreterError::new(format!("Module {} has no global named {}", path, s)))?;
+                        imported.push((s.clone(), value));
+                    }
+                }
+                imported
+            }
+        };
+        let importer = self.modules.loading.last().cloned();
+        for (name, _) in &imported {
+            if let Some(current) = self.scope.get_local(name) {
+                match self.modules.bound.get(&(importer.clone(), name.clone())) {
+                    Some((from, value)) if from == &key && value == &current => (),
+                    _ => return Err(InterpreterError::new(format!("Can't import {} from {}: there is already a global named {}", name, path, name)))
+                }
+            }
+        }
+        for (name, value) in imported {
+            self.scope.redeclare(&name, value.clone());
+            self.modules.bound.insert((importer.clone(), name), (key.clone(), value));
+        }
+        Ok(())
+    }
+
+    /// Runs the module at `path`, relative to the directory of the current script, unless it
+    /// has already been run, and returns its canonical path and its globals.
+    fn load_module(&mut self, path: &str) -> super::Result<(PathBuf, ScopeEnvironment)> {
+        let full_path = match &self.script_dir {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path)
+        };
+        let key = full_path.canonicalize()
+            .map_err(|e| InterpreterError::new(format!("Can't import {}: {}", path, e)))?;
+        if let Some(globals) = self.modules.loaded.get(&key) {
+            return Ok((key, globals.clone()));
+        }
+        if let Some(i) = self.modules.loading.iter().position(|p| p == &key) {
+            let cycle: Vec<String> = self.modules.loading[i..].iter().chain(Some(&key)).map(|p| p.display().to_string()).collect();
+            return Err(InterpreterError::new(format!("Import cycle: {}", cycle.join(" -> "))));
+        }
+        let source = std::fs::read_to_string(&key)
+            .map_err(|e| InterpreterError::new(format!("Can't import {}: {}", path, e)))?;
+
+        self.modules.loading.push(key.clone());
+        let globals = self.prelude.new_child();
+        let result = self.run_module(&key, &source, globals.clone());
+        self.modules.loading.pop();
+        result?;
+        self.modules.loaded.insert(key.clone(), globals.clone());
+        Ok((key, globals))
+    }
+
+    /// Runs `source` with `globals` as its global scope, putting the interpreter back the way it
+    /// was afterwards. Errors inside the module become notes on a single error for the import.
+    fn run_module(&mut self, path: &Path, source: &str, globals: ScopeEnvironment) -> super::Result<()> {
+        let name = path.display().to_string();
+        let previous_globals = std::mem::replace(&mut self.globals, globals.clone());
+        let previous_scope = std::mem::replace(&mut self.scope, globals);
+        let previous_name = std::mem::replace(&mut self.source_name, name.clone());
+        let previous_dir = std::mem::replace(&mut self.script_dir, path.parent().map(Path::to_path_buf));
+
+        let result = match self.prepare(source) {
+            Ok(stmts) => stmts.iter().try_for_each(|s| self.interpret(s).map(|_| ())).map_err(|e| vec![e.diagnostic()]),
+            Err(diagnostics) => Err(diagnostics)
+        };
+
+        self.globals = previous_globals;
+        self.scope = previous_scope;
+        self.source_name = previous_name;
+        self.script_dir = previous_dir;
+
+        result.map_err(|diagnostics| {
+            let mut error = InterpreterError::new(format!("Failed to import {}", name));
+            if self.exit_code.is_none() {
+                error.notes = diagnostics.iter().map(|d| match d.span() {
+                    Some(span) => format!("{}:{}: {}", name, span, d.message()),
+                    None => format!("{}: {}", name, d.message())
+                }).collect();
+            }
+            error
+        })
+    }
+}
@@ -0,0 +1,145 @@
+use super::{InterpreterError, LoxMap, MapKey, NativeFunction, Value};
+use std::cell::RefCell;
+use std::io::BufRead;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The functions every interpreter starts with.
+pub fn core_library() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("clock", 0, |_, _| {
+            let elapsed = SystemTime::now().duration_since(UNIX_EPOCH)
+                .map_err(|e| InterpreterError::new(format!("clock: {}", e)))?;
+            Ok(Value::Number(elapsed.as_secs_f64()))
+        }),
+        NativeFunction::new("str", 1, |_, args| Ok(Value::string(&args[0]))),
+        NativeFunction::new("num", 1, |_, args| match &args[0] {
+            Value::Number(n) => Ok(Value::Number(*n)),
+            v => {
+                let contents = v.string_contents().ok_or_else(|| type_error("num", "a number or a string", v))?;
+                contents.trim().parse().map(Value::Number)
+                    .map_err(|_| InterpreterError::new(format!("num: can't convert '{}' to a number", contents)))
+            }
+        }),
+        NativeFunction::new("len", 1, |_, args| match &args[0] {
+            Value::List(l) => Ok(Value::Number(l.borrow().len() as f64)),
+            Value::Map(m) => Ok(Value::Number(m.borrow().len() as f64)),
+            v => match v.string_contents() {
+                Some(s) => Ok(Value::Number(s.chars().count() as f64)),
+                None => Err(type_error("len", "a string, a list or a map", v))
+            }
+        }),
+        NativeFunction::new("type", 1, |_, args| Ok(Value::string(args[0].type_name()))),
+        NativeFunction::new("input", 1, |interpreter, args| {
+            let out = interpreter.output();
+            write!(out, "{}", args[0]).and_then(|_| out.flush()).map_err(|e| InterpreterError::new(format!("input: {}", e)))?;
+            let mut line = String::new();
+            let read = std::io::stdin().lock().read_line(&mut line)
+                .map_err(|e| InterpreterError::new(format!("input: {}", e)))?;
+            if read == 0 {
+                return Ok(Value::Nil);
+            }
+            Ok(Value::string(line.trim_end_matches(&['\r', '\n'][..])))
+        }),
+        NativeFunction::new("sqrt", 1, |_, args| Ok(Value::Number(number("sqrt", &args[0])?.sqrt()))),
+        NativeFunction::new("floor", 1, |_, args| Ok(Value::Number(number("floor", &args[0])?.floor()))),
+        NativeFunction::new("abs", 1, |_, args| Ok(Value::Number(number("abs", &args[0])?.abs()))),
+        NativeFunction::new("pow", 2, |_, args| {
+            Ok(Value::Number(number("pow", &args[0])?.powf(number("pow", &args[1])?)))
+        }),
+        NativeFunction::new("push", 2, |_, args| {
+            list("push", &args[0])?.borrow_mut().push(args[1].clone());
+            Ok(Value::Nil)
+        }),
+        NativeFunction::new("pop", 1, |_, args| {
+            list("pop", &args[0])?.borrow_mut().pop().ok_or_else(|| InterpreterError::new("pop: the list is empty"))
+        }),
+        NativeFunction::new("insert", 3, |_, args| {
+            let mut l = list("insert", &args[0])?.borrow_mut();
+            // inserting at the end is allowed
+            let i = args[1].as_position(l.len())?;
+            l.insert(i, args[2].clone());
+            Ok(Value::Nil)
+        }),
+        NativeFunction::new("remove", 2, |_, args| match &args[0] {
+            Value::Map(m) => Ok(m.borrow_mut().remove(&MapKey::from_value(&args[1])?).unwrap_or(Value::Nil)),
+            v => {
+                let mut l = list("remove", v)?.borrow_mut();
+                let i = args[1].as_index(l.len())?;
+                Ok(l.remove(i))
+            }
+        }),
+        NativeFunction::new("slice", 3, |_, args| {
+            let l = list("slice", &args[0])?.borrow();
+            let start = args[1].as_position(l.len())?;
+            let end = args[2].as_position(l.len())?;
+            if start > end {
+                return Err(InterpreterError::new(format!("slice: start {} is after end {}", start, end)));
+            }
+            Ok(Value::list(l[start..end].to_vec()))
+        }),
+        NativeFunction::new("each", 2, |interpreter, args| {
+            let l = list("each", &args[0])?;
+            // look the list up again on every step, so the callback may change it
+            for i in 0.. {
+                let v = match l.borrow().get(i) {
+                    Some(v) => v.clone(),
+                    None => break
+                };
+                interpreter.call(&args[1], vec![v])?;
+            }
+            Ok(Value::Nil)
+        }),
+        NativeFunction::new("keys", 1, |_, args| {
+            Ok(Value::list(map("keys", &args[0])?.borrow().iter().map(|(k, _)| k.to_value()).collect()))
+        }),
+        NativeFunction::new("values", 1, |_, args| {
+            Ok(Value::list(map("values", &args[0])?.borrow().iter().map(|(_, v)| v.clone()).collect()))
+        }),
+        NativeFunction::new("has", 2, |_, args| {
+            Ok(Value::Bool(map("has", &args[0])?.borrow().contains(&MapKey::from_value(&args[1])?)))
+        }),
+        NativeFunction::new("exit", 1, |interpreter, args| {
+            let code = exit_code(number("exit", &args[0])?)?;
+            interpreter.exit_code = Some(code);
+            // unwinds the running program; execute_source sees the exit code and stops quietly
+            Err(InterpreterError::new(format!("exit({})", code)))
+        })
+    ]
+}
+
+/// Checks that `code` is something a process can exit with.
+fn exit_code(code: f64) -> super::Result<i32> {
+    if code.fract() != 0.0 {
+        Err(InterpreterError::new(format!("exit: the code must be a whole number, got {}", code)))
+    } else if !(0.0..=255.0).contains(&code) {
+        Err(InterpreterError::new(format!("exit: the code must be from 0 to 255, got {}", code)))
+    } else {
+        Ok(code as i32)
+    }
+}
+
+fn number(name: &str, v: &Value) -> super::Result<f64> {
+    match v {
+        Value::Number(n) => Ok(*n),
+        _ => Err(type_error(name, "a number", v))
+    }
+}
+
+fn list<'a>(name: &str, v: &'a Value) -> super::Result<&'a Rc<RefCell<Vec<Value>>>> {
+    match v {
+        Value::List(l) => Ok(l),
+        _ => Err(type_error(name, "a list", v))
+    }
+}
+
+fn map<'a>(name: &str, v: &'a Value) -> super::Result<&'a Rc<RefCell<LoxMap>>> {
+    match v {
+        Value::Map(m) => Ok(m),
+        _ => Err(type_error(name, "a map", v))
+    }
+}
+
+fn type_error(name: &str, expected: &str, got: &Value) -> InterpreterError {
+    InterpreterError::new(format!("{} expects {}, got {}", name, expected, got.type_name()))
+}
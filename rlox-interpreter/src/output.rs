@@ -0,0 +1,55 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// An in-memory sink for `print` output or diagnostics. Clones share one buffer, so a clone
+/// can be given to an [`Interpreter`](crate::Interpreter) and the original read afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct BufferSink {
+    buffer: Rc<RefCell<Vec<u8>>>
+}
+
+impl BufferSink {
+    pub fn new() -> BufferSink {
+        BufferSink::default()
+    }
+
+    /// Everything written so far.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.buffer.borrow()).into_owned()
+    }
+
+    /// Returns everything written so far and empties the buffer.
+    pub fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.buffer.borrow_mut());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for BufferSink {
+
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BufferSink;
+    use std::io::Write;
+
+    #[test]
+    fn it_shares_its_buffer_between_clones() {
+        let sink = BufferSink::new();
+        let mut writer = sink.clone();
+        writeln!(writer, "hello").unwrap();
+        assert_eq!("hello\n", sink.contents());
+        assert_eq!("hello\n", sink.take());
+        assert_eq!("", sink.contents());
+    }
+}
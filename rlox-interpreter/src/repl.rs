@@ -0,0 +1,408 @@
+use super::{Interpreter, ModuleCache, Value};
+use rlox_contract::{Stmt, Token};
+use rlox_parser::ast_printer::print;
+use rlox_parser::{ParseErrorKind, Parser};
+use rlox_scanner::Scanner;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+
+const PROMPT: &str = "rlox] ";
+const CONTINUATION_PROMPT: &str = "  ..] ";
+const HISTORY_FILE: &str = ".rlox_history";
+const KEYWORDS: [&str; 19] = ["and", "break", "class", "continue", "else", "false", "for", "fun", "if", "import", "nil", "or",
+    "print", "return", "super", "this", "true", "var", "while"];
+
+const HELP: &str = "\
+:env             show the variables in the current scope
+:ast <code>      show the syntax tree of <code> without running it
+:tokens <code>   show the tokens <code> is scanned into
+:load <file>     run a file in this session
+:reset           forget every global declared in this session
+:help            show this list
+quit             leave the REPL";
+
+enum ReplInput {
+    Line(String),
+    /// The user pressed Ctrl-C.
+    Cancel,
+    End
+}
+
+/// Completes the word before the cursor from a snapshot of the keywords and names in scope,
+/// taken before each line is read.
+struct LoxHelper {
+    names: Vec<String>
+}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].char_indices().rev()
+            .find(|&(_, c)| !c.is_alphanumeric() && c != '_')
+            .map(|(i, c)| i + c.len_utf8())
+            .unwrap_or(0);
+        let prefix = &line[start..pos];
+        if prefix.is_empty() {
+            return Ok((pos, Vec::new()));
+        }
+        Ok((start, self.names.iter().filter(|n| n.starts_with(prefix)).cloned().collect()))
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LoxHelper {}
+impl Validator for LoxHelper {}
+impl Helper for LoxHelper {}
+
+/// Whether `source` stops partway through a statement, so a REPL should read another line
+/// before running it: a string, bracket or brace is still open, or the parser ran out of tokens.
+pub fn needs_more_input(source: &str) -> bool {
+    let tokens = match Scanner::new().scan(source) {
+        Ok(tokens) => tokens,
+        // unterminated strings and interpolations
+        Err(e) => return e.diagnostic().code() == "L0002"
+    };
+    let depth = tokens.iter().fold(0i32, |depth, t| match t.token() {
+        Token::LeftParen | Token::LeftBrace | Token::LeftBracket => depth + 1,
+        Token::RightParen | Token::RightBrace | Token::RightBracket => depth - 1,
+        _ => depth
+    });
+    if depth > 0 {
+        return true;
+    }
+    let mut parser = Parser::new();
+    parser.add_tokens(*tokens);
+    let (_, errors) = parser.parse_with_errors();
+    errors.iter().any(|e| e.kind() == ParseErrorKind::UnexpectedEof) && !ends_with_bare_expression(source)
+}
+
+/// Whether `source` parses once a `;` is added, with an expression statement last.
+fn ends_with_bare_expression(source: &str) -> bool {
+    let tokens = match Scanner::new().scan(&with_semicolon(source)) {
+        Ok(tokens) => tokens,
+        Err(_) => return false
+    };
+    let mut parser = Parser::new();
+    parser.add_tokens(*tokens);
+    matches!(parser.parse().as_deref(), Ok([.., Stmt::ExprStmt(..)]))
+}
+
+impl Interpreter {
+
+    /// Runs an interactive session on the terminal, with line editing, tab completion and a
+    /// history kept in `~/.rlox_history`.
+    pub fn start_repl(&mut self, stdin: &std::io::Stdin, stdout: &mut std::io::Stdout) -> std::io::Result<()> {
+        let mut editor = match Editor::<LoxHelper, DefaultHistory>::new() {
+            Ok(editor) => editor,
+            Err(_) => return self.run_repl(stdin.lock(), stdout)
+        };
+        editor.set_helper(Some(LoxHelper { names: Vec::new() }));
+        let history = home::home_dir().map(|home| home.join(HISTORY_FILE));
+        if let Some(path) = &history {
+            // there is no history yet the first time round
+            let _ = editor.load_history(path);
+        }
+
+        let colored = stdout.is_terminal();
+        let result = self.repl_loop(stdout, colored, |interpreter, prompt, _| {
+            if let Some(helper) = editor.helper_mut() {
+                helper.names = interpreter.completions("");
+            }
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    let _ = editor.add_history_entry(line.as_str());
+                    Ok(ReplInput::Line(line))
+                },
+                Err(ReadlineError::Interrupted) => Ok(ReplInput::Cancel),
+                Err(ReadlineError::Eof) => Ok(ReplInput::End),
+                Err(ReadlineError::Io(e)) => Err(e),
+                Err(e) => Err(std::io::Error::other(e))
+            }
+        });
+
+        if let Some(path) = &history {
+            if let Err(e) = editor.save_history(path) {
+                let _ = writeln!(self.err, "Couldn't save the REPL history to {}: {}", path.display(), e);
+            }
+        }
+        result
+    }
+
+    /// Runs a session reading lines from `input` rather than the terminal, writing prompts and
+    /// results to `output`.
+    pub fn run_repl<R, W>(&mut self, mut input: R, output: &mut W) -> std::io::Result<()> where R : BufRead, W : Write {
+        self.repl_loop(output, false, |_, prompt, output| {
+            write!(output, "{}", prompt)?;
+            output.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(ReplInput::End);
+            }
+            // only the line ending goes, since the line may be inside a string
+            Ok(ReplInput::Line(line.trim_end_matches(&['\r', '\n'][..]).to_string()))
+        })
+    }
+
+    /// Names that could complete `prefix`: keywords and every variable visible from the current scope.
+    pub fn completions(&self, prefix: &str) -> Vec<String> {
+        let mut names: Vec<String> = KEYWORDS.iter().map(|k| k.to_string()).chain(self.scope.names())
+            .filter(|n| n.starts_with(prefix))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Reads statements with `read` until `quit` or the end of input, running each once it is
+    /// complete. A blank line runs whatever has been typed so far, complete or not, and
+    /// cancelling a line throws it away. The values of bare expressions are shown in green when
+    /// `colored` is set.
+    fn repl_loop<W, F>(&mut self, output: &mut W, colored: bool, mut read: F) -> std::io::Result<()>
+        where W : Write, F : FnMut(&Interpreter, &str, &mut W) -> std::io::Result<ReplInput> {
+        self.set_source_name("<repl>");
+        let mut buffer = String::new();
+        while self.exit_code.is_none() {
+            let prompt = if buffer.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+            let line = match read(self, prompt, output)? {
+                ReplInput::Line(line) => line,
+                ReplInput::Cancel => {
+                    buffer.clear();
+                    continue;
+                },
+                ReplInput::End => break
+            };
+            if buffer.is_empty() && line.trim() == "quit" {
+                break;
+            }
+            if buffer.is_empty() && line.trim_start().starts_with(':') {
+                self.meta_command(line.trim(), output, colored)?;
+                continue;
+            }
+            buffer.push_str(&line);
+            buffer.push('\n');
+            if !line.trim().is_empty() && needs_more_input(&buffer) {
+                continue;
+            }
+            let source = std::mem::take(&mut buffer);
+            if !source.trim().is_empty() {
+                self.run_repl_input(&source, output, colored)?;
+            }
+        }
+        writeln!(output, "Exiting...")?;
+        Ok(())
+    }
+
+    /// Runs one of the `:` commands listed by `:help`.
+    fn meta_command<W>(&mut self, line: &str, output: &mut W, colored: bool) -> std::io::Result<()> where W : Write {
+        let (command, argument) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, "")
+        };
+        if argument.is_empty() && matches!(command, ":ast" | ":tokens" | ":load") {
+            let what = if command == ":load" { "<file>" } else { "<code>" };
+            return writeln!(output, "Usage: {} {}", command, what);
+        }
+        match command {
+            ":env" => writeln!(output, "{}", self.scope)?,
+            ":ast" => {
+                let parsed = self.scanner.scan(argument).map_err(|e| vec![e.diagnostic()]).and_then(|tokens| {
+                    self.parser.add_tokens(*tokens);
+                    self.parser.parse().map_err(|e| e.diagnostics())
+                });
+                match parsed {
+                    Ok(stmts) => for s in stmts {
+                        writeln!(output, "{}", print(&s).trim())?;
+                    },
+                    Err(diagnostics) => for d in diagnostics {
+                        self.report(&d, argument);
+                    }
+                }
+            },
+            ":tokens" => match self.scanner.scan(argument) {
+                Ok(tokens) => for t in tokens.iter() {
+                    writeln!(output, "{}", t)?;
+                },
+                Err(e) => self.report(&e.diagnostic(), argument)
+            },
+            ":load" => self.load(Path::new(argument), output, colored)?,
+            ":reset" => {
+                self.reset();
+                writeln!(output, "Cleared every global")?;
+            },
+            ":help" => writeln!(output, "{}", HELP)?,
+            _ => writeln!(output, "Unknown command {}, try :help", command)?
+        }
+        Ok(())
+    }
+
+    /// Runs the file at `path` as if it had been typed in, reporting errors against the file and
+    /// resolving its imports relative to it.
+    fn load<W>(&mut self, path: &Path, output: &mut W, colored: bool) -> std::io::Result<()> where W : Write {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => return writeln!(output, "Couldn't read {}: {}", path.display(), e)
+        };
+        let previous_name = std::mem::replace(&mut self.source_name, path.display().to_string());
+        let previous_dir = std::mem::replace(&mut self.script_dir, path.parent().map(Path::to_path_buf));
+        let result = self.run_repl_input(&source, output, colored);
+        self.source_name = previous_name;
+        self.script_dir = previous_dir;
+        result
+    }
+
+    /// Starts over with fresh globals below the core library, forgetting imported modules too.
+    fn reset(&mut self) {
+        self.globals = self.prelude.new_child();
+        self.scope = self.globals.clone();
+        self.modules = ModuleCache::default();
+    }
+
+    /// Runs what was typed. Input that only parses with a `;` added at the end is taken to finish
+    /// with a bare expression, whose value is always shown, after a `=>`.
+    fn run_repl_input<W>(&mut self, source: &str, output: &mut W, colored: bool) -> std::io::Result<()> where W : Write {
+        let (stmts, bare) = match self.prepare(source) {
+            Ok(stmts) => (stmts, false),
+            Err(diagnostics) => match self.prepare(&with_semicolon(source)) {
+                Ok(stmts) if matches!(stmts.last(), Some(Stmt::ExprStmt(..))) => (stmts, true),
+                _ => {
+                    for d in diagnostics {
+                        self.report(&d, source);
+                    }
+                    return Ok(());
+                }
+            }
+        };
+        let count = stmts.len();
+        for (i, stmt) in stmts.iter().enumerate() {
+            writeln!(output)?;
+            match self.interpret(stmt) {
+                Err(_) if self.exit_code.is_some() => break,
+                Err(e) => self.report(&e.diagnostic(), source),
+                Ok(v) if bare && i + 1 == count => {
+                    if colored {
+                        writeln!(output, "\x1b[32m=> {}\x1b[0m", v)?
+                    } else {
+                        writeln!(output, "=> {}", v)?
+                    }
+                },
+                Ok(v) => {
+                    if v == Value::Nil { continue; } else { writeln!(output, "{}", v)? };
+                    writeln!(output, "OK.")?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `source` with the `;` a bare expression at its end is missing. It goes on a line of its own
+/// so that a trailing comment can't swallow it.
+fn with_semicolon(source: &str) -> String {
+    format!("{}\n;", source.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{needs_more_input, Interpreter, LoxHelper};
+    use rustyline::completion::Completer;
+    use rustyline::history::DefaultHistory;
+    use rustyline::Context;
+    use crate::BufferSink;
+
+    #[test]
+    fn it_waits_for_unbalanced_or_unfinished_input() {
+        assert!(needs_more_input("fun f() {\n"));
+        assert!(needs_more_input("print (1 +\n"));
+        assert!(needs_more_input("var xs = [1,\n"));
+        assert!(needs_more_input("print \"two\nlines"));
+        assert!(needs_more_input("print 1 +\n"));
+        assert!(!needs_more_input("print 1;\n"));
+        assert!(!needs_more_input("print 1);\n"));
+        assert!(!needs_more_input("print @;\n"));
+        assert!(!needs_more_input("1 + 2\n"));
+        assert!(!needs_more_input("var a = 1; a // the answer\n"));
+        assert!(needs_more_input("var a = 1\n"));
+    }
+
+    #[test]
+    fn it_runs_statements_typed_across_several_lines() {
+        let out = BufferSink::new();
+        let err = BufferSink::new();
+        let mut interpreter = Interpreter::with_output(out.clone(), err.clone());
+        let input = "fun add(a, b) {\n  return a + b;\n}\nprint add(1,\n 2);\nprint (1 +\n\nquit\n";
+        let mut shown = Vec::new();
+        interpreter.run_repl(input.as_bytes(), &mut shown).unwrap();
+
+        let shown = String::from_utf8(shown).unwrap();
+        assert_eq!(4, shown.matches("  ..] ").count(), "{}", shown);
+        assert_eq!("3.00\n", out.contents());
+        // the blank line gave up on the unfinished expression
+        assert!(err.contents().contains("P0003"), "{}", err.contents());
+    }
+
+    #[test]
+    fn it_completes_keywords_and_names_in_scope() {
+        let mut interpreter = Interpreter::default();
+        interpreter.eval("var printed = 1; fun outer() {}").unwrap();
+        assert_eq!(vec!["print", "printed"], interpreter.completions("pri"));
+        assert!(interpreter.completions("").contains(&"len".to_string()));
+
+        let helper = LoxHelper { names: interpreter.completions("") };
+        let history = DefaultHistory::new();
+        let (start, candidates) = helper.complete("print out", 9, &Context::new(&history)).unwrap();
+        assert_eq!((6, vec!["outer".to_string()]), (start, candidates));
+        assert!(helper.complete("print ", 6, &Context::new(&history)).unwrap().1.is_empty());
+        // the word can follow a character longer than a byte
+        let line = "var s = \"€pr";
+        assert_eq!((12, vec!["print".to_string(), "printed".to_string()]),
+            helper.complete(line, line.len(), &Context::new(&history)).unwrap());
+        assert!(helper.complete("var s = \"€", 12, &Context::new(&history)).unwrap().1.is_empty());
+    }
+
+    #[test]
+    fn it_runs_meta_commands() {
+        let dir = std::env::temp_dir().join(format!("rlox-repl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.lox"), "var loaded = \"yes\";").unwrap();
+
+        let out = BufferSink::new();
+        let mut interpreter = Interpreter::with_output(out.clone(), BufferSink::new());
+        let input = format!("var a = 1;\n:env\n:ast print -a;\n:tokens a;\n:load {}\nprint loaded;\n:reset\n:env\n:nope\n",
+            dir.join("lib.lox").display());
+        let mut shown = Vec::new();
+        interpreter.run_repl(input.as_bytes(), &mut shown).unwrap();
+
+        let shown = String::from_utf8(shown).unwrap();
+        assert!(shown.contains("(a, 1.00)"), "{}", shown);
+        assert!(shown.contains("PRINT ( Minus  Ident a )"), "{}", shown);
+        assert!(shown.contains("(Ident a 1:0)\n(Semicolon 1:1)\n(Eof 1:2)"), "{}", shown);
+        assert!(shown.contains("Cleared every global\nrlox] ScopeEnv@1 [\nScope~1 []"), "{}", shown);
+        assert!(shown.contains("Unknown command :nope"), "{}", shown);
+        assert_eq!("yes\n", out.contents());
+    }
+
+    #[test]
+    fn it_shows_the_value_of_a_bare_expression() {
+        let mut interpreter = Interpreter::with_output(BufferSink::new(), BufferSink::new());
+        let input = "1 + 2\nvar a = [1]; a[0] // first\nnil\n1 + 2;\n";
+        let mut shown = Vec::new();
+        interpreter.run_repl(input.as_bytes(), &mut shown).unwrap();
+
+        let shown = String::from_utf8(shown).unwrap();
+        assert!(shown.contains("\n=> 3.00\n"), "{}", shown);
+        assert!(shown.contains("\n=> 1.00\n"), "{}", shown);
+        assert!(shown.contains("\n=> nil\n"), "{}", shown);
+        assert!(shown.contains("\n3.00\nOK.\n"), "{}", shown);
+    }
+}
@@ -0,0 +1,340 @@
+use rlox_contract::{Expr, LiteralTokenType, Span, Stmt, Token};
+use rlox_contract::diagnostics::Diagnostic;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Method,
+    Initializer
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass
+}
+
+/// Walks the parsed statements before they are interpreted, recording how many
+/// scopes separate each variable reference from its declaration and collecting
+/// static errors along the way.
+pub struct Resolver {
+    scopes: Vec<HashMap<String, bool>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    /// Number of loops enclosing the current statement within the current function.
+    loop_depth: usize,
+    errors: Vec<ResolveError>
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver { scopes: Vec::new(), current_function: FunctionType::None, current_class: ClassType::None, loop_depth: 0, errors: Vec::new() }
+    }
+
+    pub fn resolve(&mut self, stmts: &mut [Stmt]) -> std::result::Result<(), Vec<ResolveError>> {
+        for s in stmts.iter_mut() {
+            self.resolve_stmt(s);
+        }
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    fn resolve_stmt(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::PrintStmt(inner, _) | Stmt::ExprStmt(inner, _) => self.resolve_expr(inner),
+            Stmt::VarDecl { name, initializer, span } => {
+                self.declare(name, *span);
+                self.resolve_expr(initializer);
+                self.define(name);
+            },
+            Stmt::BlockStmt(stmts, _) => {
+                self.begin_scope();
+                for s in stmts.iter_mut() {
+                    self.resolve_stmt(s);
+                }
+                self.end_scope();
+            },
+            Stmt::IfStmt { condition, then_branch, else_branch, span: _ } => {
+                self.resolve_expr(condition);
+                self.resolve_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_stmt(else_branch);
+                }
+            },
+            Stmt::WhileLoop { condition, body, increment, span: _ } => {
+                self.resolve_expr(condition);
+                self.loop_depth += 1;
+                self.resolve_stmt(body);
+                self.loop_depth -= 1;
+                if let Some(increment) = increment {
+                    self.resolve_expr(increment);
+                }
+            },
+            Stmt::Break(span) => {
+                if self.loop_depth == 0 {
+                    self.error("R0009", "Can't use 'break' outside of a loop", *span);
+                }
+            },
+            Stmt::Continue(span) => {
+                if self.loop_depth == 0 {
+                    self.error("R0009", "Can't use 'continue' outside of a loop", *span);
+                }
+            },
+            Stmt::Import { path: _, names, span } => {
+                // a whole-module import declares names that are only known once the module has run,
+                // which works for globals but can't be resolved inside a block or function
+                if !self.scopes.is_empty() || self.current_function != FunctionType::None {
+                    self.error("R0010", "Can only import at the top level of a script", *span);
+                }
+                for name in names.iter().flatten() {
+                    self.declare(name, *span);
+                    self.define(name);
+                }
+            },
+            Stmt::FunctionDecl { name, params: _, body: _, span } => {
+                let fn_name = name.clone();
+                self.declare(&fn_name, *span);
+                self.define(&fn_name);
+                self.resolve_function(stmt, FunctionType::Function);
+            },
+            Stmt::Return(_, value, span) => {
+                if self.current_function == FunctionType::None {
+                    self.error("R0002", "Can't return from top-level code", *span);
+                }
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        let error = ResolveError::new("R0003", "Can't return a value from an initializer", value.span())
+                            .with_help("initializers always return 'this'; use a bare 'return;' instead");
+                        self.errors.push(error);
+                    }
+                    self.resolve_expr(value);
+                }
+            },
+            Stmt::ClassDecl { name, superclass, methods, span } => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Class;
+                self.declare(name, *span);
+                self.define(name);
+
+                if let Some(s) = superclass {
+                    if let Expr::VariableExpr { name: super_name, depth: _, span: super_span } = s.as_ref() {
+                        if identifier(super_name) == identifier(name) {
+                            self.error("R0008", format!("Class {} can't inherit from itself", identifier(name)), *super_span);
+                        }
+                    }
+                    self.current_class = ClassType::Subclass;
+                    self.resolve_expr(s);
+                    self.begin_scope();
+                    self.scopes.last_mut().unwrap().insert("super".to_string(), true);
+                }
+
+                self.begin_scope();
+                self.scopes.last_mut().unwrap().insert("this".to_string(), true);
+                for m in methods.iter_mut() {
+                    let function_type = match m {
+                        Stmt::FunctionDecl { name, params: _, body: _, span: _ } if identifier(name) == "init" => FunctionType::Initializer,
+                        _ => FunctionType::Method
+                    };
+                    self.resolve_function(m, function_type);
+                }
+                self.end_scope();
+
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+                self.current_class = enclosing_class;
+            }
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::BinaryExpr { left, operator: _, right, span: _ } | Expr::LogicalExpr { left, operator: _, right, span: _ } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            },
+            Expr::GroupingExpr(inner, _) => self.resolve_expr(inner),
+            Expr::LiteralExpr(_, _) => (),
+            Expr::UnaryExpr { operator: _, right, span: _ } => self.resolve_expr(right),
+            Expr::VariableExpr { name, depth, span } => {
+                if let Some(scope) = self.scopes.last() {
+                    if scope.get(&identifier(name)) == Some(&false) {
+                        let error = ResolveError::new("R0001", format!("Can't read local variable {} in its own initializer", identifier(name)), *span)
+                            .with_help("give the new variable a different name");
+                        self.errors.push(error);
+                    }
+                }
+                *depth = self.resolve_local(name);
+            },
+            Expr::AssigmentExpr { name, value, depth, span: _ } => {
+                self.resolve_expr(value);
+                *depth = self.resolve_local(name);
+            },
+            Expr::CallExpr { callee, paren: _, arguments, span: _ } => {
+                self.resolve_expr(callee);
+                for a in arguments.iter_mut() {
+                    self.resolve_expr(a);
+                }
+            },
+            Expr::GetExpr { object, name: _, span: _ } => self.resolve_expr(object),
+            Expr::SetExpr { object, name: _, value, span: _ } => {
+                self.resolve_expr(value);
+                self.resolve_expr(object);
+            },
+            Expr::ListExpr { elements, span: _ } => {
+                for e in elements {
+                    self.resolve_expr(e);
+                }
+            },
+            Expr::MapExpr { entries, span: _ } => {
+                for (k, v) in entries {
+                    self.resolve_expr(k);
+                    self.resolve_expr(v);
+                }
+            },
+            Expr::InterpolationExpr { parts, span: _ } => {
+                for p in parts {
+                    self.resolve_expr(p);
+                }
+            },
+            Expr::IndexExpr { object, index, span: _ } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+            },
+            Expr::IndexSetExpr { object, index, value, span: _ } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+                self.resolve_expr(value);
+            },
+            Expr::ThisExpr { keyword, depth, span } => {
+                if self.current_class == ClassType::None {
+                    self.error("R0005", "Can't use 'this' outside of a class", *span);
+                } else {
+                    *depth = self.resolve_local(keyword);
+                }
+            },
+            Expr::SuperExpr { keyword, method: _, depth, span } => {
+                match self.current_class {
+                    ClassType::None => self.error("R0006", "Can't use 'super' outside of a class", *span),
+                    ClassType::Class => self.error("R0007", "Can't use 'super' in a class with no superclass", *span),
+                    ClassType::Subclass => *depth = self.resolve_local(keyword)
+                }
+            }
+        }
+    }
+
+    fn resolve_function(&mut self, function: &mut Stmt, function_type: FunctionType) {
+        if let Stmt::FunctionDecl { name: _, params, body, span: _ } = function {
+            let enclosing_function = self.current_function;
+            let enclosing_loops = std::mem::replace(&mut self.loop_depth, 0);
+            self.current_function = function_type;
+            self.begin_scope();
+            for (p, span) in params.iter() {
+                self.declare(p, *span);
+                self.define(p);
+            }
+            for s in body.iter_mut() {
+                self.resolve_stmt(s);
+            }
+            self.end_scope();
+            self.current_function = enclosing_function;
+            self.loop_depth = enclosing_loops;
+        }
+    }
+
+    fn resolve_local(&self, name: &Token) -> Option<usize> {
+        let key = identifier(name);
+        self.scopes.iter().rev().position(|s| s.contains_key(&key))
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token, span: Span) {
+        let key = identifier(name);
+        let duplicate = match self.scopes.last_mut() {
+            Some(scope) => scope.insert(key.clone(), false).is_some(),
+            None => false
+        };
+        if duplicate {
+            self.error("R0004", format!("Already a variable named {} in this scope", key), span);
+        }
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(identifier(name), true);
+        }
+    }
+
+    fn error<B: ToString>(&mut self, code: &'static str, msg: B, span: Span) {
+        self.errors.push(ResolveError::new(code, msg, span));
+    }
+}
+
+fn identifier(token: &Token) -> String {
+    match token {
+        Token::Literal(LiteralTokenType::IdentifierLiteral(s)) => s.clone(),
+        Token::This => "this".to_string(),
+        Token::Super => "super".to_string(),
+        t => format!("{}", t)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolveError {
+    msg: String,
+    code: &'static str,
+    span: Span,
+    help: Option<String>
+}
+
+impl ResolveError {
+    pub fn new<B : ToString>(code: &'static str, msg: B, span: Span) -> ResolveError {
+        ResolveError { msg: msg.to_string(), code, span, help: None }
+    }
+
+    fn with_help<B : ToString>(mut self, help: B) -> ResolveError {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.code, &self.msg).with_span(Some(self.span));
+        match &self.help {
+            Some(help) => diagnostic.with_help(help),
+            None => diagnostic
+        }
+    }
+}
+impl Error for ResolveError {}
+impl Display for ResolveError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{}: {}", self.span, self.msg)?;
+        Ok(())
+    }
+}
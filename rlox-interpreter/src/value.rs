@@ -0,0 +1,284 @@
+use crate::callable::{Callable, LoxFunction, NativeFunction};
+use crate::class::{LoxClass, LoxInstance};
+use crate::gc::{self, Node};
+use crate::map::LoxMap;
+use crate::InterpreterError;
+use rlox_contract::ExprLiteralValue;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::rc::Rc;
+
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Rc<str>),
+    Function(LoxFunction),
+    NativeFunction(NativeFunction),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<LoxMap>>),
+}
+
+impl Value {
+    pub fn nil() -> Value {
+        Value::Nil
+    }
+
+    pub fn string<B>(contents: B) -> Value where B : ToString {
+        Value::String(Rc::from(contents.to_string()))
+    }
+
+    /// The text of a string value.
+    pub fn string_contents(&self) -> Option<String> {
+        match self {
+            Value::String(s) => Some(s.to_string()),
+            _ => None
+        }
+    }
+
+    pub fn list(elements: Vec<Value>) -> Value {
+        let list = Rc::new(RefCell::new(elements));
+        gc::track(Node::List(list.clone()));
+        Value::List(list)
+    }
+
+    pub fn map(map: LoxMap) -> Value {
+        let map = Rc::new(RefCell::new(map));
+        gc::track(Node::Map(map.clone()));
+        Value::Map(map)
+    }
+
+    /// Checks that this value can index a list of `len` elements and returns it as a position.
+    pub fn as_index(&self, len: usize) -> Result<usize, InterpreterError> {
+        match self {
+            Value::Number(n) if n.fract() != 0.0 => Err(InterpreterError::new(format!("List index must be a whole number, got {}", n))),
+            Value::Number(n) if *n < 0.0 || *n >= len as f64 => {
+                Err(InterpreterError::new(format!("Index {} out of bounds for list of length {}", n, len)))
+            },
+            Value::Number(n) => Ok(*n as usize),
+            v => Err(InterpreterError::new(format!("List index must be a number, got {}", v.type_name())))
+        }
+    }
+
+    /// Like [`Value::as_index`], but also accepts `len` itself: the position just past the last
+    /// element, where a new one may be inserted or a slice may end.
+    pub fn as_position(&self, len: usize) -> Result<usize, InterpreterError> {
+        match self {
+            Value::Number(n) if *n == len as f64 => Ok(len),
+            v => v.as_index(len)
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn as_callable(&self) -> Option<&dyn Callable> {
+        match self {
+            Value::Function(f) => Some(f),
+            Value::NativeFunction(f) => Some(f),
+            Value::Class(c) => Some(c),
+            _ => None
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::NativeFunction(_) => "function",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::List(_) => "list",
+            Value::Map(_) => "map"
+        }
+    }
+}
+
+impl From<&ExprLiteralValue> for Value {
+    fn from(literal: &ExprLiteralValue) -> Value {
+        match literal {
+            ExprLiteralValue::NilLiteral => Value::Nil,
+            ExprLiteralValue::BooleanLiteral(b) => Value::Bool(*b),
+            ExprLiteralValue::NumberLiteral(n) => Value::Number(*n),
+            ExprLiteralValue::StringLiteral(s) => Value::String(Rc::from(s.as_str()))
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Value {
+        Value::Number(n)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Value {
+        Value::Number(n.into())
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::string(s)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::string(s)
+    }
+}
+
+impl From<NativeFunction> for Value {
+    fn from(f: NativeFunction) -> Value {
+        Value::NativeFunction(f)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(elements: Vec<Value>) -> Value {
+        Value::list(elements)
+    }
+}
+
+impl<T> From<Option<T>> for Value where T : Into<Value> {
+    fn from(v: Option<T>) -> Value {
+        v.map_or(Value::Nil, Into::into)
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = InterpreterError;
+
+    fn try_from(v: Value) -> Result<f64, InterpreterError> {
+        match v {
+            Value::Number(n) => Ok(n),
+            v => Err(InterpreterError::new(format!("Expected number, got {}", v.type_name())))
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = InterpreterError;
+
+    fn try_from(v: Value) -> Result<bool, InterpreterError> {
+        match v {
+            Value::Bool(b) => Ok(b),
+            v => Err(InterpreterError::new(format!("Expected bool, got {}", v.type_name())))
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = InterpreterError;
+
+    fn try_from(v: Value) -> Result<String, InterpreterError> {
+        v.string_contents().ok_or_else(|| InterpreterError::new(format!("Expected string, got {}", v.type_name())))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(l), Value::Bool(r)) => l == r,
+            (Value::Number(l), Value::Number(r)) => l == r,
+            (Value::String(l), Value::String(r)) => l == r,
+            (Value::Function(l), Value::Function(r)) => l == r,
+            (Value::NativeFunction(l), Value::NativeFunction(r)) => l == r,
+            (Value::Class(l), Value::Class(r)) => Rc::ptr_eq(l, r),
+            (Value::Instance(l), Value::Instance(r)) => Rc::ptr_eq(l, r),
+            (Value::List(l), Value::List(r)) => Rc::ptr_eq(l, r),
+            (Value::Map(l), Value::Map(r)) => Rc::ptr_eq(l, r),
+            _ => false
+        }
+    }
+}
+
+impl std::fmt::Debug for Value {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Value::Nil => write!(f, "Nil"),
+            Value::Bool(b) => f.debug_tuple("Bool").field(b).finish(),
+            Value::Number(n) => f.debug_tuple("Number").field(n).finish(),
+            Value::String(s) => f.debug_tuple("String").field(s).finish(),
+            Value::Function(fun) => f.debug_tuple("Function").field(fun).finish(),
+            Value::NativeFunction(fun) => f.debug_tuple("NativeFunction").field(fun).finish(),
+            Value::Class(c) => f.debug_tuple("Class").field(c).finish(),
+            Value::Instance(i) => f.debug_tuple("Instance").field(i).finish(),
+            // lists and maps may contain themselves, so they are shown the way they print
+            Value::List(_) => write!(f, "List({})", self),
+            Value::Map(_) => write!(f, "Map({})", self)
+        }
+    }
+}
+
+impl Display for Value {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{:.2}", n),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Function(fun) => write!(f, "<fn {}>", fun.name()),
+            Value::NativeFunction(fun) => write!(f, "<native fn {}>", fun.name()),
+            Value::Class(c) => write!(f, "{}", c.name()),
+            Value::Instance(i) => write!(f, "{} instance", i.borrow().class_name()),
+            Value::List(_) | Value::Map(_) => write_nested(f, self, &mut Vec::new())
+        }
+    }
+}
+
+/// Writes `value`, printing lists and maps that contain themselves as `[...]` and `{...}`.
+fn write_nested(f: &mut std::fmt::Formatter<'_>, value: &Value, seen: &mut Vec<*const ()>) -> std::fmt::Result {
+    let ptr = match value {
+        Value::List(l) => Rc::as_ptr(l) as *const (),
+        Value::Map(m) => Rc::as_ptr(m) as *const (),
+        v => return write!(f, "{}", v)
+    };
+    if seen.contains(&ptr) {
+        return write!(f, "{}", if let Value::List(_) = value { "[...]" } else { "{...}" });
+    }
+    seen.push(ptr);
+    match value {
+        Value::List(l) => {
+            write!(f, "[")?;
+            for (i, v) in l.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_nested(f, v, seen)?;
+            }
+            write!(f, "]")?;
+        },
+        Value::Map(m) => {
+            write!(f, "{{")?;
+            for (i, (k, v)) in m.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: ", k.to_value())?;
+                write_nested(f, v, seen)?;
+            }
+            write!(f, "}}")?;
+        },
+        _ => ()
+    }
+    seen.pop();
+    Ok(())
+}
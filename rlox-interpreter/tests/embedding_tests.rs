@@ -0,0 +1,115 @@
+extern crate rlox_interpreter;
+
+
+#[cfg(test)]
+mod embedding_tests {
+    use rlox_interpreter::{Interpreter, NativeFunction, Value};
+    use std::convert::TryFrom;
+    use std::path::PathBuf;
+
+    /// A fresh directory holding `files`, for tests that import modules.
+    fn module_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rlox-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, source) in files {
+            std::fs::write(dir.join(file), source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn it_evaluates_source_into_values() {
+        let mut interpreter = Interpreter::default();
+        assert_eq!(Value::from(7.0), interpreter.eval("var a = 3; a + 4;").unwrap());
+        assert_eq!(Value::Nil, interpreter.eval("var b = 1;").unwrap());
+        assert_eq!("hi there", String::try_from(interpreter.eval("\"hi\" + \" there\";").unwrap()).unwrap());
+        assert_eq!(Value::Nil, interpreter.eval("").unwrap());
+    }
+
+    #[test]
+    fn it_returns_errors_as_diagnostics() {
+        let mut interpreter = Interpreter::default();
+        assert_eq!("P0003", interpreter.eval("1 +").unwrap_err()[0].code());
+        assert_eq!("R0002", interpreter.eval("return 1;").unwrap_err()[0].code());
+        let runtime = interpreter.eval("-\"x\";").unwrap_err();
+        assert_eq!("E0001", runtime[0].code());
+        assert_eq!("Expected number, got string", runtime[0].message());
+    }
+
+    #[test]
+    fn it_calls_lox_functions_with_rust_values() {
+        let mut interpreter = Interpreter::default();
+        interpreter.define_global("greeting", "Hello").unwrap();
+        interpreter.eval("fun greet(name, times) { return greeting + \" \" + name + \" x\" + str(times); }").unwrap();
+
+        let result = interpreter.call_function("greet", vec!["Ada".into(), 2.into()]).unwrap();
+        assert_eq!("Hello Ada x2.00", String::try_from(result).unwrap());
+        assert!(interpreter.call_function("greet", vec![]).is_err());
+        assert!(interpreter.call_function("missing", vec![]).is_err());
+    }
+
+    #[test]
+    fn it_calls_rust_closures_from_lox() {
+        let mut interpreter = Interpreter::default();
+        let add = NativeFunction::new("add", 2, |_, args| {
+            let a = f64::try_from(args[0].clone())?;
+            let b = f64::try_from(args[1].clone())?;
+            Ok(Value::from(a + b))
+        });
+        interpreter.define_native(add).unwrap();
+        assert_eq!(Value::from(5.0), interpreter.eval("add(2, 3);").unwrap());
+        assert_eq!("Expected number, got bool", interpreter.eval("add(true, 3);").unwrap_err()[0].message());
+        assert_eq!(Some(true), interpreter.global("add").map(|v| v.type_name() == "function"));
+    }
+
+    #[test]
+    fn it_converts_between_rust_and_lox_values() {
+        assert_eq!(Value::Nil, Value::from(None::<f64>));
+        assert_eq!(Value::Bool(true), Value::from(Some(true)));
+        assert!(bool::try_from(Value::Nil).is_err());
+        assert_eq!(Ok(1.5), f64::try_from(Value::from(1.5)).map_err(|e| e.to_string()));
+    }
+
+    #[test]
+    fn it_runs_each_imported_module_once_in_its_own_globals() {
+        let dir = module_dir("imports", &[
+            ("counter.lox", "var count = 0; fun bump() { count = count + 1; return count; }"),
+            ("main.lox", "import { bump } from \"counter.lox\";")
+        ]);
+        let mut interpreter = Interpreter::default();
+        interpreter.set_script_path(dir.join("main.lox"));
+        interpreter.eval("var count = 10; import { bump } from \"counter.lox\";").unwrap();
+        assert_eq!(Value::from(2.0), interpreter.eval("bump(); bump();").unwrap());
+        assert_eq!(Some(Value::from(10.0)), interpreter.global("count"));
+
+        // a whole import would replace the importer's own count
+        assert_eq!("Can't import count from counter.lox: there is already a global named count",
+            interpreter.eval("import \"counter.lox\";").unwrap_err()[0].message());
+        assert_eq!(Some(Value::from(10.0)), interpreter.global("count"));
+        // importing bump again is fine, and the module isn't run again
+        assert_eq!(Value::from(3.0), interpreter.eval("import { bump } from \"counter.lox\"; bump();").unwrap());
+        interpreter.eval("fun bump() { return 0; }").unwrap();
+        assert!(interpreter.eval("import { bump } from \"counter.lox\";").is_err());
+        assert_eq!("Module counter.lox has no global named missing",
+            interpreter.eval("import { missing } from \"counter.lox\";").unwrap_err()[0].message());
+    }
+
+    #[test]
+    fn it_reports_import_cycles_and_errors_inside_modules() {
+        let dir = module_dir("cycles", &[
+            ("a.lox", "import \"b.lox\";"),
+            ("b.lox", "import \"a.lox\";"),
+            ("broken.lox", "var x = 1;\nx();")
+        ]);
+        let mut interpreter = Interpreter::default();
+        interpreter.set_script_path(dir.join("a.lox"));
+
+        let cycle = &interpreter.eval("import \"b.lox\";").unwrap_err()[0];
+        assert!(cycle.message().starts_with("Failed to import"));
+        assert!(cycle.notes()[0].contains("Import cycle"), "{:?}", cycle.notes());
+
+        let broken = &interpreter.eval("import \"broken.lox\";").unwrap_err()[0];
+        assert!(broken.notes()[0].contains("broken.lox:2:1"), "{:?}", broken.notes());
+        assert!(interpreter.eval("import \"missing.lox\";").unwrap_err()[0].message().starts_with("Can't import missing.lox"));
+    }
+}
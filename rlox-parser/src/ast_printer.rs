@@ -1,115 +1,228 @@
 extern crate rlox_contract;
-use rlox_contract::{Expr, ExprLiteralValue};
+use rlox_contract::{Expr, ExprLiteralValue, Stmt};
 
-pub fn print(expr: &Expr) -> String {
-    let mut expr_stack = vec![];
+pub fn print(stmt: &Stmt) -> String {
+    print_tree(PrinterIntermediateResult::SubStmt(stmt))
+}
+
+pub fn print_expr(expr: &Expr) -> String {
+    print_tree(PrinterIntermediateResult::SubExpr(expr))
+}
+
+fn print_tree(root: PrinterIntermediateResult) -> String {
+    let mut expr_stack = vec![root];
 
-    expr_stack.push(PrinterIntermediateResult::SubExpr(expr));
     let mut fin_stack = vec![];
     while let Some(ir) = expr_stack.pop() {
         match ir {
             
             PrinterIntermediateResult::SubExpr(e) => {
                 match e {
-                    Expr::BinaryExpr { left, operator, right } => {
+                    Expr::BinaryExpr { left, operator, right, span: _ } => {
                         expr_stack.push(PrinterIntermediateResult::SubExpr(left));
                         expr_stack.push(PrinterIntermediateResult::PrintAction(format!(" {} ", operator)));
                         expr_stack.push(PrinterIntermediateResult::SubExpr(right));
                     },
-                    Expr::GroupingExpr(b) => {
+                    Expr::GroupingExpr(b, _) => {
                         expr_stack.push(PrinterIntermediateResult::PrintAction(" ( group ".to_string()));
                         expr_stack.push(PrinterIntermediateResult::SubExpr(b));
                         
                         expr_stack.push(PrinterIntermediateResult::PrintAction(" ) ".to_string()));
                     }, 
-                    Expr::UnaryExpr { operator, right } => {
+                    Expr::UnaryExpr { operator, right, span: _ } => {
                         expr_stack.push(PrinterIntermediateResult::PrintAction(format!("( {} ", operator)));
                         expr_stack.push(PrinterIntermediateResult::SubExpr(right));
                         expr_stack.push(PrinterIntermediateResult::PrintAction(" ) ".to_string()));
                         
                     },
-                    Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s)) => {
-                        expr_stack.push(PrinterIntermediateResult::PrintAction(s.clone().replace('"', "")));
+                    Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s), _) => {
+                        expr_stack.push(PrinterIntermediateResult::PrintAction(s.clone()));
                     },
-                    Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n)) => {
+                    Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(n), _) => {
                         expr_stack.push(PrinterIntermediateResult::PrintAction(format!("{:.2}", n)));
                     },
-                    Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(true)) => { expr_stack.push(PrinterIntermediateResult::PrintAction("true".to_string()))},
-                    Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(false)) => { expr_stack.push(PrinterIntermediateResult::PrintAction("false".to_string()))},
-                    Expr::LiteralExpr(ExprLiteralValue::NilLiteral) => { expr_stack.push(PrinterIntermediateResult::PrintAction("nil".to_string()))},
-                    Expr::PrintStmt(inner) => {
-                        expr_stack.push(PrinterIntermediateResult::PrintAction(format!(" PRINT ")));
+                    Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(true), _) => { expr_stack.push(PrinterIntermediateResult::PrintAction("true".to_string()))},
+                    Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(false), _) => { expr_stack.push(PrinterIntermediateResult::PrintAction("false".to_string()))},
+                    Expr::LiteralExpr(ExprLiteralValue::NilLiteral, _) => { expr_stack.push(PrinterIntermediateResult::PrintAction("nil".to_string()))},
+                    Expr::VariableExpr { name: identifier, depth: _, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::PrintAction(format!(" {}", identifier)));
+                    },
+                    Expr::AssigmentExpr {name, value, depth: _, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::PrintAction(format!(" {}", name)));
+                        expr_stack.push(PrinterIntermediateResult::SubExpr(value));
+                    },
+                    Expr::LogicalExpr { left, operator, right, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::SubExpr(left));
+                        expr_stack.push(PrinterIntermediateResult::PrintAction(format!(" {:?} ", operator)));
+                        expr_stack.push(PrinterIntermediateResult::SubExpr(right));
+                    },
+                    Expr::CallExpr { callee, paren:_, arguments, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("CALL ".to_string()));
+                        expr_stack.push(PrinterIntermediateResult::SubExpr(callee));
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("(\n".to_string()));
+                        for a in arguments {
+                            expr_stack.push(PrinterIntermediateResult::SubExpr(a));
+                            expr_stack.push(PrinterIntermediateResult::PrintAction(",".to_string()));
+                        }
+                        expr_stack.push(PrinterIntermediateResult::PrintAction(");\n".to_string()));
+                    },
+                    Expr::GetExpr { object, name, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::SubExpr(object));
+                        expr_stack.push(PrinterIntermediateResult::PrintAction(format!(".{}", name)));
+                    },
+                    Expr::SetExpr { object, name, value, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::SubExpr(object));
+                        expr_stack.push(PrinterIntermediateResult::PrintAction(format!(".{} = ", name)));
+                        expr_stack.push(PrinterIntermediateResult::SubExpr(value));
+                    },
+                    Expr::ThisExpr { keyword: _, depth: _, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("this".to_string()));
+                    },
+                    Expr::SuperExpr { keyword: _, method, depth: _, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::PrintAction(format!("super.{}", method)));
+                    },
+                    Expr::ListExpr { elements, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("[".to_string()));
+                        for (i, e) in elements.iter().enumerate() {
+                            if i > 0 {
+                                expr_stack.push(PrinterIntermediateResult::PrintAction(", ".to_string()));
+                            }
+                            expr_stack.push(PrinterIntermediateResult::SubExpr(e));
+                        }
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("]".to_string()));
+                    },
+                    Expr::MapExpr { entries, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("{".to_string()));
+                        for (i, (k, v)) in entries.iter().enumerate() {
+                            if i > 0 {
+                                expr_stack.push(PrinterIntermediateResult::PrintAction(", ".to_string()));
+                            }
+                            expr_stack.push(PrinterIntermediateResult::SubExpr(k));
+                            expr_stack.push(PrinterIntermediateResult::PrintAction(": ".to_string()));
+                            expr_stack.push(PrinterIntermediateResult::SubExpr(v));
+                        }
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("}".to_string()));
+                    },
+                    Expr::InterpolationExpr { parts, span: _ } => {
+                        for p in parts {
+                            if let Expr::LiteralExpr(ExprLiteralValue::StringLiteral(_), _) = p.as_ref() {
+                                expr_stack.push(PrinterIntermediateResult::SubExpr(p));
+                            } else {
+                                expr_stack.push(PrinterIntermediateResult::PrintAction("${".to_string()));
+                                expr_stack.push(PrinterIntermediateResult::SubExpr(p));
+                                expr_stack.push(PrinterIntermediateResult::PrintAction("}".to_string()));
+                            }
+                        }
+                    },
+                    Expr::IndexExpr { object, index, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::SubExpr(object));
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("[".to_string()));
+                        expr_stack.push(PrinterIntermediateResult::SubExpr(index));
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("]".to_string()));
+                    },
+                    Expr::IndexSetExpr { object, index, value, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::SubExpr(object));
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("[".to_string()));
+                        expr_stack.push(PrinterIntermediateResult::SubExpr(index));
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("] = ".to_string()));
+                        expr_stack.push(PrinterIntermediateResult::SubExpr(value));
+                    }
+                }
+                
+            },
+            PrinterIntermediateResult::SubStmt(st) => {
+                match st {
+                    Stmt::PrintStmt(inner, _) => {
+                        expr_stack.push(PrinterIntermediateResult::PrintAction(" PRINT ".to_string()));
                         expr_stack.push(PrinterIntermediateResult::SubExpr(inner));
 
                     },
-                    Expr::ExprStmt(inner) => {
-                        expr_stack.push(PrinterIntermediateResult::PrintAction(format!(" EXPRSTMT ")));
+                    Stmt::ExprStmt(inner, _) => {
                         expr_stack.push(PrinterIntermediateResult::SubExpr(inner));
                     },
-                    Expr::VarDecl { name, initializer } => {
+                    Stmt::VarDecl { name, initializer, span: _ } => {
                         expr_stack.push(PrinterIntermediateResult::PrintAction(format!(" var {} = ", name)));
                         expr_stack.push(PrinterIntermediateResult::SubExpr(initializer));
                     },
-                    Expr::VariableExpr(identifier) => {
-                        expr_stack.push(PrinterIntermediateResult::PrintAction(format!(" {}", identifier)));
-                    },
-                    Expr::AssigmentExpr {name, value} => {
-                        expr_stack.push(PrinterIntermediateResult::PrintAction(format!(" {}", name)));
-                        expr_stack.push(PrinterIntermediateResult::SubExpr(value));
-                    },
-                    Expr::BlockStmt(stmts) => {
+                    Stmt::BlockStmt(stmts, _) => {
                         expr_stack.push(PrinterIntermediateResult::PrintAction("{".to_string()));
                         for s in stmts {
-                            expr_stack.push(PrinterIntermediateResult::SubExpr(s));
+                            expr_stack.push(PrinterIntermediateResult::SubStmt(s));
                         }
                         expr_stack.push(PrinterIntermediateResult::PrintAction("}".to_string()));
                     },
-                    Expr::IfStmt { condition, then_branch, else_branch } => {
+                    Stmt::IfStmt { condition, then_branch, else_branch, span: _ } => {
                         expr_stack.push(PrinterIntermediateResult::PrintAction("if ".to_string()));
                         expr_stack.push(PrinterIntermediateResult::SubExpr(condition));
                         expr_stack.push(PrinterIntermediateResult::PrintAction(" {".to_string()));
-                        expr_stack.push(PrinterIntermediateResult::SubExpr(then_branch));
-                        expr_stack.push(PrinterIntermediateResult::PrintAction(" } else {".to_string()));
-                        expr_stack.push(PrinterIntermediateResult::SubExpr(else_branch));
+                        expr_stack.push(PrinterIntermediateResult::SubStmt(then_branch));
+                        if let Some(else_branch) = else_branch {
+                            expr_stack.push(PrinterIntermediateResult::PrintAction(" } else {".to_string()));
+                            expr_stack.push(PrinterIntermediateResult::SubStmt(else_branch));
+                        }
                         expr_stack.push(PrinterIntermediateResult::PrintAction(" }".to_string()));
                     },
-                    Expr::LogicalExpr { left: _, operator: _, right: _} => unimplemented!(),
-                    Expr::WhileLoop { condition: _, body: _ } => unimplemented!(),
-                    Expr::CallExpr { callee, paren:_, arguments } => {
-                        expr_stack.push(PrinterIntermediateResult::PrintAction("CALL ".to_string()));
-                        expr_stack.push(PrinterIntermediateResult::SubExpr(callee));
-                        expr_stack.push(PrinterIntermediateResult::PrintAction("(\n".to_string()));
-                        for a in arguments {
-                            expr_stack.push(PrinterIntermediateResult::SubExpr(a));
-                            expr_stack.push(PrinterIntermediateResult::PrintAction(",".to_string()));
+                    Stmt::WhileLoop { condition, body, increment, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("while ".to_string()));
+                        expr_stack.push(PrinterIntermediateResult::SubExpr(condition));
+                        if let Some(increment) = increment {
+                            expr_stack.push(PrinterIntermediateResult::PrintAction(" then".to_string()));
+                            expr_stack.push(PrinterIntermediateResult::SubExpr(increment));
                         }
-                        expr_stack.push(PrinterIntermediateResult::PrintAction(");\n".to_string()));
+                        expr_stack.push(PrinterIntermediateResult::PrintAction(" {".to_string()));
+                        expr_stack.push(PrinterIntermediateResult::SubStmt(body));
+                        expr_stack.push(PrinterIntermediateResult::PrintAction(" }".to_string()));
+                    },
+                    Stmt::Break(_) => expr_stack.push(PrinterIntermediateResult::PrintAction("break;\n".to_string())),
+                    Stmt::Continue(_) => expr_stack.push(PrinterIntermediateResult::PrintAction("continue;\n".to_string())),
+                    Stmt::Import { path, names, span: _ } => {
+                        let names = match names {
+                            Some(names) => format!("{{{}}} FROM ", names.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", ")),
+                            None => String::new()
+                        };
+                        expr_stack.push(PrinterIntermediateResult::PrintAction(format!("IMPORT {}\"{}\";\n", names, path)));
                     },
-                    Expr::FunctionExpr { name, params, body } => {
+                    Stmt::FunctionDecl { name, params, body, span: _ } => {
                         expr_stack.push(PrinterIntermediateResult::PrintAction(format!("FUN {:?}(", name)));
-                        for p in params {
+                        for (p, _) in params {
                             expr_stack.push(PrinterIntermediateResult::PrintAction(format!("{:?}", p)));
 
                         }
                         expr_stack.push(PrinterIntermediateResult::PrintAction(")\n".to_string()));
                         expr_stack.push(PrinterIntermediateResult::PrintAction("{\n".to_string()));
-                        expr_stack.push(PrinterIntermediateResult::SubExpr(body));
+                        for s in body {
+                            expr_stack.push(PrinterIntermediateResult::SubStmt(s));
+                        }
                         expr_stack.push(PrinterIntermediateResult::PrintAction("}\n".to_string()));
                     },
-                    Expr::Return(_, inner) => {
+                    Stmt::Return(_, inner, _) => {
                         expr_stack.push(PrinterIntermediateResult::PrintAction("return ".to_string()));
-                        expr_stack.push(PrinterIntermediateResult::SubExpr(inner));
+                        if let Some(inner) = inner {
+                            expr_stack.push(PrinterIntermediateResult::SubExpr(inner));
+                        }
                         expr_stack.push(PrinterIntermediateResult::PrintAction(";\n".to_string()));
+                    },
+                    Stmt::ClassDecl { name, superclass, methods, span: _ } => {
+                        expr_stack.push(PrinterIntermediateResult::PrintAction(format!("CLASS {} ", name)));
+                        if let Some(s) = superclass {
+                            expr_stack.push(PrinterIntermediateResult::PrintAction("<".to_string()));
+                            expr_stack.push(PrinterIntermediateResult::SubExpr(s));
+                            expr_stack.push(PrinterIntermediateResult::PrintAction(" ".to_string()));
+                        }
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("{\n".to_string()));
+                        for m in methods {
+                            expr_stack.push(PrinterIntermediateResult::SubStmt(m));
+                        }
+                        expr_stack.push(PrinterIntermediateResult::PrintAction("}\n".to_string()));
                     }
                 }
-                
             },
             PrinterIntermediateResult::PrintAction(s) => fin_stack.push(s)
         }
     }
     let mut result = String::new();
     for s in fin_stack.iter().rev() {
-        result.push_str(&s);
+        result.push_str(s);
     }
 
     result
@@ -118,6 +231,7 @@ pub fn print(expr: &Expr) -> String {
 #[derive(Debug)]
 enum PrinterIntermediateResult<'a> {
     PrintAction(String),
-    SubExpr(&'a Expr)
+    SubExpr(&'a Expr),
+    SubStmt(&'a Stmt)
 }
 
@@ -0,0 +1,266 @@
+use rlox_contract::{Expr, ExprLiteralValue, LiteralTokenType, Stmt, Token};
+
+const INDENT: &str = "    ";
+
+/// Turns parsed statements back into Lox source, laid out with one statement per line, four
+/// spaces of indentation and a blank line around top-level functions and classes.
+///
+/// Comments are not part of the syntax tree, so they are lost, and `for` loops are recognised
+/// by the shape the parser desugars them into.
+pub fn format(stmts: &[Stmt]) -> String {
+    let mut out = String::new();
+    for (i, s) in stmts.iter().enumerate() {
+        if i > 0 && (is_declaration(s) || is_declaration(&stmts[i - 1])) {
+            out.push('\n');
+        }
+        out.push_str(&stmt(s, 0));
+        out.push('\n');
+    }
+    out
+}
+
+fn is_declaration(stmt: &Stmt) -> bool {
+    matches!(stmt, Stmt::FunctionDecl { .. } | Stmt::ClassDecl { .. })
+}
+
+/// `stmt` at nesting depth `level`, without indentation before its first line or a newline
+/// after its last.
+fn stmt(stmt: &Stmt, level: usize) -> String {
+    match stmt {
+        Stmt::PrintStmt(e, _) => format!("print {};", expr(e)),
+        Stmt::ExprStmt(e, _) => format!("{};", expr(e)),
+        Stmt::VarDecl { name, initializer, span: _ } => match initializer.as_ref() {
+            Expr::LiteralExpr(ExprLiteralValue::NilLiteral, _) => format!("var {};", identifier(name)),
+            e => format!("var {} = {};", identifier(name), expr(e))
+        },
+        Stmt::BlockStmt(stmts, span) => match stmts.as_slice() {
+            [init @ (Stmt::VarDecl { .. } | Stmt::ExprStmt(..)), Stmt::WhileLoop { condition, body, increment: Some(increment), span: loop_span }]
+                if loop_span == span => {
+                for_loop(Some(init), condition, increment, body, level)
+            },
+            _ => block(stmts, level)
+        },
+        Stmt::IfStmt { condition, then_branch, else_branch, span: _ } => {
+            let mut s = format!("if ({}) {}", expr(condition), self::stmt(then_branch, level));
+            if let Some(else_branch) = else_branch {
+                s.push_str(" else ");
+                s.push_str(&self::stmt(else_branch, level));
+            }
+            s
+        },
+        Stmt::WhileLoop { condition, body, increment: Some(increment), span: _ } => for_loop(None, condition, increment, body, level),
+        Stmt::WhileLoop { condition, body, increment: None, span: _ } => format!("while ({}) {}", expr(condition), self::stmt(body, level)),
+        Stmt::FunctionDecl { .. } => format!("fun {}", function(stmt, level)),
+        Stmt::Return(_, None, _) => "return;".to_string(),
+        Stmt::Return(_, Some(value), _) => format!("return {};", expr(value)),
+        Stmt::ClassDecl { name, superclass, methods, span: _ } => {
+            let mut s = format!("class {}", identifier(name));
+            if let Some(superclass) = superclass {
+                s.push_str(&format!(" < {}", expr(superclass)));
+            }
+            if methods.is_empty() {
+                return s + " {}";
+            }
+            s.push_str(" {\n");
+            for (i, m) in methods.iter().enumerate() {
+                if i > 0 {
+                    s.push('\n');
+                }
+                s.push_str(&indent(level + 1));
+                s.push_str(&function(m, level + 1));
+                s.push('\n');
+            }
+            s + &indent(level) + "}"
+        },
+        Stmt::Break(_) => "break;".to_string(),
+        Stmt::Continue(_) => "continue;".to_string(),
+        Stmt::Import { path, names: None, span: _ } => format!("import {};", string(path)),
+        Stmt::Import { path, names: Some(names), span: _ } => {
+            let names: Vec<String> = names.iter().map(identifier).collect();
+            format!("import {{ {} }} from {};", names.join(", "), string(path))
+        }
+    }
+}
+
+fn block(stmts: &[Stmt], level: usize) -> String {
+    if stmts.is_empty() {
+        return "{}".to_string();
+    }
+    let mut s = "{\n".to_string();
+    for inner in stmts {
+        s.push_str(&indent(level + 1));
+        s.push_str(&stmt(inner, level + 1));
+        s.push('\n');
+    }
+    s + &indent(level) + "}"
+}
+
+fn for_loop(initializer: Option<&Stmt>, condition: &Expr, increment: &Expr, body: &Stmt, level: usize) -> String {
+    let initializer = initializer.map(|i| stmt(i, level)).unwrap_or_else(|| ";".to_string());
+    format!("for ({} {}; {}) {}", initializer, expr(condition), expr(increment), stmt(body, level))
+}
+
+/// A function or method declaration without the `fun` keyword.
+fn function(function: &Stmt, level: usize) -> String {
+    match function {
+        Stmt::FunctionDecl { name, params, body, span: _ } => {
+            let params: Vec<String> = params.iter().map(|(p, _)| identifier(p)).collect();
+            format!("{}({}) {}", identifier(name), params.join(", "), block(body, level))
+        },
+        other => stmt(other, level)
+    }
+}
+
+fn expr(expr: &Expr) -> String {
+    match expr {
+        Expr::BinaryExpr { left, operator, right, span: _ } | Expr::LogicalExpr { left, operator, right, span: _ } => {
+            format!("{} {} {}", self::expr(left), operator_symbol(operator), self::expr(right))
+        },
+        Expr::GroupingExpr(inner, _) => format!("({})", self::expr(inner)),
+        Expr::LiteralExpr(value, _) => match value {
+            ExprLiteralValue::StringLiteral(s) => string(s),
+            ExprLiteralValue::NumberLiteral(n) => n.to_string(),
+            ExprLiteralValue::BooleanLiteral(b) => b.to_string(),
+            ExprLiteralValue::NilLiteral => "nil".to_string()
+        },
+        Expr::UnaryExpr { operator, right, span: _ } => format!("{}{}", operator_symbol(operator), self::expr(right)),
+        Expr::VariableExpr { name, .. } => identifier(name),
+        Expr::AssigmentExpr { name, value, .. } => format!("{} = {}", identifier(name), self::expr(value)),
+        Expr::CallExpr { callee, paren: _, arguments, span: _ } => format!("{}({})", self::expr(callee), list(arguments)),
+        Expr::GetExpr { object, name, span: _ } => format!("{}.{}", self::expr(object), identifier(name)),
+        Expr::SetExpr { object, name, value, span: _ } => format!("{}.{} = {}", self::expr(object), identifier(name), self::expr(value)),
+        Expr::ThisExpr { .. } => "this".to_string(),
+        Expr::SuperExpr { method, .. } => format!("super.{}", identifier(method)),
+        Expr::ListExpr { elements, span: _ } => format!("[{}]", list(elements)),
+        Expr::MapExpr { entries, span: _ } => {
+            let entries: Vec<String> = entries.iter().map(|(k, v)| format!("{}: {}", self::expr(k), self::expr(v))).collect();
+            format!("{{{}}}", entries.join(", "))
+        },
+        Expr::InterpolationExpr { parts, span: _ } => {
+            let mut s = "\"".to_string();
+            for p in parts {
+                match p.as_ref() {
+                    Expr::LiteralExpr(ExprLiteralValue::StringLiteral(text), _) => s.push_str(&escape(text)),
+                    e => s.push_str(&format!("${{{}}}", self::expr(e)))
+                }
+            }
+            s + "\""
+        },
+        Expr::IndexExpr { object, index, span: _ } => format!("{}[{}]", self::expr(object), self::expr(index)),
+        Expr::IndexSetExpr { object, index, value, span: _ } => {
+            format!("{}[{}] = {}", self::expr(object), self::expr(index), self::expr(value))
+        }
+    }
+}
+
+fn list(exprs: &[Box<Expr>]) -> String {
+    exprs.iter().map(|e| expr(e)).collect::<Vec<_>>().join(", ")
+}
+
+fn string(s: &str) -> String {
+    format!("\"{}\"", escape(s))
+}
+
+/// `s` with everything the scanner would not read back as itself escaped.
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '$' if chars.peek() == Some(&'{') => escaped.push_str("\\$"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+fn identifier(token: &Token) -> String {
+    match token {
+        Token::Literal(LiteralTokenType::IdentifierLiteral(s)) => s.clone(),
+        other => format!("{:?}", other)
+    }
+}
+
+fn operator_symbol(operator: &Token) -> &'static str {
+    match operator {
+        Token::Minus => "-",
+        Token::Plus => "+",
+        Token::Star => "*",
+        Token::Slash => "/",
+        Token::Bang => "!",
+        Token::BangEqual => "!=",
+        Token::EqualEqual => "==",
+        Token::Greater => ">",
+        Token::GreaterEqual => ">=",
+        Token::Less => "<",
+        Token::LessEqual => "<=",
+        Token::And => "and",
+        Token::Or => "or",
+        _ => "?"
+    }
+}
+
+fn indent(level: usize) -> String {
+    INDENT.repeat(level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+    use crate::Parser;
+    use rlox_scanner::Scanner;
+
+    fn reformat(source: &str) -> String {
+        let mut parser = Parser::new();
+        parser.add_tokens(*Scanner::new().scan(source).expect("failed to scan"));
+        format(&parser.parse().expect("failed to parse"))
+    }
+
+    #[test]
+    fn it_lays_out_statements_and_declarations() {
+        let source = "var a=1;var b;fun add(x,y){return x+y;}class B<A{init(){this.x=super.get();}go(){return;}}
+            for(var i=0;i<3;i=i+1)if(i==1)continue;else print i;while(!(a>=2)){a=a+1;}{}";
+        let expected = "\
+var a = 1;
+var b;
+
+fun add(x, y) {
+    return x + y;
+}
+
+class B < A {
+    init() {
+        this.x = super.get();
+    }
+
+    go() {
+        return;
+    }
+}
+
+for (var i = 0; i < 3; i = i + 1) if (i == 1) continue; else print i;
+while (!(a >= 2)) {
+    a = a + 1;
+}
+{}
+";
+        assert_eq!(expected, reformat(source));
+    }
+
+    #[test]
+    fn it_prints_literals_so_they_scan_back_the_same() {
+        let source = "print [1.5, \"a\\\"b\\n\", {nil: true}][0]; xs[1]=\"\\${x} ${x+1}!\"; import {a,b} from \"lib.lox\"; import \"c.lox\";";
+        let formatted = reformat(source);
+        assert_eq!("print [1.5, \"a\\\"b\\n\", {nil: true}][0];
+xs[1] = \"\\${x} ${x + 1}!\";
+import { a, b } from \"lib.lox\";
+import \"c.lox\";
+", formatted);
+        assert_eq!(formatted, reformat(&formatted));
+    }
+}
@@ -1,69 +1,87 @@
 extern crate rlox_contract;
 extern crate log;
-use log::{debug, error, trace};
+use log::{debug, trace};
 use std::error::Error;
 use std::fmt::Display;
 use std::collections::VecDeque;
-use rlox_contract::{Expr,ExprLiteralValue, TokenContext, Token, LiteralTokenType};
+use rlox_contract::{Expr, ExprLiteralValue, Span, Stmt, TokenContext, Token, LiteralTokenType};
+use rlox_contract::diagnostics::Diagnostic;
 pub mod ast_printer;
+pub mod formatter;
 
 pub type Result<B> = std::result::Result<B, ParseError>;
 
 pub struct Parser {
     tokens: VecDeque<TokenContext>,
-    stack_depth: i32
+    previous: Span,
+    stack_depth: i32,
+    /// Every error found so far. Errors inside a block are recovered from where they happen, so
+    /// the block carries on with its next declaration.
+    errors: Vec<ParseError>
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Parser {
     pub fn new() -> Parser {
         let tokens = VecDeque::new();
-        Parser { tokens, stack_depth: 0 }
+        Parser { tokens, previous: Span::default(), stack_depth: 0, errors: Vec::new() }
     }
 
     pub fn add_tokens(&mut self, tokens: Vec<TokenContext>) {
         self.tokens.extend(tokens);
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Expr>> {
+    pub fn parse(&mut self) -> Result<Vec<Stmt>> {
+        let (stmts, mut errors) = self.parse_with_errors();
+        if !errors.is_empty() {
+            let mut first = errors.remove(0);
+            first.additional = errors.into_boxed_slice();
+            Err(first)
+        } else {
+            Ok(stmts)
+        }
+    }
 
+    /// Parses every declaration it can, synchronizing after each error, and returns the
+    /// statements it recovered alongside every error it found.
+    pub fn parse_with_errors(&mut self) -> (Vec<Stmt>, Vec<ParseError>) {
         let mut stmts = Vec::new();
-        let mut errors = Vec::new();
         while !self.eof() {
             match self.decl() {
                 Ok(smt) => { stmts.push(smt); },
-                Err(e) => {               
-                    errors.push(e);                   
+                Err(e) => {
+                    self.errors.push(e);
                 }
             }
-            
         }
         self.tokens.clear();
-        if errors.len() != 0 {
-            let mut msg = String::new();
-            for e in errors.iter() {
-                msg.push_str(&format!("{:?}\n", e.msg));
-            }
-
-            Err(ParseError::new(msg))
-        } else {
-    //        for s in stmts.iter() {
-//                // debug!("{:?}", s);
-  //          }
-            Ok(stmts)
-        }
+        (stmts, std::mem::take(&mut self.errors))
     }
 
-    fn decl(&mut self) -> Result<Expr> {
+    fn decl(&mut self) -> Result<Stmt> {
         self.add_stack("decl", 1);
         let parse_result = match self.peek().map(|e| e.token()) {
             Some(Token::Var) => self.var_decl(),
             Some(Token::Fun) => self.function(),
+            Some(Token::Class) => self.class_decl(),
+            Some(Token::Import) => self.import_decl(),
             _ => self.stmt()
         };
 
         self.add_stack("decl", -1);
-        if let Err(e) = parse_result {
-            error!("Parse Error {}", e.msg);
+        if let Err(mut e) = parse_result {
+            if e.span.is_none() {
+                e.span = Some(self.peek_span());
+            }
+            if e.found.is_none() {
+                e.found = self.peek().cloned().map(Box::new);
+            }
+            debug!("Parse Error {}", e);
             self.synchronize()?;
             Err(e)
         } else {
@@ -71,25 +89,77 @@ impl Parser {
         }
     }
 
-    fn function(&mut self) -> Result<Expr> {
-        self.consume(&Token::Fun)?;
-        let fn_name = match &self.peek().map(|e| e.token()) {
-            Some(Token::Literal(LiteralTokenType::IdentifierLiteral(s))) => Token::Literal(LiteralTokenType::IdentifierLiteral(s.clone())),
-            _ => return Err(ParseError::new("Expected identifier"))
+    fn import_decl(&mut self) -> Result<Stmt> {
+        self.add_stack("import_decl", 1);
+        let start = self.peek_span();
+        self.consume(&Token::Import)?;
+        let names = if self.token_match(&Token::LeftBrace) {
+            self.consume(&Token::LeftBrace)?;
+            let mut names = vec![self.identifier()?];
+            while self.token_match(&Token::Comma) {
+                self.consume(&Token::Comma)?;
+                names.push(self.identifier()?);
+            }
+            self.consume(&Token::RightBrace)?;
+            // `from` is only a keyword here, so it stays usable as a variable name
+            match self.peek().map(|e| e.token()) {
+                Some(Token::Literal(LiteralTokenType::IdentifierLiteral(s))) if s == "from" => { self.advance(); },
+                _ => return Err(ParseError::unexpected(self.peek(), vec![Expected::Keyword("from")]))
+            }
+            Some(names)
+        } else {
+            None
+        };
+        let path = match self.peek().map(|e| e.token()) {
+            Some(Token::Literal(LiteralTokenType::StringLiteral(s))) => {
+                let path = s.clone();
+                self.advance();
+                path
+            },
+            _ => return Err(ParseError::unexpected(self.peek(), vec![Expected::String]))
+        };
+        self.consume(&Token::Semicolon)?;
+        self.add_stack("import_decl", -1);
+        Ok(Stmt::Import { path, names, span: self.span_from(start) })
+    }
+
+    fn class_decl(&mut self) -> Result<Stmt> {
+        self.add_stack("class_decl", 1);
+        let start = self.peek_span();
+        self.consume(&Token::Class)?;
+        let name = self.identifier()?;
+        let superclass = if self.token_match(&Token::Less) {
+            self.consume(&Token::Less)?;
+            let superclass = self.identifier()?;
+            Some(Box::from(Expr::new_variable_expr(superclass, self.previous)))
+        } else {
+            None
         };
-        self.tokens.pop_front();
+        self.consume(&Token::LeftBrace)?;
+        let mut methods = Vec::new();
+        while !self.eof() && !self.token_match(&Token::RightBrace) {
+            let start = self.peek_span();
+            methods.push(self.function_body(start)?);
+        }
+        self.consume(&Token::RightBrace)?;
+        self.add_stack("class_decl", -1);
+        Ok(Stmt::ClassDecl { name, superclass, methods, span: self.span_from(start) })
+    }
+
+    fn function(&mut self) -> Result<Stmt> {
+        let start = self.peek_span();
+        self.consume(&Token::Fun)?;
+        self.function_body(start)
+    }
+
+    fn function_body(&mut self, start: Span) -> Result<Stmt> {
+        let fn_name = self.identifier()?;
         self.consume(&Token::LeftParen)?;
         let mut params = Vec::new();
-        loop {
-            if  Some(&Token::RightParen) != self.peek().map(|e| e.token()) {
-                let _ = match &self.peek().map(|e| e.token()) {
-                    Some(Token::Literal(LiteralTokenType::IdentifierLiteral(s))) => {
-                        params.push(Token::Literal(LiteralTokenType::IdentifierLiteral(s.clone())));
-                        self.tokens.pop_front();
-                    },
-                    _ => return Err(ParseError::new("Expected identifier"))
-                };
-
+        if !self.token_match(&Token::RightParen) {
+            loop {
+                let span = self.peek_span();
+                params.push((self.identifier()?, span));
                 if !self.token_match(&Token::Comma) {
                     break;
                 } else {
@@ -98,42 +168,43 @@ impl Parser {
             }
         }
         self.consume(&Token::RightParen)?;
-        let body = self.block()?;
-        Ok(Expr::FunctionExpr { name: fn_name, params, body: Box::from(body)})
-        
+        let body = self.block_stmts()?;
+        Ok(Stmt::FunctionDecl { name: fn_name, params, body, span: self.span_from(start) })
+    }
+
+    fn identifier(&mut self) -> Result<Token> {
+        match self.peek().map(|e| e.token()) {
+            Some(Token::Literal(LiteralTokenType::IdentifierLiteral(s))) => {
+                let ident = Token::from_identifier(s);
+                self.advance();
+                Ok(ident)
+            },
+            _ => Err(ParseError::unexpected(self.peek(), vec![Expected::Identifier]))
+        }
     }
 
-    fn var_decl(&mut self) -> Result<Expr> {
+    fn var_decl(&mut self) -> Result<Stmt> {
         self.add_stack("var_decl", 1);
+        let start = self.peek_span();
         self.consume(&Token::Var)?;
-        if let Some(tc) = self.tokens.pop_front() {
-            if let Token::Literal(LiteralTokenType::IdentifierLiteral(_)) = &tc.token() {
-                
-                if let Some(Token::Equal) = self.peek().map(|e| e.token()) {
-                    self.consume(&Token::Equal)?;
-                    let initializer = self.expression()?;
-                    self.consume(&Token::Semicolon)?;
-                    self.add_stack("var_decl", -1);
-                    Ok(Expr::VarDecl { name: tc.token().clone(), initializer: Box::from(initializer) })    
-                } else {
-                    self.consume(&Token::Semicolon)?;
-                    self.add_stack("var_decl", -1);
-                    Ok(Expr::VarDecl { name: tc.token().clone(), initializer: Box::from(Expr::LiteralExpr(ExprLiteralValue::NilLiteral))})
-                }
-
-            } else {
-                return Err(ParseError::new("Expected identifier"));
-            }
+        let name = self.identifier()?;
+        let name_span = self.previous;
+        let initializer = if let Some(Token::Equal) = self.peek().map(|e| e.token()) {
+            self.consume(&Token::Equal)?;
+            self.expression()?
         } else {
-            return Err(ParseError::new("Unexpected EOF"));
-        }
+            Expr::LiteralExpr(ExprLiteralValue::NilLiteral, name_span)
+        };
+        self.consume(&Token::Semicolon)?;
+        self.add_stack("var_decl", -1);
+        Ok(Stmt::VarDecl { name, initializer: Box::from(initializer), span: self.span_from(start) })
     }
 
-    fn stmt(&mut self) -> Result<Expr> {
+    fn stmt(&mut self) -> Result<Stmt> {
         self.add_stack("stmt", 1);
         if self.eof() {
-            error!("Unexpected EOF, expected [stmt]");
-            Err(ParseError::new(format!("Unexpected EOF, expected [stmt]"))) 
+            debug!("Unexpected EOF, expected [stmt]");
+            Err(ParseError::unexpected(self.peek(), vec![Expected::Statement]))
         } else if let Some(tc) = self.peek() {
             let r = match tc.token() {
                 Token::Print => self.print_stmt(),
@@ -142,172 +213,154 @@ impl Parser {
                 Token::While => self.while_loop(),
                 Token::For => self.for_loop(),
                 Token::Return => self.return_stmt(),
+                Token::Break | Token::Continue => self.loop_control(),
                 _ => self.expression_stmt()
             }?;
             self.add_stack("stmt", -1);
             Ok(r)
         } else {
-            error!("Unexpected EOF, expected [stmt]");
-            Err(ParseError::new(format!("Unexpected EOF, expected [stmt]"))) 
+            debug!("Unexpected EOF, expected [stmt]");
+            Err(ParseError::unexpected(self.peek(), vec![Expected::Statement]))
         }
     }
 
-    fn for_loop(&mut self) -> Result<Expr> {
+    fn for_loop(&mut self) -> Result<Stmt> {
         self.add_stack("for_loop", 1);
+        let start = self.peek_span();
         self.consume(&Token::For)?;
         self.consume(&Token::LeftParen)?;
         let initializer = match self.peek().map(|e| e.token()) {
             Some(Token::Semicolon) => {
                 self.consume(&Token::Semicolon)?;
-                Expr::LiteralExpr(ExprLiteralValue::NilLiteral)
-            },
-            Some(Token::Var) => {
-                let ini = self.var_decl()?;
-                ini
+                None
             },
-            _ => {
-                let ini = self.expression_stmt()?;
-                ini
-            }
+            Some(Token::Var) => Some(self.var_decl()?),
+            _ => Some(self.expression_stmt()?)
         };
 
-        let mut condition = match self.peek().map(|e| e.token()) {
-            Some(Token::Semicolon) => {
-                self.consume(&Token::Semicolon)?;
-                Expr::LiteralExpr(ExprLiteralValue::NilLiteral)
-            },
-            _ => {
-
-                let e = self.expression_stmt()?;
-                e
-            }
+        let condition = match self.peek().map(|e| e.token()) {
+            Some(Token::Semicolon) => Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(true), self.peek_span()),
+            _ => self.expression()?
         };
-
+        self.consume(&Token::Semicolon)?;
 
         let increment = match self.peek().map(|e| e.token()) {
-            Some(Token::RightParen) => Expr::LiteralExpr(ExprLiteralValue::NilLiteral),
-            _ => {
-                let e = self.expression()?;
-                e
-            }
+            Some(Token::RightParen) => None,
+            _ => Some(self.expression()?)
         };
         self.consume(&Token::RightParen)?;
-        let mut body = self.stmt()?;
-
-        match &increment {
-            Expr::LiteralExpr(ExprLiteralValue::NilLiteral) => (),
-            inc => {
-                body = Expr::BlockStmt(vec![Box::from(body), Box::from(inc.clone())]);
-                ()
-            }
-        };
+        let body = self.stmt()?;
 
-        match condition {
-            Expr::LiteralExpr(ExprLiteralValue::NilLiteral) => {
-                condition = Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(true));
-                ()
-            },
-            _ => {
-                ()
-            }
-        };
-        body = Expr::WhileLoop { condition: Box::from(condition), body: Box::from(body) };
-        match initializer {
-            Expr::LiteralExpr(ExprLiteralValue::NilLiteral) => (),
-            _ => {
-                body = Expr::BlockStmt(vec![Box::from(initializer), Box::from(body)]);
-                ()
-            }
-        };
+        let span = self.span_from(start);
+        let mut for_loop = Stmt::WhileLoop { condition: Box::from(condition), body: Box::from(body), increment: increment.map(Box::from), span };
+        if let Some(init) = initializer {
+            for_loop = Stmt::BlockStmt(vec![init, for_loop], span);
+        }
         self.add_stack("for_loop", -1);
-        Ok(body)
+        Ok(for_loop)
     }
 
-    fn while_loop(&mut self) -> Result<Expr> {
+    fn while_loop(&mut self) -> Result<Stmt> {
         self.add_stack("while", 1);
+        let start = self.peek_span();
         self.consume(&Token::While)?;
         self.consume(&Token::LeftParen)?;
         let cond = self.expression()?;
         self.consume(&Token::RightParen)?;
         let body = self.stmt()?;
         self.add_stack("while", -1);
-        Ok(Expr::WhileLoop { condition: Box::from(cond), body: Box::from(body) })
+        Ok(Stmt::WhileLoop { condition: Box::from(cond), body: Box::from(body), increment: None, span: self.span_from(start) })
+    }
+
+    fn loop_control(&mut self) -> Result<Stmt> {
+        let keyword = self.advance().ok_or(ParseError::new("No tokens to pop"))?;
+        self.consume(&Token::Semicolon)?;
+        let span = keyword.span().to(self.previous);
+        match keyword.token() {
+            Token::Break => Ok(Stmt::Break(span)),
+            Token::Continue => Ok(Stmt::Continue(span)),
+            _ => Err(ParseError::new("Expected break or continue"))
+        }
     }
 
-    fn if_stmt(&mut self) -> Result<Expr> {
+    fn if_stmt(&mut self) -> Result<Stmt> {
         self.add_stack("if", 1);
+        let start = self.peek_span();
         self.consume(&Token::If)?;
         self.consume(&Token::LeftParen)?;
         let condition = self.expression()?;
         self.consume(&Token::RightParen)?;
         let then_branch = self.stmt()?;
-        if let Some(Token::Else) = self.peek().map(|e| e.token()) {
+        let else_branch = if let Some(Token::Else) = self.peek().map(|e| e.token()) {
             self.consume(&Token::Else)?;
-            let else_branch = self.stmt()?;
-            self.add_stack("if", -1);
-            Ok(Expr::IfStmt { condition: Box::from(condition), then_branch: Box::from(then_branch), else_branch: Box::from(else_branch)})
+            Some(Box::from(self.stmt()?))
         } else {
-            let else_branch = Expr::LiteralExpr(ExprLiteralValue::NilLiteral);
-            self.add_stack("if", -1);
-            Ok(Expr::IfStmt { condition: Box::from(condition), then_branch: Box::from(then_branch), else_branch: Box::from(else_branch)})
-
-        }
+            None
+        };
+        self.add_stack("if", -1);
+        Ok(Stmt::IfStmt { condition: Box::from(condition), then_branch: Box::from(then_branch), else_branch, span: self.span_from(start) })
     }
 
-    fn return_stmt(&mut self) -> Result<Expr> {
+    fn return_stmt(&mut self) -> Result<Stmt> {
         if self.token_match(&Token::Return) {
-            let r = self.tokens.pop_front().ok_or(ParseError::new("No tokens to pop"))?;
+            let r = self.advance().ok_or(ParseError::new("No tokens to pop"))?;
             let val = match self.peek().map(|e| e.token()) {
-                Some(Token::Semicolon) => {
-                    unimplemented!()
-                },
-                _ => {
-                    let v = self.expression()?;
-                    v
-                }
+                Some(Token::Semicolon) => None,
+                _ => Some(Box::from(self.expression()?))
             };
             self.consume(&Token::Semicolon)?;
-            Ok(Expr::Return(r.token().clone(), Box::from(val)))
+            Ok(Stmt::Return(r.token().clone(), val, r.span().to(self.previous)))
 
         } else {
             Err(ParseError::new("Expected return".to_string()))
         }
     }
 
-    fn expression_stmt(&mut self) -> Result<Expr> {
+    fn expression_stmt(&mut self) -> Result<Stmt> {
         self.add_stack("expr_stmt", 1);
         let e = self.expression()?;
         self.consume(&Token::Semicolon)?;
         self.add_stack("expr_stmt", -1);
-        Ok(e)
+        let span = e.span().to(self.previous);
+        Ok(Stmt::ExprStmt(Box::from(e), span))
     }
 
-    fn print_stmt(&mut self) -> Result<Expr> {
+    fn print_stmt(&mut self) -> Result<Stmt> {
         self.add_stack("print", 1);
+        let start = self.peek_span();
         self.consume(&Token::Print)?;
         let e = self.expression()?;
         self.consume(&Token::Semicolon)?;
         self.add_stack("print", -1);
-        Ok(Expr::PrintStmt(Box::from(e)))
+        Ok(Stmt::PrintStmt(Box::from(e), self.span_from(start)))
+    }
+
+    fn block(&mut self) -> Result<Stmt> {
+        let start = self.peek_span();
+        let stmts = self.block_stmts()?;
+        Ok(Stmt::BlockStmt(stmts, self.span_from(start)))
     }
 
-    fn block(&mut self) -> Result<Expr> {
+    fn block_stmts(&mut self) -> Result<Vec<Stmt>> {
         let mut es = Vec::new();
         self.consume(&Token::LeftBrace)?;
         while !self.eof() && self.peek().map(|e| e.token()) != Some(&Token::RightBrace) {
-            let dec = self.decl()?;
-            es.push(Box::from(dec));
+            // decl has already synchronized after an error, so there is nothing left to recover
+            match self.decl() {
+                Ok(stmt) => es.push(stmt),
+                Err(e) => self.errors.push(e)
+            }
         }
 
         self.consume(&Token::RightBrace)?;
-        Ok(Expr::BlockStmt(es))
+        Ok(es)
     }
 
     fn expression(&mut self) -> Result<Expr> {
         self.add_stack("expr", 1);
         if self.eof() { 
-            error!("Unexpected EOF, expected [equality]");
-            Err(ParseError::new(format!("Unexpected EOF, expected [equality]"))) 
+            debug!("Unexpected EOF, expected [equality]");
+            Err(ParseError::unexpected(self.peek(), vec![Expected::Expression]))
         }
         else {
             let r = self.assignment();
@@ -323,11 +376,25 @@ impl Parser {
             self.consume(&Token::Equal)?;
             let value = self.assignment()?;
 
-            if let Expr::VariableExpr(name) = expr {
-                self.add_stack("assign", -1);
-                Ok(Expr::AssigmentExpr { name, value: Box::from(value)})
-            } else {
-                return Err(ParseError::new("Invalid assignment target"));
+            self.add_stack("assign", -1);
+            match expr {
+                Expr::VariableExpr { name, depth: _, span } => {
+                    let span = span.to(value.span());
+                    Ok(Expr::AssigmentExpr { name, value: Box::from(value), depth: None, span })
+                },
+                Expr::GetExpr { object, name, span } => {
+                    let span = span.to(value.span());
+                    Ok(Expr::SetExpr { object, name, value: Box::from(value), span })
+                },
+                Expr::IndexExpr { object, index, span } => {
+                    let span = span.to(value.span());
+                    Ok(Expr::IndexSetExpr { object, index, value: Box::from(value), span })
+                },
+                target => {
+                    let mut e = ParseError::new_at("Invalid assignment target", target.span());
+                    e.kind = ParseErrorKind::InvalidAssignmentTarget;
+                    Err(e)
+                }
             }
         }  else {
             self.add_stack("assign", -1);
@@ -365,7 +432,7 @@ impl Parser {
         let mut l = self.comparison()?;
 
         while self.token_match(&Token::BangEqual) || self.token_match(&Token::EqualEqual) {
-            let o = self.tokens.pop_front().unwrap();
+            let o = self.advance().unwrap();
             let r = self.comparison()?;
             l = Expr::new_binary_expr(l, o.token().clone(), r);
         }
@@ -378,7 +445,7 @@ impl Parser {
         let mut l = self.term()?;
 
         while self.token_match(&Token::Less) || self.token_match(&Token::LessEqual) || self.token_match(&Token::Greater) || self.token_match(&Token::GreaterEqual) {
-            let o = self.tokens.pop_front().unwrap();
+            let o = self.advance().unwrap();
             let r = self.term()?;
             l = Expr::new_binary_expr(l, o.token().clone(), r);
         }
@@ -391,7 +458,7 @@ impl Parser {
         let mut l = self.factor()?;
 
         while self.token_match(&Token::Minus) || self.token_match(&Token::Plus) {
-            let o = self.tokens.pop_front().unwrap();
+            let o = self.advance().unwrap();
             let r = self.factor()?;
             l = Expr::new_binary_expr(l, o.token().clone(), r);
         }
@@ -403,7 +470,7 @@ impl Parser {
         self.add_stack("factor", 1);
         let mut l = self.unary()?;
         while self.token_match(&Token::Star) || self.token_match(&Token::Slash) {
-            let o = self.tokens.pop_front().unwrap();
+            let o = self.advance().unwrap();
             let r = self.unary()?;
             l = Expr::new_binary_expr(l, o.token().clone(), r);
         }
@@ -415,10 +482,11 @@ impl Parser {
 
         self.add_stack("unary", 1);
         if self.token_match(&Token::Bang) || self.token_match(&Token::Minus) {
-            let o = self.tokens.pop_front().unwrap();
+            let o = self.advance().unwrap();
             let r = self.unary()?;
             self.add_stack("unary", -1);
-            Ok(Expr::UnaryExpr { operator: o.token().clone(), right: Box::from(r) })
+            let span = o.span().to(r.span());
+            Ok(Expr::UnaryExpr { operator: o.token().clone(), right: Box::from(r), span })
         } else {
             let p = self.call()?;
             self.add_stack("unary", -1);
@@ -428,63 +496,149 @@ impl Parser {
     fn call(&mut self) -> Result<Expr> {
         let mut expr = self.primary()?;
 
-        while let Some(Token::LeftParen) = self.peek().map(|e| e.token()) {
-            self.consume(&Token::LeftParen)?;
-            expr = self.finish_call(expr)?;
+        loop {
+            match self.peek().map(|e| e.token()) {
+                Some(Token::LeftParen) => {
+                    self.consume(&Token::LeftParen)?;
+                    expr = self.finish_call(expr)?;
+                },
+                Some(Token::Dot) => {
+                    self.consume(&Token::Dot)?;
+                    let name = self.identifier()?;
+                    let span = expr.span().to(self.previous);
+                    expr = Expr::GetExpr { object: Box::from(expr), name, span };
+                },
+                Some(Token::LeftBracket) => {
+                    self.consume(&Token::LeftBracket)?;
+                    let index = self.expression()?;
+                    self.consume(&Token::RightBracket)?;
+                    let span = expr.span().to(self.previous);
+                    expr = Expr::IndexExpr { object: Box::from(expr), index: Box::from(index), span };
+                },
+                _ => break
+            }
         }
         Ok(expr)
     }
 
     fn finish_call(&mut self, expr: Expr) -> Result<Expr> {
-        let mut args = Vec::new();
-        if Some(&Token::RightParen) != self.peek().map(|e| e.token()) {
+        let args = self.expression_list(&Token::RightParen)?;
+        let span = expr.span().to(self.previous);
+        Ok(Expr::CallExpr { callee: Box::from(expr), paren: Token::RightParen, arguments: args.into_iter().map(Box::from).collect(), span })
+        
+    }
+
+    /// Parses comma separated expressions up to and including `end`.
+    fn expression_list(&mut self, end: &Token) -> Result<Vec<Expr>> {
+        let mut exprs = Vec::new();
+        if Some(end) != self.peek().map(|e| e.token()) {
             loop {
-                
-                let next_arg = self.expression()?;
-                args.push(next_arg);
+                exprs.push(self.expression()?);
                 if let Some(Token::Comma) = self.peek().map(|e| e.token()) {
                     self.consume(&Token::Comma)?;
+                } else {
+                    break;
+                }
+            }
+        }
+        self.consume(end)?;
+        Ok(exprs)
+    }
+
+    /// Parses the rest of an interpolated string, whose text up to the first `${` is `first`.
+    fn interpolation(&mut self, first: String, start: Span) -> Result<Expr> {
+        let mut parts = Vec::new();
+        let mut text = Some((first, start));
+        loop {
+            if let Some((s, span)) = text.take().filter(|(s, _)| !s.is_empty()) {
+                parts.push(Box::from(Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s), span)));
+            }
+            parts.push(Box::from(self.expression()?));
+            // the scanner ends every interpolated expression with more of the string
+            match self.advance() {
+                Some(t) => match t.token() {
+                    Token::Interpolation(s) => text = Some((s.clone(), t.span())),
+                    Token::Literal(LiteralTokenType::StringLiteral(s)) => {
+                        if !s.is_empty() {
+                            parts.push(Box::from(Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s.clone()), t.span())));
+                        }
+                        break;
+                    },
+                    _ => {
+                        let err = ParseError::unexpected(Some(&t), vec![Expected::Token(Token::RightBrace)]);
+                        self.tokens.push_front(t);
+                        return Err(err);
+                    }
+                },
+                None => return Err(ParseError::unexpected(None, vec![Expected::Token(Token::RightBrace)]))
+            }
+        }
+        Ok(Expr::InterpolationExpr { parts, span: self.span_from(start) })
+    }
 
+    fn map_entries(&mut self, start: Span) -> Result<Expr> {
+        let mut entries = Vec::new();
+        if Some(&Token::RightBrace) != self.peek().map(|e| e.token()) {
+            loop {
+                let key = self.expression()?;
+                self.consume(&Token::Colon)?;
+                let value = self.expression()?;
+                entries.push((Box::from(key), Box::from(value)));
+                if let Some(Token::Comma) = self.peek().map(|e| e.token()) {
+                    self.consume(&Token::Comma)?;
                 } else {
                     break;
                 }
             }
-        } 
-        self.consume(&Token::RightParen)?;
-        Ok(Expr::CallExpr { callee: Box::from(expr), paren: Token::RightParen, arguments: args.iter().map(|e| Box::from(e.clone())).collect() })
-        
+        }
+        self.consume(&Token::RightBrace)?;
+        Ok(Expr::MapExpr { entries, span: self.span_from(start) })
     }
 
     fn primary(&mut self) -> Result<Expr> {
         self.add_stack("primary", 1);
         if self.peek().is_none() {
-            Err(ParseError::new("Unexpected EOF, expected [primary]"))
+            Err(ParseError::unexpected(None, vec![Expected::Expression]))
         } else {
-            if let Some(e) = self.tokens.pop_front() {
+            if let Some(e) = self.advance() {
+                let span = e.span();
                 let res = match e.token() {
                     Token::Literal(LiteralTokenType::NumberLiteral(n)) => {
-                        Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(*n))
+                        Expr::LiteralExpr(ExprLiteralValue::NumberLiteral(*n), span)
+                    },
+                    Token::Literal(LiteralTokenType::StringLiteral(s)) => Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s.to_string()), span),
+                    Token::Interpolation(s) => self.interpolation(s.clone(), span)?,
+                    Token::Literal(LiteralTokenType::IdentifierLiteral(s)) => Expr::new_variable_expr(Token::from_identifier(s), span),
+                    Token::Nil => Expr::LiteralExpr(ExprLiteralValue::NilLiteral, span),
+                    Token::True => Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(true), span),
+                    Token::False => Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(false), span),
+                    Token::This => Expr::ThisExpr { keyword: Token::This, depth: None, span },
+                    Token::Super => {
+                        self.consume(&Token::Dot)?;
+                        let method = self.identifier()?;
+                        Expr::SuperExpr { keyword: Token::Super, method, depth: None, span: self.span_from(span) }
                     },
-                    Token::Literal(LiteralTokenType::StringLiteral(s)) => Expr::LiteralExpr(ExprLiteralValue::StringLiteral(s.to_string())),
-                    Token::Literal(LiteralTokenType::IdentifierLiteral(s)) => Expr::VariableExpr(Token::Literal(LiteralTokenType::IdentifierLiteral(s.clone()))),
-                    Token::Nil => Expr::LiteralExpr(ExprLiteralValue::NilLiteral),
-                    Token::True => Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(true)),
-                    Token::False => Expr::LiteralExpr(ExprLiteralValue::BooleanLiteral(false)),
                     Token::LeftParen => {
                         let ex = self.expression()?;
                         self.consume(&Token::RightParen)?;
-                        Expr::GroupingExpr(Box::from(ex))
+                        Expr::GroupingExpr(Box::from(ex), self.span_from(span))
+                    },
+                    Token::LeftBracket => {
+                        let elements = self.expression_list(&Token::RightBracket)?;
+                        Expr::ListExpr { elements: elements.into_iter().map(Box::from).collect(), span: self.span_from(span) }
                     },
+                    // a brace only starts a block where a statement is expected, so here it is a map
+                    Token::LeftBrace => self.map_entries(span)?,
                     _ => {
-                        let t = &e.token().clone();
+                        let err = ParseError::unexpected(Some(&e), vec![Expected::Expression]);
                         self.tokens.push_front(e);
-                        return Err(ParseError::new(format!("Unexpected {:?}, expected [primary]", t)));
+                        return Err(err);
                     }
                 };
                 self.add_stack("primary", -1);
                 Ok(res)
             } else {
-                return Err(ParseError::new("Unexpected EOF, expected [primary]"));
+                Err(ParseError::unexpected(None, vec![Expected::Expression]))
             }
             
 
@@ -492,8 +646,24 @@ impl Parser {
         }
     }
 
+    fn advance(&mut self) -> Option<TokenContext> {
+        let tc = self.tokens.pop_front();
+        if let Some(t) = &tc {
+            self.previous = t.span();
+        }
+        tc
+    }
+
+    fn peek_span(&self) -> Span {
+        self.peek().map(|t| t.span()).unwrap_or(self.previous)
+    }
+
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous)
+    }
+
     fn peek(&self) -> Option<&TokenContext> {
-        if self.tokens.len() == 0 {
+        if self.tokens.is_empty() {
             None
         } else {
             Some(&self.tokens[0])
@@ -501,30 +671,24 @@ impl Parser {
     }
 
     fn token_match(&self, token: &Token) -> bool {
-        if !self.eof() && self.peek().unwrap().token() == token {
-            true
-        } else {
-            false
-        }
+        !self.eof() && self.peek().unwrap().token() == token
     }
 
+    /// Whether the parser has reached the `Eof` token, or run out of tokens without one.
     fn eof(&self) -> bool {
-        if let Some(&Token::Eof) = self.peek().map(|e| e.token()) {
-
-            true
-        } else { false }
+        matches!(self.peek().map(|e| e.token()), Some(&Token::Eof) | None)
     }
 
     fn consume(&mut self, token: &Token) -> Result<()> {
         if self.eof() {
-            Err(ParseError::new(format!("Unexpected EOF, expected {:?}", token)))
+            Err(ParseError::unexpected(self.peek(), vec![Expected::Token(token.clone())]))
         } else {
             match self.peek() {
                 Some(t) => {
                     if *(*t).token() == *token { 
-                        self.tokens.pop_front(); Ok(()) 
+                        self.advance(); Ok(()) 
                     } else {
-                        Err(ParseError::new(format!("Unexpected char {:?}, expected {:?}", t.token(), token)))
+                        Err(ParseError::unexpected(Some(t), vec![Expected::Token(token.clone())]))
                     }
                 },
                 _ => {
@@ -539,18 +703,18 @@ impl Parser {
         if self.eof() {
             return Ok(())
         }
-        let mut previous = self.tokens.pop_front().unwrap();
+        let mut previous = self.advance().unwrap();
         while !self.eof() {
             if previous.token() == &Token::Semicolon { return Ok(()); }
             match self.peek().map(|e| e.token()) {
                 Some(t) => match t {
-                    Token::Class|Token::Fun|Token::Var|Token::For|Token::If|Token::While|Token::Print|Token::Return => { 
+                    Token::Class|Token::Fun|Token::Var|Token::For|Token::If|Token::While|Token::Print|Token::Return|Token::Break|Token::Continue|Token::Import => { 
                         debug!("Found synchroization target {:?}", t);
                         return Ok(())
                     },
                     _ => {
                         debug!("Skipping token {:?}", previous);
-                        previous = self.tokens.pop_front().unwrap();
+                        previous = self.advance().unwrap();
                     }
                 },
                 None => {return Ok(());}
@@ -559,8 +723,8 @@ impl Parser {
         Ok(())
     }
 
-    fn add_stack(&mut self, method: &str, direction: i32) -> () {
-        self.stack_depth += 1 * direction;
+    fn add_stack(&mut self, method: &str, direction: i32) {
+        self.stack_depth += direction;
 
         let mut pad = String::new();
         for _ in 0..self.stack_depth {
@@ -571,27 +735,135 @@ impl Parser {
 }
 
 
+/// What the parser was looking for when it failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    Token(Token),
+    Identifier,
+    Keyword(&'static str),
+    String,
+    Expression,
+    Statement
+}
+
+impl Display for Expected {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Expected::Token(t) => write!(f, "{:?}", t),
+            Expected::Identifier => write!(f, "identifier"),
+            Expected::Keyword(k) => write!(f, "'{}'", k),
+            Expected::String => write!(f, "string"),
+            Expected::Expression => write!(f, "expression"),
+            Expected::Statement => write!(f, "statement")
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedToken,
+    UnexpectedEof,
+    InvalidAssignmentTarget,
+    Other
+}
+
 #[derive(Debug)]
 pub struct ParseError {
-    msg: String
+    msg: String,
+    span: Option<Span>,
+    kind: ParseErrorKind,
+    found: Option<Box<TokenContext>>,
+    expected: Vec<Expected>,
+    additional: Box<[ParseError]>
 }
 
 impl ParseError {
     pub fn new<B : ToString>(msg:B) -> ParseError {
-        ParseError { msg: msg.to_string()}
+        ParseError { msg: msg.to_string(), span: None, kind: ParseErrorKind::Other, found: None, expected: Vec::new(), additional: Box::new([]) }
+    }
+
+    pub fn new_at<B : ToString>(msg:B, span: Span) -> ParseError {
+        ParseError { span: Some(span), ..ParseError::new(msg) }
+    }
+
+    /// An error for finding `found` (or running out of tokens) where one of `expected` should have been.
+    pub fn unexpected(found: Option<&TokenContext>, expected: Vec<Expected>) -> ParseError {
+        let wanted = expected.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(" or ");
+        let (msg, kind) = match found.map(|t| t.token()) {
+            None | Some(Token::Eof) => (format!("Unexpected EOF, expected {}", wanted), ParseErrorKind::UnexpectedEof),
+            Some(t) => (format!("Unexpected {:?}, expected {}", t, wanted), ParseErrorKind::UnexpectedToken)
+        };
+        ParseError { msg, span: found.map(|t| t.span()), kind, found: found.cloned().map(Box::new), expected, additional: Box::new([]) }
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn kind(&self) -> ParseErrorKind {
+        self.kind
+    }
+
+    /// The token the parser stopped at, with its position in the source.
+    pub fn found(&self) -> Option<&TokenContext> {
+        self.found.as_deref()
+    }
+
+    pub fn expected(&self) -> &[Expected] {
+        &self.expected
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self.kind {
+            ParseErrorKind::UnexpectedToken | ParseErrorKind::Other => "P0001",
+            ParseErrorKind::InvalidAssignmentTarget => "P0002",
+            ParseErrorKind::UnexpectedEof => "P0003"
+        }
+    }
+
+    fn help(&self) -> Option<&'static str> {
+        match self.kind {
+            ParseErrorKind::InvalidAssignmentTarget => Some("only variables, instance fields, list elements and map entries can be assigned to"),
+            _ if self.expected == [Expected::Token(Token::Semicolon)] => Some("add a ';' to end the previous statement"),
+            _ => None
+        }
+    }
+
+    /// One diagnostic for this error and one for each further error found while recovering.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostic = Diagnostic::error(self.code(), &self.msg).with_span(self.span);
+        if let Some(help) = self.help() {
+            diagnostic = diagnostic.with_help(help);
+        }
+        let mut diagnostics = vec![diagnostic];
+        for e in self.additional.iter() {
+            diagnostics.extend(e.diagnostics());
+        }
+        diagnostics
     }
 }
 impl Error for ParseError {}
 impl Display for ParseError {
     
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> { 
+        if let Some(span) = self.span {
+            write!(f, "{}: ", span)?;
+        }
         write!(f, "{}", self.msg)?;
+        for e in self.additional.iter() {
+            write!(f, "\n{}", e)?;
+        }
         Ok(())
     }
 }
 #[cfg(test)]
 mod tests {
-    use super::{Token, TokenContext, Parser};
+    use super::{Expected, Expr, ParseErrorKind, Span, Stmt, Token, TokenContext, Parser};
     use super::ast_printer::print;
 
     #[test]
@@ -599,7 +871,7 @@ mod tests {
         let ts = vec![
             TokenContext::new(Token::from_number(3.0), 1, 0, "3.0"),
             TokenContext::new(Token::BangEqual, 1, 4, "!="), 
-            TokenContext::new(Token::from_string(r#""bye now""#), 1, 6, "\"bye now\""),
+            TokenContext::new(Token::from_string("bye now"), 1, 6, "\"bye now\""),
             TokenContext::new(Token::Semicolon, 1, 7, ";"),
             TokenContext::new(Token::Eof, 1, 11, "")
             ];
@@ -666,6 +938,284 @@ mod tests {
         let mut parser = Parser::new();
         parser.add_tokens(ts);
         let res = parser.parse().unwrap();
+        let _r = print(&res[0]);
+    }
+
+    #[test]
+    fn test_parses_property_assignment_into_set_expr() {
+        let ts = vec![
+            TokenContext::new(Token::from_identifier("point"), 1, 0, "point"),
+            TokenContext::new(Token::Dot, 1, 5, "."),
+            TokenContext::new(Token::from_identifier("x"), 1, 6, "x"),
+            TokenContext::new(Token::Equal, 1, 8, "="),
+            TokenContext::new(Token::from_number(1.0), 1, 10, "1"),
+            TokenContext::new(Token::Semicolon, 1, 11, ";"),
+            TokenContext::new(Token::Eof, 1, 12, "")
+        ];
+        let mut parser = Parser::new();
+        parser.add_tokens(ts);
+        let res = parser.parse().unwrap();
         let r = print(&res[0]);
+
+        assert_eq!(" Ident point.Ident x = 1.00", r);
+    }
+
+    #[test]
+    fn test_parses_list_literals_and_index_assignment() {
+        let ts = vec![
+            TokenContext::new(Token::from_identifier("xs"), 1, 0, "xs"),
+            TokenContext::new(Token::LeftBracket, 1, 2, "["),
+            TokenContext::new(Token::from_number(0.0), 1, 3, "0"),
+            TokenContext::new(Token::RightBracket, 1, 4, "]"),
+            TokenContext::new(Token::Equal, 1, 6, "="),
+            TokenContext::new(Token::LeftBracket, 1, 8, "["),
+            TokenContext::new(Token::from_number(1.0), 1, 9, "1"),
+            TokenContext::new(Token::Comma, 1, 10, ","),
+            TokenContext::new(Token::LeftBracket, 1, 12, "["),
+            TokenContext::new(Token::RightBracket, 1, 13, "]"),
+            TokenContext::new(Token::RightBracket, 1, 14, "]"),
+            TokenContext::new(Token::Semicolon, 1, 15, ";"),
+            TokenContext::new(Token::Eof, 1, 16, "")
+        ];
+        let mut parser = Parser::new();
+        parser.add_tokens(ts);
+        let res = parser.parse().unwrap();
+
+        assert_eq!(" Ident xs[0.00] = [1.00, []]", print(&res[0]));
+        assert_eq!(Span::new(1, 1, 1, 16), res[0].span());
+    }
+
+    #[test]
+    fn test_parses_braces_as_maps_in_expressions_and_blocks_in_statements() {
+        let ts = vec![
+            TokenContext::new(Token::LeftBrace, 1, 0, "{"),
+            TokenContext::new(Token::Print, 1, 2, "print"),
+            TokenContext::new(Token::LeftBrace, 1, 8, "{"),
+            TokenContext::new(Token::from_string("k"), 1, 9, "\"k\""),
+            TokenContext::new(Token::Colon, 1, 12, ":"),
+            TokenContext::new(Token::from_number(1.0), 1, 14, "1"),
+            TokenContext::new(Token::RightBrace, 1, 15, "}"),
+            TokenContext::new(Token::Semicolon, 1, 16, ";"),
+            TokenContext::new(Token::RightBrace, 1, 18, "}"),
+            TokenContext::new(Token::Eof, 1, 19, "")
+        ];
+        let mut parser = Parser::new();
+        parser.add_tokens(ts);
+        let res = parser.parse().unwrap();
+
+        if let Stmt::BlockStmt(stmts, _) = &res[0] {
+            assert_eq!("PRINT {k: 1.00}", print(&stmts[0]).trim());
+        } else {
+            panic!("expected a block");
+        }
+    }
+
+    #[test]
+    fn test_parses_interpolated_strings_into_their_parts() {
+        let ts = vec![
+            TokenContext::new(Token::Print, 1, 0, "print"),
+            TokenContext::new(Token::Interpolation("Hi ".to_string()), 1, 6, "\"Hi ${"),
+            TokenContext::new(Token::from_identifier("name"), 1, 12, "name"),
+            TokenContext::new(Token::from_string(""), 1, 16, "}\""),
+            TokenContext::new(Token::Semicolon, 1, 18, ";"),
+            TokenContext::new(Token::Eof, 1, 19, "")
+        ];
+        let mut parser = Parser::new();
+        parser.add_tokens(ts);
+        let res = parser.parse().unwrap();
+
+        if let Stmt::PrintStmt(inner, _) = &res[0] {
+            assert!(matches!(inner.as_ref(), Expr::InterpolationExpr { parts, .. } if parts.len() == 2));
+            assert_eq!(Span::new(1, 7, 1, 18), inner.span());
+        } else {
+            panic!("expected print statement");
+        }
+        assert_eq!("PRINT Hi ${ Ident name}", print(&res[0]).trim());
+    }
+
+    #[test]
+    fn test_parses_if_without_else_into_if_stmt() {
+        let ts = vec![
+            TokenContext::new(Token::If, 1, 0, "if"),
+            TokenContext::new(Token::LeftParen, 1, 3, "("),
+            TokenContext::new(Token::True, 1, 4, "true"),
+            TokenContext::new(Token::RightParen, 1, 8, ")"),
+            TokenContext::new(Token::Print, 1, 10, "print"),
+            TokenContext::new(Token::from_number(1.0), 1, 16, "1"),
+            TokenContext::new(Token::Semicolon, 1, 17, ";"),
+            TokenContext::new(Token::Eof, 1, 18, "")
+        ];
+        let mut parser = Parser::new();
+        parser.add_tokens(ts);
+        let res = parser.parse().unwrap();
+
+        assert!(matches!(&res[0], Stmt::IfStmt { else_branch: None, .. }));
+        assert_eq!("if true { PRINT 1.00 }", print(&res[0]));
+    }
+
+    #[test]
+    fn test_parses_both_forms_of_import() {
+        let ts = vec![
+            TokenContext::new(Token::Import, 1, 0, "import"),
+            TokenContext::new(Token::from_string("lib.lox"), 1, 7, "\"lib.lox\""),
+            TokenContext::new(Token::Semicolon, 1, 16, ";"),
+            TokenContext::new(Token::Import, 2, 0, "import"),
+            TokenContext::new(Token::LeftBrace, 2, 7, "{"),
+            TokenContext::new(Token::from_identifier("a"), 2, 8, "a"),
+            TokenContext::new(Token::Comma, 2, 9, ","),
+            TokenContext::new(Token::from_identifier("b"), 2, 11, "b"),
+            TokenContext::new(Token::RightBrace, 2, 12, "}"),
+            TokenContext::new(Token::from_identifier("from"), 2, 14, "from"),
+            TokenContext::new(Token::from_string("lib.lox"), 2, 19, "\"lib.lox\""),
+            TokenContext::new(Token::Semicolon, 2, 28, ";"),
+            TokenContext::new(Token::Import, 3, 0, "import"),
+            TokenContext::new(Token::LeftBrace, 3, 7, "{"),
+            TokenContext::new(Token::from_identifier("a"), 3, 8, "a"),
+            TokenContext::new(Token::RightBrace, 3, 9, "}"),
+            TokenContext::new(Token::from_string("lib.lox"), 3, 11, "\"lib.lox\""),
+            TokenContext::new(Token::Semicolon, 3, 20, ";"),
+            TokenContext::new(Token::Eof, 3, 21, "")
+        ];
+        let mut parser = Parser::new();
+        parser.add_tokens(ts);
+        let (stmts, errors) = parser.parse_with_errors();
+
+        assert_eq!("IMPORT \"lib.lox\";\n", print(&stmts[0]));
+        assert_eq!("IMPORT {Ident a, Ident b} FROM \"lib.lox\";\n", print(&stmts[1]));
+        assert_eq!(Span::new(2, 1, 2, 29), stmts[1].span());
+        assert_eq!(vec![Expected::Keyword("from")], errors[0].expected());
+    }
+
+    #[test]
+    fn test_parses_break_and_continue_inside_loop_bodies() {
+        let ts = vec![
+            TokenContext::new(Token::While, 1, 0, "while"),
+            TokenContext::new(Token::LeftParen, 1, 6, "("),
+            TokenContext::new(Token::True, 1, 7, "true"),
+            TokenContext::new(Token::RightParen, 1, 11, ")"),
+            TokenContext::new(Token::LeftBrace, 1, 13, "{"),
+            TokenContext::new(Token::Continue, 1, 15, "continue"),
+            TokenContext::new(Token::Semicolon, 1, 23, ";"),
+            TokenContext::new(Token::Break, 1, 25, "break"),
+            TokenContext::new(Token::Semicolon, 1, 30, ";"),
+            TokenContext::new(Token::RightBrace, 1, 32, "}"),
+            TokenContext::new(Token::Eof, 1, 33, "")
+        ];
+        let mut parser = Parser::new();
+        parser.add_tokens(ts);
+        let res = parser.parse().unwrap();
+
+        assert!(matches!(&res[0], Stmt::WhileLoop { increment: None, .. }));
+        if let Stmt::WhileLoop { body, .. } = &res[0] {
+            if let Stmt::BlockStmt(stmts, _) = body.as_ref() {
+                assert_eq!(Span::new(1, 16, 1, 24), stmts[0].span());
+                assert!(matches!(stmts[1], Stmt::Break(_)));
+            } else {
+                panic!("expected block body");
+            }
+        }
+    }
+
+    #[test]
+    fn test_records_source_spans_on_nodes_and_errors() {
+        let ts = vec![
+            TokenContext::new(Token::Print, 1, 0, "print"),
+            TokenContext::new(Token::from_number(1.0), 1, 6, "1"),
+            TokenContext::new(Token::Plus, 1, 8, "+"),
+            TokenContext::new(Token::from_number(2.0), 1, 10, "2"),
+            TokenContext::new(Token::Semicolon, 1, 11, ";"),
+            TokenContext::new(Token::Var, 2, 0, "var"),
+            TokenContext::new(Token::Semicolon, 2, 3, ";"),
+            TokenContext::new(Token::Eof, 2, 4, "")
+        ];
+        let mut parser = Parser::new();
+        parser.add_tokens(ts.clone());
+        let err = parser.parse().unwrap_err();
+        assert_eq!("2:4: Unexpected Semicolon, expected identifier", err.to_string().trim());
+
+        parser.add_tokens(ts[..5].iter().cloned().chain(ts[7..].iter().cloned()).collect());
+        let res = parser.parse().unwrap();
+        assert_eq!(Span::new(1, 1, 1, 12), res[0].span());
+        if let Stmt::PrintStmt(inner, _) = &res[0] {
+            assert_eq!(Span::new(1, 7, 1, 11), inner.span());
+        } else {
+            panic!("expected print statement");
+        }
+    }
+
+    #[test]
+    fn test_reports_a_diagnostic_for_every_recovered_error() {
+        let ts = vec![
+            TokenContext::new(Token::from_number(1.0), 1, 0, "1"),
+            TokenContext::new(Token::Plus, 1, 2, "+"),
+            TokenContext::new(Token::Semicolon, 1, 4, ";"),
+            TokenContext::new(Token::from_number(1.0), 2, 0, "1"),
+            TokenContext::new(Token::Equal, 2, 2, "="),
+            TokenContext::new(Token::from_number(2.0), 2, 4, "2"),
+            TokenContext::new(Token::Semicolon, 2, 5, ";"),
+            TokenContext::new(Token::Eof, 2, 6, "")
+        ];
+        let mut parser = Parser::new();
+        parser.add_tokens(ts);
+        let diagnostics = parser.parse().unwrap_err().diagnostics();
+
+        assert_eq!(2, diagnostics.len());
+        assert_eq!("P0001", diagnostics[0].code());
+        assert_eq!("P0002", diagnostics[1].code());
+        assert_eq!(Some(Span::new(2, 1, 2, 1)), diagnostics[1].span());
+    }
+
+    #[test]
+    fn test_recovers_from_errors_inside_a_block_once() {
+        let source = "fun f() {\n    var x = ;\n    print x;\n}\n{\n    print 1 +;\n    var a = 2;\n}\nprint ];\nprint 3;";
+        let mut parser = Parser::new();
+        parser.add_tokens(*rlox_scanner::Scanner::new().scan(source).unwrap());
+        let (stmts, errors) = parser.parse_with_errors();
+
+        let found: Vec<(&Token, usize)> = errors.iter().map(|e| e.found().unwrap()).map(|t| (t.token(), t.line_number())).collect();
+        assert_eq!(vec![(&Token::Semicolon, 2), (&Token::Semicolon, 6), (&Token::RightBracket, 9)], found);
+        let printed: Vec<String> = stmts.iter().map(|s| print(s).trim().to_string()).collect();
+        assert_eq!(3, printed.len(), "{:?}", printed);
+        assert!(printed[0].contains("Ident x"), "{}", printed[0]);
+        assert!(printed[1].contains("Ident a"), "{}", printed[1]);
+        assert_eq!("PRINT 3.00", printed[2]);
+    }
+
+    #[test]
+    fn test_parses_nothing_without_tokens() {
+        let mut parser = Parser::new();
+        parser.add_tokens(vec![]);
+        let (stmts, errors) = parser.parse_with_errors();
+        assert!(stmts.is_empty() && errors.is_empty());
+    }
+
+    #[test]
+    fn test_returns_recovered_statements_with_structured_errors() {
+        let ts = vec![
+            TokenContext::new(Token::Var, 1, 0, "var"),
+            TokenContext::new(Token::Semicolon, 1, 4, ";"),
+            TokenContext::new(Token::Print, 2, 0, "print"),
+            TokenContext::new(Token::from_number(1.0), 2, 6, "1"),
+            TokenContext::new(Token::Semicolon, 2, 7, ";"),
+            TokenContext::new(Token::Print, 3, 0, "print"),
+            TokenContext::new(Token::from_number(2.0), 3, 6, "2"),
+            TokenContext::new(Token::Eof, 3, 7, "")
+        ];
+        let mut parser = Parser::new();
+        parser.add_tokens(ts);
+        let (stmts, errors) = parser.parse_with_errors();
+
+        assert_eq!(1, stmts.len());
+        assert_eq!("PRINT 1.00", print(&stmts[0]).trim());
+        assert_eq!(2, errors.len());
+
+        assert_eq!(ParseErrorKind::UnexpectedToken, errors[0].kind());
+        assert_eq!([Expected::Identifier], errors[0].expected());
+        let found = errors[0].found().unwrap();
+        assert_eq!((&Token::Semicolon, 1, 4), (found.token(), found.line_number(), found.start_char_offset()));
+
+        assert_eq!(ParseErrorKind::UnexpectedEof, errors[1].kind());
+        assert_eq!([Expected::Token(Token::Semicolon)], errors[1].expected());
+        assert_eq!(Some(&Token::Eof), errors[1].found().map(|t| t.token()));
     }
 }
@@ -1,6 +1,7 @@
 extern crate rlox_contract;
 use std::fmt::Display;
-use rlox_contract::{TokenContext, Token, LiteralTokenType};
+use rlox_contract::{TokenContext, Token, LiteralTokenType, Span};
+use rlox_contract::diagnostics::Diagnostic;
 use std::error::Error;
 
 type Result<T> = std::result::Result<T, LexicalError>;
@@ -9,6 +10,12 @@ pub struct Scanner {
 
 }
 
+impl Default for Scanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Scanner {
 
 
@@ -20,19 +27,46 @@ impl Scanner {
 
     pub fn scan(&self, source: &str) -> Result<Box<Vec<TokenContext>>> {
         
-        if source.len() == 0 {
-            return Ok(Box::from(vec![]));
-        }
-    
         let mut tokens : Vec<TokenContext> = Vec::new();
         
         let mut current_idx = 0;
         let mut line = 1;
         let mut char_idx : usize= 0;
         let chars : Vec<char> = source.chars().collect();
+        // strings with an interpolation still open, innermost last: the number of braces opened
+        // inside the interpolation, and where the string started
+        let mut interpolations : Vec<(usize, Span)> = Vec::new();
     
-        while current_idx < source.len() {
-            if chars[current_idx].is_whitespace() {
+        while current_idx < chars.len() {
+            let resumes_string = chars[current_idx] == '}' && interpolations.last().is_some_and(|(depth, _)| *depth == 0);
+            if chars[current_idx] == '"' || resumes_string {
+                if resumes_string && matches!(tokens.last().map(|t| t.token()), Some(Token::Interpolation(_))) {
+                    let span = Span::new(line, char_idx - 1, line, char_idx + 1);
+                    return Err(LexicalError::new_at("L0004", "Expected an expression inside '${}'", span));
+                }
+                let start = if resumes_string {
+                    interpolations.pop().expect("no interpolation to resume").1
+                } else {
+                    Span::new(line, char_idx + 1, line, char_idx + 1)
+                };
+                let (contents, end, interpolated) = scan_string(&chars, current_idx, start, line, char_idx)?;
+                let token = if interpolated {
+                    interpolations.push((0, start));
+                    Token::Interpolation(contents)
+                } else {
+                    Token::from_string(contents)
+                };
+                let lexeme: String = chars[current_idx..end].iter().collect();
+                tokens.push(TokenContext::new(token, line, char_idx, lexeme));
+                for nxt_c in chars[current_idx..end].iter() {
+                    char_idx += 1;
+                    if *nxt_c == '\n' {
+                        line += 1;
+                        char_idx = 0;
+                    }
+                }
+                current_idx = end;
+            } else if chars[current_idx].is_whitespace() {
                 char_idx += 1;
                 if chars[current_idx] == '\n' {
                     line += 1;
@@ -43,9 +77,22 @@ impl Scanner {
                 let ctx = match &chars[current_idx] {
                     '(' => TokenContext::new(Token::LeftParen, line, char_idx, '('),
                     ')' => TokenContext::new(Token::RightParen, line, char_idx, ')'),
-                    '{' => TokenContext::new(Token::LeftBrace, line, char_idx, '{'),
-                    '}' => TokenContext::new(Token::RightBrace, line, char_idx, '}'),
+                    '{' => {
+                        if let Some((depth, _)) = interpolations.last_mut() {
+                            *depth += 1;
+                        }
+                        TokenContext::new(Token::LeftBrace, line, char_idx, '{')
+                    },
+                    '}' => {
+                        if let Some((depth, _)) = interpolations.last_mut() {
+                            *depth -= 1;
+                        }
+                        TokenContext::new(Token::RightBrace, line, char_idx, '}')
+                    },
+                    '[' => TokenContext::new(Token::LeftBracket, line, char_idx, '['),
+                    ']' => TokenContext::new(Token::RightBracket, line, char_idx, ']'),
                     ',' => TokenContext::new(Token::Comma, line, char_idx, ','),
+                    ':' => TokenContext::new(Token::Colon, line, char_idx, ':'),
                     '.' => TokenContext::new(Token::Dot, line, char_idx, '.'),
                     '*' => TokenContext::new(Token::Star, line, char_idx, '*'),
                     '-' => TokenContext::new(Token::Minus, line, char_idx, '-'),
@@ -79,7 +126,7 @@ impl Scanner {
                             '/' => {
                                 
                                 if chars.len() > current_idx + 1 && chars[current_idx + 1] == '/' {
-                                    for (_idx, j) in chars[current_idx..].iter().enumerate() {
+                                    for j in chars[current_idx..].iter() {
                                         current_idx+=1;   
                                         
                                         if *j == '\n' {
@@ -95,12 +142,12 @@ impl Scanner {
                                 }
                             }
                             _ => {
-                                if c.is_digit(10) {
+                                if c.is_ascii_digit() {
                                     let mut num_str = String::new();
     
-                                    for (_ix, nxt_c) in chars[current_idx..].iter().enumerate() {
-                                        let wc = nxt_c.clone();
-                                        if !(wc.is_digit(10)) && wc != '.' {
+                                    for nxt_c in chars[current_idx..].iter() {
+                                        let wc = *nxt_c;
+                                        if !wc.is_ascii_digit() && wc != '.' {
                                             break;
                                         } else {
                                             num_str.push(wc);
@@ -110,47 +157,32 @@ impl Scanner {
                                     TokenContext::new(Token::Literal(LiteralTokenType::NumberLiteral(num)), line, char_idx, num_str)
     
                                     
-                                }else if *c == '"' {
-                                    let mut chunk = String::new();
-                                    if current_idx + 1 > chars.len() {
-                                        return Err(LexicalError::new(format!("Unexpected EOF, expected \"")));
-                                    }
-                                    chunk.push('"');
-                                    let start_char_idx = char_idx;
-                                    for (_ix, nxt_c) in chars[current_idx+1..].iter().enumerate() {
-                                        chunk.push(nxt_c.clone());
-                                        char_idx += 1;
-                                        if nxt_c.clone() == '"' {
-                                            break;
-                                        }
-                                        if nxt_c.clone() == '\n' {
-                                            line += 1;
-                                            char_idx = 0;
-                                        }
-                                    }
-                                    TokenContext::new(Token::Literal(LiteralTokenType::StringLiteral(chunk.clone())), line, start_char_idx, chunk.clone())
                                 } else {
                                     let mut chunk = String::new();
-                                    for (_ix, nxt_c) in chars[current_idx..].iter().enumerate() {
+                                    for nxt_c in chars[current_idx..].iter() {
                                         
-                                        if nxt_c.is_whitespace() || *nxt_c == ';' || *nxt_c == '(' || *nxt_c == ')' || *nxt_c == ',' {
+                                        if !nxt_c.is_alphanumeric() && *nxt_c != '_' {
                                             break;
-                                        } else if !nxt_c.is_alphanumeric() && *nxt_c != '_' {
-                                            return Err(LexicalError::new(format!("UNEXPECTED CHAR {} at {}:{}", nxt_c, line, char_idx)));
-                                        }
-                                        else {
+                                        } else {
                                             chunk.push(*nxt_c);
                                         }
     
                                     }
+                                    if chunk.is_empty() {
+                                        let span = Span::new(line, char_idx + 1, line, char_idx + 1);
+                                        return Err(LexicalError::new_at("L0001", format!("Unexpected character '{}'", c), span));
+                                    }
                                     match chunk.as_str() {
                                         "and" => TokenContext::new(Token::And, line, char_idx, chunk),
+                                        "break" => TokenContext::new(Token::Break, line, char_idx, chunk),
                                         "class" => TokenContext::new(Token::Class, line, char_idx, chunk),
+                                        "continue" => TokenContext::new(Token::Continue, line, char_idx, chunk),
                                         "else" => TokenContext::new(Token::Else, line, char_idx, chunk),
                                         "false" => TokenContext::new(Token::False, line, char_idx, chunk),
                                         "fun" => TokenContext::new(Token::Fun, line, char_idx, chunk),
                                         "for" => TokenContext::new(Token::For, line, char_idx, chunk),
                                         "if" => TokenContext::new(Token::If, line, char_idx, chunk),
+                                        "import" => TokenContext::new(Token::Import, line, char_idx, chunk),
                                         "nil" => TokenContext::new(Token::Nil, line, char_idx, chunk),
                                         "or" => TokenContext::new(Token::Or, line, char_idx, chunk),
                                         "print" => TokenContext::new(Token::Print, line, char_idx, chunk),
@@ -176,6 +208,9 @@ impl Scanner {
             }
             
             
+        }
+        if let Some((_, start)) = interpolations.last() {
+            return Err(LexicalError::new_at("L0002", "Unterminated string", *start));
         }
         tokens.push(TokenContext::new(Token::Eof, line, char_idx, ""));
         Ok(Box::from(tokens))
@@ -183,20 +218,109 @@ impl Scanner {
 }
 
 
+/// Decodes the string literal, or the part of one, that starts after the quote or interpolation
+/// brace at `start`. Returns its contents, the index just past the closing quote or the `${` that
+/// ends it, and whether it was a `${`. `line` and `col` are the position of the character at
+/// `start`, and `string_start` where the whole string began.
+fn scan_string(chars: &[char], start: usize, string_start: Span, line: usize, col: usize) -> Result<(String, usize, bool)> {
+    let mut contents = String::new();
+    // position of chars[i], with a 0-based column like `col`
+    let (mut l, mut c) = (line, col + 1);
+    let mut i = start + 1;
+    while i < chars.len() {
+        let consumed = match chars[i] {
+            '"' => return Ok((contents, i + 1, false)),
+            '$' if chars.get(i + 1) == Some(&'{') => return Ok((contents, i + 2, true)),
+            '\\' if i + 1 < chars.len() => {
+                let (decoded, consumed) = escape(&chars[i..]);
+                match decoded {
+                    Some(ch) => contents.push(ch),
+                    None => {
+                        let sequence: String = chars[i..i + consumed].iter().collect();
+                        let span = Span::new(l, c + 1, l, c + consumed);
+                        return Err(LexicalError::new_at("L0003", format!("Invalid escape sequence '{}'", sequence), span));
+                    }
+                }
+                consumed
+            },
+            ch => {
+                contents.push(ch);
+                1
+            }
+        };
+        for ch in &chars[i..i + consumed] {
+            c += 1;
+            if *ch == '\n' {
+                l += 1;
+                c = 0;
+            }
+        }
+        i += consumed;
+    }
+    Err(LexicalError::new_at("L0002", "Unterminated string", string_start))
+}
+
+/// Decodes the escape sequence at the start of `chars`, which begins with a backslash. Returns
+/// the character it stands for, or `None` if it is invalid, and how many characters it spans.
+fn escape(chars: &[char]) -> (Option<char>, usize) {
+    match chars[1] {
+        'n' => (Some('\n'), 2),
+        't' => (Some('\t'), 2),
+        '\\' => (Some('\\'), 2),
+        '"' => (Some('"'), 2),
+        '$' => (Some('$'), 2),
+        'u' => {
+            if chars.get(2) != Some(&'{') {
+                return (None, 2);
+            }
+            let digits: String = chars[3..].iter().take_while(|ch| ch.is_ascii_hexdigit()).collect();
+            let end = 3 + digits.len();
+            let closed = chars.get(end) == Some(&'}');
+            if !closed || digits.is_empty() || digits.len() > 6 {
+                return (None, if closed { end + 1 } else { end });
+            }
+            let decoded = u32::from_str_radix(&digits, 16).ok().and_then(std::char::from_u32);
+            (decoded, end + 1)
+        },
+        _ => (None, 2)
+    }
+}
 
 #[derive(Debug,Clone)]
 pub struct LexicalError {
-    message: String
+    message: String,
+    code: &'static str,
+    span: Option<Span>
 }
 impl LexicalError {
     fn new<B>(message: B) -> LexicalError where B : ToString {
-        LexicalError { message: message.to_string() }
+        LexicalError { message: message.to_string(), code: "L0000", span: None }
+    }
+
+    fn new_at<B>(code: &'static str, message: B, span: Span) -> LexicalError where B : ToString {
+        LexicalError { message: message.to_string(), code, span: Some(span) }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.code, &self.message).with_span(self.span);
+        match self.code {
+            "L0002" => diagnostic.with_help("add a closing '\"' to end the string"),
+            "L0003" => diagnostic.with_help("the escapes are \\n, \\t, \\\\, \\\", \\$ and \\u{...} with up to six hex digits"),
+            _ => diagnostic
+        }
     }
 }
 impl Error for LexicalError {}
 impl Display for LexicalError {
     
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> { 
+        if let Some(span) = self.span {
+            write!(f, "{}: ", span)?;
+        }
         write!(f, "{}", self.message)?;
         Ok(())
     }
@@ -213,6 +337,12 @@ mod tests {
         assert_eq!(TokenContext::new(Token::LeftParen, 1, 0, "("), res[0]);
     }
 
+    #[test]
+    fn it_ends_empty_source_with_eof() {
+        let res = super::Scanner::new().scan("").unwrap();
+        assert_eq!(vec![TokenContext::new(Token::Eof, 1, 0, "")], *res);
+    }
+
     #[test]
     fn it_parses_out_simple_two_char_lexeme() {
         let source = "<=";
@@ -297,6 +427,72 @@ mod tests {
     fn it_parses_strings_correctly() {
         let source = "\"hello\"";
         let res = super::Scanner::new().scan(source).unwrap();
-        assert_eq!(TokenContext::new(Token::from_string("\"hello\""), 1, 0, "\"hello\""), res[0]);
+        assert_eq!(TokenContext::new(Token::from_string("hello"), 1, 0, "\"hello\""), res[0]);
+    }
+
+    #[test]
+    fn it_decodes_escape_sequences_in_strings() {
+        let source = r#""say \"hi\"\n\tC:\\ \u{1F600}\u{e9}" x"#;
+        let res = super::Scanner::new().scan(source).unwrap();
+        let expected = Token::from_string("say \"hi\"\n\tC:\\ \u{1F600}\u{e9}");
+        assert_eq!(TokenContext::new(expected, 1, 0, &source[..source.len() - 2]), res[0]);
+        // columns after the string count characters, not the escapes' bytes
+        assert_eq!(TokenContext::new(Token::from_identifier("x"), 1, 37, "x"), res[1]);
+    }
+
+    #[test]
+    fn it_splits_interpolated_strings_around_their_expressions() {
+        let source = r#""a${ {"k": "${b}"} }c" x"#;
+        let tokens: Vec<Token> = super::Scanner::new().scan(source).unwrap().iter().map(|t| t.token().clone()).collect();
+        assert_eq!(vec![
+            Token::Interpolation("a".to_string()),
+            Token::LeftBrace,
+            Token::from_string("k"),
+            Token::Colon,
+            Token::Interpolation("".to_string()),
+            Token::from_identifier("b"),
+            Token::from_string(""),
+            Token::RightBrace,
+            Token::from_string("c"),
+            Token::from_identifier("x"),
+            Token::Eof
+        ], tokens);
+
+        let err = super::Scanner::new().scan("var s = \"a ${b\nc").unwrap_err();
+        assert_eq!("1:9: Unterminated string", err.to_string());
+        let err = super::Scanner::new().scan("\"${}\"").unwrap_err();
+        assert_eq!("L0004", err.diagnostic().code());
+    }
+
+    #[test]
+    fn it_reports_invalid_escape_sequences() {
+        let errors: Vec<String> = [r#""a\q""#, r#""\u{110000}""#, r#""\u{}""#, r#""\u41""#].iter()
+            .map(|source| super::Scanner::new().scan(source).unwrap_err().to_string())
+            .collect();
+        assert_eq!(vec![
+            "1:3: Invalid escape sequence '\\q'",
+            "1:2: Invalid escape sequence '\\u{110000}'",
+            "1:2: Invalid escape sequence '\\u{}'",
+            "1:2: Invalid escape sequence '\\u'"
+        ], errors);
+        let err = super::Scanner::new().scan(r#""ends with \""#).unwrap_err();
+        assert_eq!("L0002", err.diagnostic().code());
+    }
+
+    #[test]
+    fn it_splits_identifiers_on_property_access() {
+        let source = "this.count";
+        let res = super::Scanner::new().scan(source).unwrap();
+        assert_eq!(4, res.len());
+        assert_eq!(TokenContext::new(Token::This, 1, 0, "this"), res[0]);
+        assert_eq!(TokenContext::new(Token::Dot, 1, 4, "."), res[1]);
+        assert_eq!(TokenContext::new(Token::from_identifier("count"), 1, 5, "count"), res[2]);
+    }
+
+    #[test]
+    fn it_reports_unterminated_strings_with_their_position() {
+        let err = super::Scanner::new().scan("var s = \"abc;").unwrap_err();
+        assert_eq!("1:9: Unterminated string", err.to_string());
+        assert_eq!("L0002", err.diagnostic().code());
     }
 }
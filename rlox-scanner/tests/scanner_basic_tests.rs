@@ -18,11 +18,11 @@ mod scanner_basic_tests {
         // bye
         "#;
         let res = rlox_scanner::Scanner::new().scan(source).unwrap();
-        let expected = vec![
+        let expected = [
             rlox_contract::TokenContext::new(rlox_contract::Token::Class, 1, 0, "class"),
             rlox_contract::TokenContext::new(rlox_contract::Token::from_identifier("foo"), 1, 6, "foo"),
             rlox_contract::TokenContext::new(rlox_contract::Token::LeftBrace, 1, 10, "{"),
-            rlox_contract::TokenContext::new(rlox_contract::Token::from_string("\"hello now\""), 2, 12, "\"hello now\""),
+            rlox_contract::TokenContext::new(rlox_contract::Token::from_string("hello now"), 2, 12, "\"hello now\""),
             rlox_contract::TokenContext::new(rlox_contract::Token::RightBrace, 3, 8, "}"),
         ];
         assert_eq!(6, res.len());
@@ -0,0 +1,99 @@
+use crate::value::Value;
+use rlox_contract::Span;
+use std::fmt::Write;
+
+/// A single VM instruction. Operands are stored inline and kept small so that an instruction
+/// fits in four bytes: constant, name and jump operands are `u16`, slots and argument counts `u8`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    Constant(u16),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u8),
+    SetLocal(u8),
+    GetGlobal(u16),
+    DefineGlobal(u16),
+    SetGlobal(u16),
+    GetUpvalue(u8),
+    SetUpvalue(u8),
+    GetProperty(u16),
+    SetProperty(u16),
+    GetSuper(u16),
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump(u16),
+    JumpIfFalse(u16),
+    Loop(u16),
+    Call(u8),
+    Invoke(u16, u8),
+    SuperInvoke(u16, u8),
+    Closure(u16),
+    CloseUpvalue,
+    Return,
+    Class(u16),
+    Inherit,
+    Method(u16),
+    BuildList(u16),
+    BuildMap(u16),
+    Interpolate(u16),
+    GetIndex,
+    SetIndex
+}
+
+/// A compiled sequence of instructions together with its constant pool. Every instruction
+/// records the span of the node it was compiled from so runtime errors can point at the source.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub constants: Vec<Value>,
+    pub spans: Vec<Span>
+}
+
+impl Chunk {
+    pub fn new() -> Chunk {
+        Chunk::default()
+    }
+
+    pub fn write(&mut self, op: OpCode, span: Span) -> usize {
+        self.code.push(op);
+        self.spans.push(span);
+        self.code.len() - 1
+    }
+
+    /// Adds `value` to the constant pool, returning its index or `None` if the pool is full.
+    pub fn add_constant(&mut self, value: Value) -> Option<u16> {
+        if self.constants.len() > u16::MAX as usize {
+            return None;
+        }
+        self.constants.push(value);
+        Some((self.constants.len() - 1) as u16)
+    }
+
+    /// Renders a human readable listing of the chunk, one instruction per line.
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut out = format!("== {} ==\n", name);
+        for (offset, (op, span)) in self.code.iter().zip(&self.spans).enumerate() {
+            let _ = match op {
+                OpCode::Constant(c) | OpCode::GetGlobal(c) | OpCode::DefineGlobal(c) | OpCode::SetGlobal(c)
+                | OpCode::GetProperty(c) | OpCode::SetProperty(c) | OpCode::GetSuper(c) | OpCode::Closure(c)
+                | OpCode::Class(c) | OpCode::Method(c) => {
+                    writeln!(out, "{:04} {:>7} {:?} ; {:?}", offset, span.to_string(), op, self.constants[*c as usize])
+                },
+                _ => writeln!(out, "{:04} {:>7} {:?}", offset, span.to_string(), op)
+            };
+        }
+        out
+    }
+}
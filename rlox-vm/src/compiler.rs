@@ -0,0 +1,775 @@
+use crate::chunk::{Chunk, OpCode};
+use crate::object::{Function, Heap, Obj, ObjRef, UpvalueDescriptor};
+use crate::value::Value;
+use log::debug;
+use rlox_contract::{Expr, ExprLiteralValue, LiteralTokenType, Span, Stmt, Token};
+use rlox_contract::diagnostics::Diagnostic;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer
+}
+
+struct Local {
+    name: String,
+    /// `None` while the variable's initializer is still being compiled.
+    depth: Option<usize>,
+    is_captured: bool
+}
+
+/// Book-keeping for the function currently being compiled; one per level of nesting.
+struct FunctionState {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    names: HashMap<String, u16>,
+    loops: Vec<LoopState>
+}
+
+/// A loop being compiled, with the `break` and `continue` jumps still waiting to be patched.
+struct LoopState {
+    scope_depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>
+}
+
+struct ClassState {
+    has_superclass: bool
+}
+
+/// Compiles the AST into bytecode. Variables are resolved here into stack slots, upvalues or
+/// globals rather than by the tree-walker's resolver, so the compiler reports the same static errors.
+pub struct Compiler<'h> {
+    heap: &'h mut Heap,
+    states: Vec<FunctionState>,
+    classes: Vec<ClassState>,
+    errors: Vec<CompileError>
+}
+
+impl<'h> Compiler<'h> {
+    pub fn new(heap: &'h mut Heap) -> Compiler<'h> {
+        Compiler { heap, states: Vec::new(), classes: Vec::new(), errors: Vec::new() }
+    }
+
+    /// Compiles `stmts` into the top-level script function, returning a handle to it.
+    pub fn compile(mut self, stmts: &[Stmt]) -> std::result::Result<ObjRef, Vec<CompileError>> {
+        self.begin_function(FunctionKind::Script, None);
+        for s in stmts {
+            self.stmt(s);
+        }
+        let end = stmts.last().map(|s| s.span()).unwrap_or_default();
+        let function = self.end_function(end);
+        if self.errors.is_empty() {
+            Ok(self.heap.alloc(Obj::Function(Rc::new(function))))
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::PrintStmt(inner, span) => {
+                self.expr(inner);
+                self.emit(OpCode::Print, *span);
+            },
+            Stmt::ExprStmt(inner, span) => {
+                self.expr(inner);
+                self.emit(OpCode::Pop, *span);
+            },
+            Stmt::VarDecl { name, initializer, span } => {
+                let global = self.declare_variable(name, *span);
+                self.expr(initializer);
+                self.define_variable(global, *span);
+            },
+            Stmt::BlockStmt(stmts, span) => {
+                self.begin_scope();
+                for s in stmts {
+                    self.stmt(s);
+                }
+                self.end_scope(*span);
+            },
+            Stmt::IfStmt { condition, then_branch, else_branch, span } => {
+                self.expr(condition);
+                let then_jump = self.emit(OpCode::JumpIfFalse(0), *span);
+                self.emit(OpCode::Pop, *span);
+                self.stmt(then_branch);
+                let else_jump = self.emit(OpCode::Jump(0), *span);
+                self.patch_jump(then_jump, *span);
+                self.emit(OpCode::Pop, *span);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+                self.patch_jump(else_jump, *span);
+            },
+            Stmt::WhileLoop { condition, body, increment, span } => {
+                let loop_start = self.chunk().code.len();
+                self.expr(condition);
+                let exit_jump = self.emit(OpCode::JumpIfFalse(0), *span);
+                self.emit(OpCode::Pop, *span);
+                let scope_depth = self.current().scope_depth;
+                self.current_mut().loops.push(LoopState { scope_depth, breaks: Vec::new(), continues: Vec::new() });
+                self.stmt(body);
+                let state = self.current_mut().loops.pop().expect("no loop being compiled");
+                for jump in state.continues {
+                    self.patch_jump(jump, *span);
+                }
+                if let Some(increment) = increment {
+                    self.expr(increment);
+                    self.emit(OpCode::Pop, *span);
+                }
+                self.emit_loop(loop_start, *span);
+                self.patch_jump(exit_jump, *span);
+                self.emit(OpCode::Pop, *span);
+                for jump in state.breaks {
+                    self.patch_jump(jump, *span);
+                }
+            },
+            Stmt::Break(span) => self.loop_control(true, *span),
+            Stmt::Continue(span) => self.loop_control(false, *span),
+            Stmt::Import { span, .. } => {
+                let error = CompileError::new("C0007", "The bytecode VM doesn't support imports", *span)
+                    .with_help("run scripts that import modules with the tree-walking interpreter ('--backend tree')");
+                self.errors.push(error);
+            },
+            Stmt::FunctionDecl { name, params, body, span } => {
+                let global = self.declare_variable(name, *span);
+                // a function may refer to itself, so it is usable before its body is compiled
+                self.mark_initialized();
+                self.function(FunctionKind::Function, name, params, body, *span);
+                self.define_variable(global, *span);
+            },
+            Stmt::Return(_, value, span) => {
+                match self.current().kind {
+                    FunctionKind::Script => self.error("R0002", "Can't return from top-level code", *span),
+                    FunctionKind::Initializer => {
+                        if let Some(value) = value {
+                            let error = CompileError::new("R0003", "Can't return a value from an initializer", value.span())
+                                .with_help("initializers always return 'this'; use a bare 'return;' instead");
+                            self.errors.push(error);
+                        }
+                        self.emit(OpCode::GetLocal(0), *span);
+                        self.emit(OpCode::Return, *span);
+                    },
+                    FunctionKind::Function | FunctionKind::Method => {
+                        match value {
+                            Some(value) => self.expr(value),
+                            None => {
+                                self.emit(OpCode::Nil, *span);
+                            }
+                        }
+                        self.emit(OpCode::Return, *span);
+                    }
+                }
+            },
+            Stmt::ClassDecl { name, superclass, methods, span } => {
+                let class_name = identifier(name);
+                let name_constant = self.identifier_constant(&class_name, *span);
+                let global = self.declare_variable(name, *span);
+                self.emit(OpCode::Class(name_constant), *span);
+                self.define_variable(global, *span);
+                self.classes.push(ClassState { has_superclass: false });
+
+                if let Some(superclass) = superclass {
+                    if let Expr::VariableExpr { name: super_name, depth: _, span: super_span } = superclass.as_ref() {
+                        if identifier(super_name) == class_name {
+                            self.error("R0008", format!("Class {} can't inherit from itself", class_name), *super_span);
+                        }
+                    }
+                    self.expr(superclass);
+                    self.begin_scope();
+                    self.add_local("super", *span);
+                    self.mark_initialized();
+                    self.named_variable(&class_name, *span);
+                    self.emit(OpCode::Inherit, *span);
+                    if let Some(class) = self.classes.last_mut() {
+                        class.has_superclass = true;
+                    }
+                }
+
+                self.named_variable(&class_name, *span);
+                for m in methods {
+                    if let Stmt::FunctionDecl { name, params, body, span } = m {
+                        let method_name = identifier(name);
+                        let constant = self.identifier_constant(&method_name, *span);
+                        let kind = if method_name == "init" { FunctionKind::Initializer } else { FunctionKind::Method };
+                        self.function(kind, name, params, body, *span);
+                        self.emit(OpCode::Method(constant), *span);
+                    }
+                }
+                self.emit(OpCode::Pop, *span);
+
+                if self.classes.pop().is_some_and(|c| c.has_superclass) {
+                    self.end_scope(*span);
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::LiteralExpr(lit, span) => {
+                match lit {
+                    ExprLiteralValue::NilLiteral => self.emit(OpCode::Nil, *span),
+                    ExprLiteralValue::BooleanLiteral(true) => self.emit(OpCode::True, *span),
+                    ExprLiteralValue::BooleanLiteral(false) => self.emit(OpCode::False, *span),
+                    ExprLiteralValue::NumberLiteral(n) => self.emit_constant(Value::Number(*n), *span),
+                    ExprLiteralValue::StringLiteral(s) => {
+                        let r = self.heap.alloc(Obj::String(Rc::from(s.as_str())));
+                        self.emit_constant(Value::Obj(r), *span)
+                    }
+                };
+            },
+            Expr::GroupingExpr(inner, _) => self.expr(inner),
+            Expr::UnaryExpr { operator, right, span } => {
+                self.expr(right);
+                let op = match operator {
+                    Token::Bang => OpCode::Not,
+                    Token::Minus => OpCode::Negate,
+                    _ => return self.error("C0006", format!("Expected unary operator, got {:?}", operator), *span)
+                };
+                self.emit(op, *span);
+            },
+            Expr::BinaryExpr { left, operator, right, span } => {
+                self.expr(left);
+                self.expr(right);
+                let ops: &[OpCode] = match operator {
+                    Token::Plus => &[OpCode::Add],
+                    Token::Minus => &[OpCode::Subtract],
+                    Token::Star => &[OpCode::Multiply],
+                    Token::Slash => &[OpCode::Divide],
+                    Token::Greater => &[OpCode::Greater],
+                    Token::GreaterEqual => &[OpCode::GreaterEqual],
+                    Token::Less => &[OpCode::Less],
+                    Token::LessEqual => &[OpCode::LessEqual],
+                    Token::EqualEqual => &[OpCode::Equal],
+                    Token::BangEqual => &[OpCode::Equal, OpCode::Not],
+                    _ => return self.error("C0006", format!("Expected binary operator, got {:?}", operator), *span)
+                };
+                for op in ops {
+                    self.emit(*op, *span);
+                }
+            },
+            Expr::VariableExpr { name, depth: _, span } => self.named_variable(&identifier(name), *span),
+            Expr::AssigmentExpr { name, value, depth: _, span } => {
+                self.expr(value);
+                let (_, set) = self.variable_ops(&identifier(name), *span);
+                self.emit(set, *span);
+            },
+            Expr::LogicalExpr { left, operator, right, span } => {
+                self.expr(left);
+                match operator {
+                    Token::And => {
+                        let end_jump = self.emit(OpCode::JumpIfFalse(0), *span);
+                        self.emit(OpCode::Pop, *span);
+                        self.expr(right);
+                        self.patch_jump(end_jump, *span);
+                    },
+                    Token::Or => {
+                        let else_jump = self.emit(OpCode::JumpIfFalse(0), *span);
+                        let end_jump = self.emit(OpCode::Jump(0), *span);
+                        self.patch_jump(else_jump, *span);
+                        self.emit(OpCode::Pop, *span);
+                        self.expr(right);
+                        self.patch_jump(end_jump, *span);
+                    },
+                    _ => self.error("C0006", format!("Expected logical operator, got {:?}", operator), *span)
+                }
+            },
+            Expr::CallExpr { callee, paren: _, arguments, span } => {
+                match callee.as_ref() {
+                    Expr::GetExpr { object, name, span: _ } => {
+                        self.expr(object);
+                        let argc = self.arguments(arguments, *span);
+                        let constant = self.identifier_constant(&identifier(name), *span);
+                        self.emit(OpCode::Invoke(constant, argc), *span);
+                    },
+                    Expr::SuperExpr { keyword: _, method, depth: _, span: super_span } => {
+                        if self.check_super(*super_span) {
+                            self.named_variable("this", *super_span);
+                            let argc = self.arguments(arguments, *span);
+                            self.named_variable("super", *super_span);
+                            let constant = self.identifier_constant(&identifier(method), *span);
+                            self.emit(OpCode::SuperInvoke(constant, argc), *span);
+                        }
+                    },
+                    _ => {
+                        self.expr(callee);
+                        let argc = self.arguments(arguments, *span);
+                        self.emit(OpCode::Call(argc), *span);
+                    }
+                }
+            },
+            Expr::GetExpr { object, name, span } => {
+                self.expr(object);
+                let constant = self.identifier_constant(&identifier(name), *span);
+                self.emit(OpCode::GetProperty(constant), *span);
+            },
+            Expr::SetExpr { object, name, value, span } => {
+                self.expr(object);
+                self.expr(value);
+                let constant = self.identifier_constant(&identifier(name), *span);
+                self.emit(OpCode::SetProperty(constant), *span);
+            },
+            Expr::ListExpr { elements, span } => {
+                for e in elements {
+                    self.expr(e);
+                }
+                if elements.len() > u16::MAX as usize {
+                    self.error("C0004", format!("Can't have more than {} elements in a list literal", u16::MAX), *span);
+                }
+                self.emit(OpCode::BuildList(elements.len() as u16), *span);
+            },
+            Expr::MapExpr { entries, span } => {
+                for (k, v) in entries {
+                    self.expr(k);
+                    self.expr(v);
+                }
+                if entries.len() > u16::MAX as usize {
+                    self.error("C0004", format!("Can't have more than {} entries in a map literal", u16::MAX), *span);
+                }
+                self.emit(OpCode::BuildMap(entries.len() as u16), *span);
+            },
+            Expr::InterpolationExpr { parts, span } => {
+                for p in parts {
+                    self.expr(p);
+                }
+                if parts.len() > u16::MAX as usize {
+                    self.error("C0004", format!("Can't have more than {} parts in an interpolated string", u16::MAX), *span);
+                }
+                self.emit(OpCode::Interpolate(parts.len() as u16), *span);
+            },
+            Expr::IndexExpr { object, index, span } => {
+                self.expr(object);
+                self.expr(index);
+                self.emit(OpCode::GetIndex, *span);
+            },
+            Expr::IndexSetExpr { object, index, value, span } => {
+                self.expr(object);
+                self.expr(index);
+                self.expr(value);
+                self.emit(OpCode::SetIndex, *span);
+            },
+            Expr::ThisExpr { keyword: _, depth: _, span } => {
+                if self.classes.is_empty() {
+                    self.error("R0005", "Can't use 'this' outside of a class", *span);
+                } else {
+                    self.named_variable("this", *span);
+                }
+            },
+            Expr::SuperExpr { keyword: _, method, depth: _, span } => {
+                if self.check_super(*span) {
+                    self.named_variable("this", *span);
+                    self.named_variable("super", *span);
+                    let constant = self.identifier_constant(&identifier(method), *span);
+                    self.emit(OpCode::GetSuper(constant), *span);
+                }
+            }
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Box<Expr>], span: Span) -> u8 {
+        if arguments.len() > u8::MAX as usize {
+            self.error("C0004", format!("Can't have more than {} arguments", u8::MAX), span);
+        }
+        for a in arguments {
+            self.expr(a);
+        }
+        arguments.len() as u8
+    }
+
+    fn check_super(&mut self, span: Span) -> bool {
+        match self.classes.last() {
+            None => self.error("R0006", "Can't use 'super' outside of a class", span),
+            Some(ClassState { has_superclass: false }) => self.error("R0007", "Can't use 'super' in a class with no superclass", span),
+            Some(ClassState { has_superclass: true }) => return true
+        }
+        false
+    }
+
+    fn function(&mut self, kind: FunctionKind, name: &Token, params: &[(Token, Span)], body: &[Stmt], span: Span) {
+        self.begin_function(kind, Some(Rc::from(identifier(name).as_str())));
+        self.begin_scope();
+        if params.len() > u8::MAX as usize {
+            self.error("C0004", format!("Can't have more than {} parameters", u8::MAX), span);
+        }
+        for (p, p_span) in params {
+            self.declare_variable(p, *p_span);
+            self.mark_initialized();
+        }
+        self.current_mut().function.arity = params.len();
+        for s in body {
+            self.stmt(s);
+        }
+        let function = self.end_function(span);
+        let r = self.heap.alloc(Obj::Function(Rc::new(function)));
+        let constant = self.make_constant(Value::Obj(r), span);
+        self.emit(OpCode::Closure(constant), span);
+    }
+
+    fn begin_function(&mut self, kind: FunctionKind, name: Option<Rc<str>>) {
+        // slot zero holds the callee, or the receiver inside methods
+        let receiver = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => ""
+        };
+        let function = Function { name, arity: 0, upvalues: Vec::new(), chunk: Chunk::new() };
+        let locals = vec![Local { name: receiver.to_string(), depth: Some(0), is_captured: false }];
+        self.states.push(FunctionState { function, kind, locals, scope_depth: 0, names: HashMap::new(), loops: Vec::new() });
+    }
+
+    fn end_function(&mut self, span: Span) -> Function {
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal(0), span);
+        } else {
+            self.emit(OpCode::Nil, span);
+        }
+        self.emit(OpCode::Return, span);
+        let state = self.states.pop().expect("no function being compiled");
+        let function = state.function;
+        debug!("\n{}", function.chunk.disassemble(function.name.as_deref().unwrap_or("<script>")));
+        function
+    }
+
+    /// Leaves the innermost loop through a jump patched once the loop is compiled, discarding
+    /// the locals declared inside it on the way out.
+    fn loop_control(&mut self, is_break: bool, span: Span) {
+        let keyword = if is_break { "break" } else { "continue" };
+        let depth = match self.current().loops.last() {
+            Some(l) => l.scope_depth,
+            None => return self.error("R0009", format!("Can't use '{}' outside of a loop", keyword), span)
+        };
+        let ops: Vec<OpCode> = self.current().locals.iter().rev()
+            .take_while(|l| l.depth.is_none_or(|d| d > depth))
+            .map(|l| if l.is_captured { OpCode::CloseUpvalue } else { OpCode::Pop })
+            .collect();
+        for op in ops {
+            self.emit(op, span);
+        }
+        let jump = self.emit(OpCode::Jump(0), span);
+        let state = self.current_mut().loops.last_mut().expect("no loop being compiled");
+        if is_break {
+            state.breaks.push(jump);
+        } else {
+            state.continues.push(jump);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self, span: Span) {
+        self.current_mut().scope_depth -= 1;
+        let depth = self.current().scope_depth;
+        while let Some(local) = self.current().locals.last() {
+            if local.depth.is_some_and(|d| d <= depth) {
+                break;
+            }
+            let op = if local.is_captured { OpCode::CloseUpvalue } else { OpCode::Pop };
+            self.emit(op, span);
+            self.current_mut().locals.pop();
+        }
+    }
+
+    /// Declares `name` in the current scope, returning the constant holding its name if it is a global.
+    fn declare_variable(&mut self, name: &Token, span: Span) -> Option<u16> {
+        let name = identifier(name);
+        if self.current().scope_depth == 0 {
+            return Some(self.identifier_constant(&name, span));
+        }
+        let state = self.current();
+        let duplicate = state.locals.iter().rev()
+            .take_while(|l| l.depth.is_none_or(|d| d >= state.scope_depth))
+            .any(|l| l.name == name);
+        if duplicate {
+            self.error("R0004", format!("Already a variable named {} in this scope", name), span);
+        }
+        self.add_local(&name, span);
+        None
+    }
+
+    fn define_variable(&mut self, global: Option<u16>, span: Span) {
+        match global {
+            Some(constant) => {
+                self.emit(OpCode::DefineGlobal(constant), span);
+            },
+            None => self.mark_initialized()
+        }
+    }
+
+    fn add_local(&mut self, name: &str, span: Span) {
+        if self.current().locals.len() > u8::MAX as usize {
+            return self.error("C0002", "Too many local variables in function", span);
+        }
+        self.current_mut().locals.push(Local { name: name.to_string(), depth: None, is_captured: false });
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.current_mut();
+        if state.scope_depth == 0 {
+            return;
+        }
+        let depth = state.scope_depth;
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+
+    fn named_variable(&mut self, name: &str, span: Span) {
+        let (get, _) = self.variable_ops(name, span);
+        self.emit(get, span);
+    }
+
+    /// Resolves `name` to the pair of instructions that read and write it.
+    fn variable_ops(&mut self, name: &str, span: Span) -> (OpCode, OpCode) {
+        let top = self.states.len() - 1;
+        if let Some(slot) = self.resolve_local(top, name, span) {
+            (OpCode::GetLocal(slot), OpCode::SetLocal(slot))
+        } else if let Some(index) = self.resolve_upvalue(top, name, span) {
+            (OpCode::GetUpvalue(index), OpCode::SetUpvalue(index))
+        } else {
+            let constant = self.identifier_constant(name, span);
+            (OpCode::GetGlobal(constant), OpCode::SetGlobal(constant))
+        }
+    }
+
+    fn resolve_local(&mut self, state: usize, name: &str, span: Span) -> Option<u8> {
+        let found = self.states[state].locals.iter().enumerate().rev()
+            .find(|(_, l)| l.name == name)
+            .map(|(i, l)| (i, l.depth.is_none()));
+        match found {
+            Some((slot, uninitialized)) => {
+                if uninitialized {
+                    let error = CompileError::new("R0001", format!("Can't read local variable {} in its own initializer", name), span)
+                        .with_help("give the new variable a different name");
+                    self.errors.push(error);
+                }
+                Some(slot as u8)
+            },
+            None => None
+        }
+    }
+
+    fn resolve_upvalue(&mut self, state: usize, name: &str, span: Span) -> Option<u8> {
+        if state == 0 {
+            return None;
+        }
+        if let Some(local) = self.resolve_local(state - 1, name, span) {
+            self.states[state - 1].locals[local as usize].is_captured = true;
+            return Some(self.add_upvalue(state, local, true, span));
+        }
+        if let Some(upvalue) = self.resolve_upvalue(state - 1, name, span) {
+            return Some(self.add_upvalue(state, upvalue, false, span));
+        }
+        None
+    }
+
+    fn add_upvalue(&mut self, state: usize, index: u8, is_local: bool, span: Span) -> u8 {
+        let descriptor = UpvalueDescriptor { is_local, index };
+        let upvalues = &self.states[state].function.upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == descriptor) {
+            return existing as u8;
+        }
+        if upvalues.len() > u8::MAX as usize {
+            self.error("C0003", "Too many closure variables in function", span);
+            return 0;
+        }
+        self.states[state].function.upvalues.push(descriptor);
+        (self.states[state].function.upvalues.len() - 1) as u8
+    }
+
+    fn identifier_constant(&mut self, name: &str, span: Span) -> u16 {
+        if let Some(constant) = self.current().names.get(name) {
+            return *constant;
+        }
+        let r = self.heap.alloc(Obj::String(Rc::from(name)));
+        let constant = self.make_constant(Value::Obj(r), span);
+        self.current_mut().names.insert(name.to_string(), constant);
+        constant
+    }
+
+    fn make_constant(&mut self, value: Value, span: Span) -> u16 {
+        match self.chunk().add_constant(value) {
+            Some(constant) => constant,
+            None => {
+                self.error("C0001", "Too many constants in one chunk", span);
+                0
+            }
+        }
+    }
+
+    fn emit_constant(&mut self, value: Value, span: Span) -> usize {
+        let constant = self.make_constant(value, span);
+        self.emit(OpCode::Constant(constant), span)
+    }
+
+    fn emit(&mut self, op: OpCode, span: Span) -> usize {
+        self.chunk().write(op, span)
+    }
+
+    fn emit_loop(&mut self, loop_start: usize, span: Span) {
+        let offset = self.chunk().code.len() + 1 - loop_start;
+        if offset > u16::MAX as usize {
+            self.error("C0005", "Loop body too large", span);
+        }
+        self.emit(OpCode::Loop(offset as u16), span);
+    }
+
+    /// Points the jump emitted at `at` to the next instruction to be emitted.
+    fn patch_jump(&mut self, at: usize, span: Span) {
+        let offset = self.chunk().code.len() - at - 1;
+        if offset > u16::MAX as usize {
+            self.error("C0005", "Too much code to jump over", span);
+        }
+        let offset = offset as u16;
+        let chunk = self.chunk();
+        chunk.code[at] = match chunk.code[at] {
+            OpCode::Jump(_) => OpCode::Jump(offset),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(offset),
+            op => unreachable!("can't patch {:?}", op)
+        };
+    }
+
+    fn error<B : ToString>(&mut self, code: &'static str, msg: B, span: Span) {
+        self.errors.push(CompileError::new(code, msg, span));
+    }
+
+    fn current(&self) -> &FunctionState {
+        self.states.last().expect("no function being compiled")
+    }
+
+    fn current_mut(&mut self) -> &mut FunctionState {
+        self.states.last_mut().expect("no function being compiled")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current_mut().function.chunk
+    }
+}
+
+fn identifier(token: &Token) -> String {
+    match token {
+        Token::Literal(LiteralTokenType::IdentifierLiteral(s)) => s.clone(),
+        Token::This => "this".to_string(),
+        Token::Super => "super".to_string(),
+        t => format!("{}", t)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    msg: String,
+    code: &'static str,
+    span: Span,
+    help: Option<String>
+}
+
+impl CompileError {
+    pub fn new<B : ToString>(code: &'static str, msg: B, span: Span) -> CompileError {
+        CompileError { msg: msg.to_string(), code, span, help: None }
+    }
+
+    fn with_help<B : ToString>(mut self, help: B) -> CompileError {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.code, &self.msg).with_span(Some(self.span));
+        match &self.help {
+            Some(help) => diagnostic.with_help(help),
+            None => diagnostic
+        }
+    }
+}
+impl Error for CompileError {}
+impl Display for CompileError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{}: {}", self.span, self.msg)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Compiler;
+    use crate::chunk::OpCode;
+    use crate::object::Heap;
+    use rlox_parser::Parser;
+    use rlox_scanner::Scanner;
+
+    fn compile(source: &str) -> (Heap, std::result::Result<crate::object::ObjRef, Vec<super::CompileError>>) {
+        let tokens = Scanner::new().scan(source).expect("failed to scan");
+        let mut parser = Parser::new();
+        parser.add_tokens(*tokens);
+        let stmts = parser.parse().expect("failed to parse");
+        let mut heap = Heap::new();
+        let result = Compiler::new(&mut heap).compile(&stmts);
+        (heap, result)
+    }
+
+    #[test]
+    fn it_compiles_expressions_into_stack_operations() {
+        let (heap, result) = compile("print 1 + 2 * 3;");
+        let script = heap.function(result.expect("failed to compile")).clone();
+        assert_eq!(vec![
+            OpCode::Constant(0), OpCode::Constant(1), OpCode::Constant(2), OpCode::Multiply, OpCode::Add,
+            OpCode::Print, OpCode::Nil, OpCode::Return
+        ], script.chunk.code);
+        assert_eq!(3, script.chunk.constants.len());
+    }
+
+    #[test]
+    fn it_resolves_locals_to_slots_and_captures_upvalues() {
+        let (heap, result) = compile("{ var a = 1; fun f() { return a; } }");
+        let script = heap.function(result.expect("failed to compile")).clone();
+        assert_eq!(OpCode::CloseUpvalue, script.chunk.code[script.chunk.code.len() - 3]);
+        let f = script.chunk.constants.iter()
+            .filter_map(|c| c.as_obj())
+            .find_map(|r| match heap.get(r) { crate::object::Obj::Function(f) => Some(f.clone()), _ => None })
+            .expect("no nested function");
+        assert_eq!(1, f.upvalues.len());
+        assert!(f.upvalues[0].is_local);
+        assert_eq!(1, f.upvalues[0].index);
+        assert_eq!(OpCode::GetUpvalue(0), f.chunk.code[0]);
+    }
+
+    #[test]
+    fn it_reports_static_errors_with_their_codes() {
+        let (_, result) = compile("return 1;\n{ var a = 1; var a = 2; }");
+        let errors = result.expect_err("expected compile errors");
+        let codes: Vec<&str> = errors.iter().map(|e| e.code).collect();
+        assert_eq!(vec!["R0002", "R0004"], codes);
+
+        let (_, result) = compile("break;\nwhile (true) { fun f() { continue; } }");
+        let errors = result.expect_err("expected compile errors");
+        assert!(errors.iter().all(|e| e.code == "R0009"));
+        assert_eq!(2, errors.len());
+
+        let (_, result) = compile("class A { init() { return nil; } }\nclass B { init() { return; } }");
+        let errors = result.expect_err("expected compile errors");
+        assert_eq!(vec!["R0003"], errors.iter().map(|e| e.code).collect::<Vec<_>>());
+
+        let (_, result) = compile("fun f(a,\n      a) {\n}");
+        let errors = result.expect_err("expected compile errors");
+        assert_eq!(("R0004", rlox_contract::Span::new(2, 7, 2, 7)), (errors[0].code, errors[0].span()));
+
+        let (_, result) = compile("import \"lib.lox\";");
+        let errors = result.expect_err("expected compile errors");
+        assert_eq!("C0007", errors[0].code);
+        assert!(errors[0].help.as_deref().unwrap_or_default().contains("--backend tree"));
+    }
+}
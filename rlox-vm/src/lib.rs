@@ -0,0 +1,924 @@
+extern crate rlox_contract;
+extern crate rlox_scanner;
+extern crate rlox_parser;
+use std::error::Error;
+use std::fmt::Display;
+use rlox_contract::Span;
+use rlox_contract::diagnostics::Diagnostic;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+use rlox_scanner::Scanner;
+use rlox_parser::Parser;
+
+pub type Result<B> = std::result::Result<B, VmError>;
+pub mod chunk;
+use chunk::OpCode;
+mod compiler;
+pub use compiler::{Compiler, CompileError};
+mod object;
+pub use object::{GcConfig, GcStats, Heap, Obj, ObjRef};
+use object::{BoundMethod, Class, Closure, Function, Instance, Map, MapKey, Upvalue};
+mod natives;
+pub use natives::NativeFunction;
+mod value;
+pub use value::Value;
+
+/// Maximum depth of nested calls before the VM reports a stack overflow.
+const FRAMES_MAX: usize = 256;
+
+struct CallFrame {
+    closure: ObjRef,
+    function: Rc<Function>,
+    ip: usize,
+    /// Index of the stack slot holding the callee; its locals follow.
+    base: usize
+}
+
+/// Runs programs by compiling them to bytecode and executing it on a stack machine.
+pub struct Vm {
+    scanner: Scanner,
+    parser: Parser,
+    heap: Heap,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Rc<str>, Value>,
+    /// Upvalues still pointing into the stack, so closures created in the same scope share them.
+    open_upvalues: Vec<ObjRef>,
+    source_name: String,
+    exit_code: Option<i32>,
+    out: Box<dyn Write>,
+    err: Box<dyn Write>
+}
+
+impl Default for Vm {
+    fn default() -> Vm {
+        Vm::with_gc_config(GcConfig::default())
+    }
+}
+
+impl Vm {
+
+    /// Creates a VM whose globals start out holding the core library.
+    pub fn with_gc_config(config: GcConfig) -> Vm {
+        let mut vm = Vm {
+            scanner: Scanner::new(),
+            parser: Parser::new(),
+            heap: Heap::with_config(config),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            source_name: "<input>".to_string(),
+            exit_code: None,
+            out: Box::new(std::io::stdout()),
+            err: Box::new(std::io::stderr())
+        };
+        for f in natives::core_library() {
+            let native = vm.heap.alloc(Obj::Native(f));
+            vm.globals.insert(Rc::from(f.name), Value::Obj(native));
+        }
+        vm
+    }
+
+    /// Creates a VM that writes `print` output to `out` and rendered diagnostics to `err`,
+    /// rather than to the process's stdout and stderr.
+    pub fn with_output<O, E>(out: O, err: E) -> Vm where O : Write + 'static, E : Write + 'static {
+        Vm { out: Box::new(out), err: Box::new(err), ..Vm::default() }
+    }
+
+    /// Statistics about the garbage collections done so far.
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    /// The code passed to `exit()`, once a program has called it.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Sets the name errors are reported against, usually the path of the script being run.
+    pub fn set_source_name<B>(&mut self, name: B) where B : ToString {
+        self.source_name = name.to_string();
+    }
+
+    /// Runs `source`, rendering any errors to the error sink. Scan, parse and compile errors
+    /// come back as `ErrorKind::InvalidData` and stop anything from running; a runtime error
+    /// comes back as `ErrorKind::InvalidInput`.
+    pub fn execute_source<B>(&mut self, source: B) -> std::io::Result<()> where B : ToString {
+        let source = source.to_string();
+        let script = match self.compile(&source) {
+            Ok(script) => script,
+            Err(diagnostics) => {
+                for d in &diagnostics {
+                    self.report(d, &source);
+                }
+                let msg = format!("{}: {} error(s) found before running", self.source_name, diagnostics.len());
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg));
+            }
+        };
+        match self.run_script(script) {
+            Err(_) if self.exit_code.is_some() => Ok(()),
+            Err(e) => {
+                self.report(&e.diagnostic(), &source);
+                Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, self.located(&e)))
+            },
+            Ok(()) => Ok(())
+        }
+    }
+
+    /// Scans, parses and compiles `source` into a script function, collecting every error found
+    /// along the way.
+    fn compile(&mut self, source: &str) -> std::result::Result<ObjRef, Vec<Diagnostic>> {
+        let tokens = self.scanner.scan(source).map_err(|le| vec![le.diagnostic()])?;
+        self.parser.add_tokens(*tokens);
+        let stmts = self.parser.parse().map_err(|pe| pe.diagnostics())?;
+        Compiler::new(&mut self.heap).compile(&stmts)
+            .map_err(|errors| errors.iter().map(CompileError::diagnostic).collect())
+    }
+
+    fn report(&mut self, diagnostic: &Diagnostic, source: &str) {
+        // there is nowhere left to report a failure to write a diagnostic
+        let _ = write!(self.err, "{}", diagnostic.render(&self.source_name, source));
+    }
+
+    fn located(&self, e: &VmError) -> String {
+        match e.span {
+            Some(span) => format!("{}:{}: {}", self.source_name, span, e.msg),
+            None => format!("{}: {}", self.source_name, e.msg)
+        }
+    }
+
+    fn run_script(&mut self, function: ObjRef) -> Result<()> {
+        // keep the script reachable while its closure is allocated
+        self.push(Value::Obj(function));
+        let closure = self.alloc(Obj::Closure(Closure { function, upvalues: Vec::new() }));
+        self.pop();
+        self.push(Value::Obj(closure));
+        let result = self.call(closure, 0).and_then(|_| self.run(0));
+        match result {
+            Ok(()) => {
+                // the script's own return value
+                self.pop();
+                Ok(())
+            },
+            Err(e) => Err(self.unwind(e))
+        }
+    }
+
+    /// Allocates `obj`, first collecting garbage if the heap has grown enough. Anything the
+    /// caller still needs must be reachable from the stack, globals or call frames.
+    fn alloc(&mut self, obj: Obj) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(obj)
+    }
+
+    fn collect_garbage(&mut self) {
+        let mut roots = self.stack.clone();
+        roots.extend(self.globals.values());
+        roots.extend(self.frames.iter().map(|f| Value::Obj(f.closure)));
+        roots.extend(self.open_upvalues.iter().map(|u| Value::Obj(*u)));
+        self.heap.collect(&roots);
+    }
+
+    /// Locates `e` at the instruction that failed, notes the call sites that led to it and
+    /// resets the VM so it can run again.
+    fn unwind(&mut self, e: VmError) -> VmError {
+        let mut spans = self.frames.iter().rev().map(|f| f.function.chunk.spans[f.ip.saturating_sub(1)]);
+        let mut e = match spans.next() {
+            Some(span) => e.with_span(span),
+            None => e
+        };
+        // deep recursion would otherwise repeat the same call site for every frame
+        let mut sites: Vec<(Span, usize)> = Vec::new();
+        for span in spans {
+            match sites.last_mut() {
+                Some((last, count)) if *last == span => *count += 1,
+                _ => sites.push((span, 1))
+            }
+        }
+        for (span, count) in sites {
+            e = match count {
+                1 => e.with_note(format!("called from {}", span)),
+                n => e.with_note(format!("called from {} ({} times)", span, n))
+            };
+        }
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        e
+    }
+
+    /// Runs until the number of call frames drops back to `depth`, leaving the value returned
+    /// by the last frame on the stack.
+    fn run(&mut self, depth: usize) -> Result<()> {
+        loop {
+            let op = {
+                let frame = self.frames.last_mut().expect("no call frame");
+                let op = frame.function.chunk.code[frame.ip];
+                frame.ip += 1;
+                op
+            };
+
+            match op {
+                OpCode::Constant(c) => {
+                    let v = self.constant(c);
+                    self.push(v);
+                },
+                OpCode::Nil => self.push(Value::Nil),
+                OpCode::True => self.push(Value::Bool(true)),
+                OpCode::False => self.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                },
+                OpCode::GetLocal(slot) => {
+                    let v = self.stack[self.frame().base + slot as usize];
+                    self.push(v);
+                },
+                OpCode::SetLocal(slot) => {
+                    let base = self.frame().base;
+                    self.stack[base + slot as usize] = self.peek(0);
+                },
+                OpCode::GetGlobal(c) => {
+                    let name = self.constant_name(c);
+                    match self.globals.get(&name) {
+                        Some(v) => {
+                            let v = *v;
+                            self.push(v);
+                        },
+                        None => return Err(VmError::new(format!("Undefined variable {}", name)))
+                    }
+                },
+                OpCode::DefineGlobal(c) => {
+                    let name = self.constant_name(c);
+                    let v = self.pop();
+                    self.globals.insert(name, v);
+                },
+                OpCode::SetGlobal(c) => {
+                    let name = self.constant_name(c);
+                    if !self.globals.contains_key(&name) {
+                        return Err(VmError::new(format!("Variable {} assigned but never declared", name)));
+                    }
+                    let v = self.peek(0);
+                    self.globals.insert(name, v);
+                },
+                OpCode::GetUpvalue(i) => {
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[i as usize];
+                    let v = match self.heap.get(upvalue) {
+                        Obj::Upvalue(Upvalue::Open(slot)) => self.stack[*slot],
+                        Obj::Upvalue(Upvalue::Closed(v)) => *v,
+                        o => unreachable!("expected an upvalue, got {:?}", o)
+                    };
+                    self.push(v);
+                },
+                OpCode::SetUpvalue(i) => {
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[i as usize];
+                    let v = self.peek(0);
+                    match self.heap.get_mut(upvalue) {
+                        Obj::Upvalue(Upvalue::Open(slot)) => self.stack[*slot] = v,
+                        Obj::Upvalue(closed) => *closed = Upvalue::Closed(v),
+                        o => unreachable!("expected an upvalue, got {:?}", o)
+                    }
+                },
+                OpCode::GetProperty(c) => {
+                    let name = self.constant_name(c);
+                    let receiver = self.peek(0);
+                    let (class, field) = match receiver.as_obj().map(|r| self.heap.get(r)) {
+                        Some(Obj::Instance(instance)) => (instance.class, instance.fields.get(&name).copied()),
+                        _ => return Err(VmError::new(format!("Only instances have properties, got {}", self.heap.display(receiver))))
+                    };
+                    match field {
+                        Some(v) => {
+                            self.pop();
+                            self.push(v);
+                        },
+                        None => self.bind_method(class, &name)?
+                    }
+                },
+                OpCode::SetProperty(c) => {
+                    let name = self.constant_name(c);
+                    let v = self.pop();
+                    let receiver = self.pop();
+                    match receiver.as_obj().map(|r| self.heap.get_mut(r)) {
+                        Some(Obj::Instance(instance)) => {
+                            instance.fields.insert(name, v);
+                        },
+                        _ => return Err(VmError::new(format!("Only instances have fields, got {}", self.heap.display(receiver))))
+                    }
+                    self.push(v);
+                },
+                OpCode::BuildList(n) => {
+                    // the elements stay on the stack while the list is allocated, so they are rooted
+                    let start = self.stack.len() - n as usize;
+                    let list = self.alloc(Obj::List(self.stack[start..].to_vec()));
+                    self.stack.truncate(start);
+                    self.push(Value::Obj(list));
+                },
+                OpCode::BuildMap(n) => {
+                    let start = self.stack.len() - 2 * n as usize;
+                    let mut map = Map::default();
+                    for entry in self.stack[start..].chunks(2) {
+                        let hashed = self.map_key(entry[0])?;
+                        map.insert(hashed, entry[0], entry[1]);
+                    }
+                    let map = self.alloc(Obj::Map(map));
+                    self.stack.truncate(start);
+                    self.push(Value::Obj(map));
+                },
+                OpCode::Interpolate(n) => {
+                    let start = self.stack.len() - n as usize;
+                    let s: String = self.stack[start..].iter().map(|v| self.heap.display(*v)).collect();
+                    let s = self.alloc(Obj::String(Rc::from(s)));
+                    self.stack.truncate(start);
+                    self.push(Value::Obj(s));
+                },
+                OpCode::GetIndex => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let v = match object.as_obj().map(|r| self.heap.get(r)) {
+                        Some(Obj::List(elements)) => elements[list_index(&self.heap, index, elements.len())?],
+                        Some(Obj::Map(map)) => match map.get(&self.map_key(index)?) {
+                            Some(v) => v,
+                            None => return Err(VmError::new(format!("Undefined key {}", self.heap.display(index))))
+                        },
+                        _ => return Err(VmError::new(format!("Only lists and maps can be indexed, got {}", self.heap.type_name(object))))
+                    };
+                    self.push(v);
+                },
+                OpCode::SetIndex => {
+                    let v = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    let hashed = self.heap.map_key(index);
+                    // checked before the list is borrowed, as the error names the index's type
+                    let checked = match object.as_obj().map(|r| self.heap.get(r)) {
+                        Some(Obj::List(elements)) => Some(list_index(&self.heap, index, elements.len())?),
+                        _ => None
+                    };
+                    match object.as_obj().map(|r| self.heap.get_mut(r)) {
+                        Some(Obj::List(elements)) => elements[checked.expect("list index checked above")] = v,
+                        Some(Obj::Map(map)) => match hashed {
+                            Some(hashed) => map.insert(hashed, index, v),
+                            None => return Err(map_key_error())
+                        },
+                        _ => return Err(VmError::new(format!("Only lists and maps can be indexed, got {}", self.heap.type_name(object))))
+                    }
+                    self.push(v);
+                },
+                OpCode::GetSuper(c) => {
+                    let name = self.constant_name(c);
+                    let superclass = self.pop();
+                    self.bind_method(superclass.as_obj().expect("superclass is not an object"), &name)?;
+                },
+                OpCode::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(Value::Bool(self.heap.values_equal(a, b)));
+                },
+                OpCode::Greater => self.comparison("GREATER", |a, b| a > b)?,
+                OpCode::GreaterEqual => self.comparison("GREATEREQUAL", |a, b| a >= b)?,
+                OpCode::Less => self.comparison("LESS", |a, b| a < b)?,
+                OpCode::LessEqual => self.comparison("LESSEQUAL", |a, b| a <= b)?,
+                OpCode::Add => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let v = match (a, b) {
+                        (Value::Number(n), Value::Number(m)) => Value::Number(n + m),
+                        _ => match (self.heap.as_string(a), self.heap.as_string(b)) {
+                            (Some(s1), Some(s2)) => {
+                                let s = format!("{}{}", s1, s2);
+                                Value::Obj(self.alloc(Obj::String(Rc::from(s))))
+                            },
+                            _ => return Err(self.operands_error("PLUS", "two numbers or two strings", a, b))
+                        }
+                    };
+                    self.push(v);
+                },
+                OpCode::Subtract => self.arithmetic("MINUS", |a, b| a - b)?,
+                OpCode::Multiply => self.arithmetic("STAR", |a, b| a * b)?,
+                OpCode::Divide => {
+                    if self.peek(0) == Value::Number(0_f64) && matches!(self.peek(1), Value::Number(_)) {
+                        return Err(VmError::new("Divide by zero error"));
+                    }
+                    self.arithmetic("SLASH", |a, b| a / b)?
+                },
+                OpCode::Not => {
+                    let v = self.pop();
+                    self.push(Value::Bool(!v.is_truthy()));
+                },
+                OpCode::Negate => {
+                    match self.pop() {
+                        Value::Number(n) => self.push(Value::Number(-n)),
+                        v => return Err(VmError::new(format!("Expected number, got {}", self.heap.type_name(v))))
+                    }
+                },
+                OpCode::Print => {
+                    let v = self.pop();
+                    let shown = self.heap.display(v);
+                    writeln!(self.out, "{}", shown).map_err(|e| VmError::new(format!("Failed to write output: {}", e)))?;
+                },
+                OpCode::Jump(offset) => self.frame_mut().ip += offset as usize,
+                OpCode::JumpIfFalse(offset) => {
+                    if !self.peek(0).is_truthy() {
+                        self.frame_mut().ip += offset as usize;
+                    }
+                },
+                OpCode::Loop(offset) => self.frame_mut().ip -= offset as usize,
+                OpCode::Call(argc) => {
+                    let callee = self.peek(argc as usize);
+                    self.call_value(callee, argc as usize)?;
+                },
+                OpCode::Invoke(c, argc) => {
+                    let name = self.constant_name(c);
+                    self.invoke(&name, argc as usize)?;
+                },
+                OpCode::SuperInvoke(c, argc) => {
+                    let name = self.constant_name(c);
+                    let superclass = self.pop();
+                    self.invoke_from_class(superclass.as_obj().expect("superclass is not an object"), &name, argc as usize)?;
+                },
+                OpCode::Closure(c) => {
+                    let function = self.constant(c).as_obj().expect("closure constant is not a function");
+                    let descriptors = self.heap.function(function).upvalues.clone();
+                    let mut upvalues = Vec::with_capacity(descriptors.len());
+                    for d in descriptors {
+                        let upvalue = if d.is_local {
+                            self.capture_upvalue(self.frame().base + d.index as usize)
+                        } else {
+                            self.heap.closure(self.frame().closure).upvalues[d.index as usize]
+                        };
+                        upvalues.push(upvalue);
+                    }
+                    let closure = self.alloc(Obj::Closure(Closure { function, upvalues }));
+                    self.push(Value::Obj(closure));
+                },
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                },
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("no call frame");
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    self.push(result);
+                    if self.frames.len() == depth {
+                        return Ok(());
+                    }
+                },
+                OpCode::Class(c) => {
+                    let name = self.constant_name(c);
+                    let class = self.alloc(Obj::Class(Class { name, methods: HashMap::new() }));
+                    self.push(Value::Obj(class));
+                },
+                OpCode::Inherit => {
+                    let superclass = self.peek(1);
+                    let methods = match superclass.as_obj().map(|r| self.heap.get(r)) {
+                        Some(Obj::Class(c)) => c.methods.clone(),
+                        _ => return Err(VmError::new(format!("Superclass must be a class, got {}", self.heap.display(superclass))))
+                    };
+                    if let Some(Obj::Class(subclass)) = self.peek(0).as_obj().map(|r| self.heap.get_mut(r)) {
+                        subclass.methods.extend(methods);
+                    }
+                    self.pop();
+                },
+                OpCode::Method(c) => {
+                    let name = self.constant_name(c);
+                    let method = self.peek(0).as_obj().expect("method is not a closure");
+                    if let Some(Obj::Class(class)) = self.peek(1).as_obj().map(|r| self.heap.get_mut(r)) {
+                        class.methods.insert(name, method);
+                    }
+                    self.pop();
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, argc: usize) -> Result<()> {
+        let base = self.stack.len() - argc - 1;
+        if let Some(r) = callee.as_obj() {
+            match self.heap.get(r) {
+                Obj::Closure(_) => return self.call(r, argc),
+                Obj::Class(class) => {
+                    let init = class.methods.get("init").copied();
+                    let instance = self.alloc(Obj::Instance(Instance { class: r, fields: HashMap::new() }));
+                    self.stack[base] = Value::Obj(instance);
+                    return match init {
+                        Some(init) => self.call(init, argc),
+                        None if argc == 0 => Ok(()),
+                        None => Err(VmError::new(format!("Expected 0 arguments but got {}", argc)))
+                    };
+                },
+                Obj::BoundMethod(BoundMethod { receiver, method }) => {
+                    let method = *method;
+                    self.stack[base] = *receiver;
+                    return self.call(method, argc);
+                },
+                Obj::Native(native) => {
+                    let native = *native;
+                    if native.arity != argc {
+                        return Err(VmError::new(format!("Expected {} arguments but got {}", native.arity, argc)));
+                    }
+                    // the arguments stay on the stack, rooted, until the native returns
+                    let args = self.stack[base + 1..].to_vec();
+                    let result = (native.function)(self, &args)?;
+                    self.stack.truncate(base);
+                    self.push(result);
+                    return Ok(());
+                },
+                _ => ()
+            }
+        }
+        Err(VmError::new(format!("Can only call functions and classes, got {}", self.heap.display(callee))))
+    }
+
+    /// Calls `callee` with `args` on behalf of a native function and runs it to completion.
+    fn call_from_native(&mut self, callee: Value, args: &[Value]) -> Result<Value> {
+        let depth = self.frames.len();
+        self.push(callee);
+        for arg in args {
+            self.push(*arg);
+        }
+        self.call_value(callee, args.len())?;
+        if self.frames.len() > depth {
+            self.run(depth)?;
+        }
+        Ok(self.pop())
+    }
+
+    fn call(&mut self, closure: ObjRef, argc: usize) -> Result<()> {
+        let function = self.heap.function(self.heap.closure(closure).function).clone();
+        if function.arity != argc {
+            return Err(VmError::new(format!("Expected {} arguments but got {}", function.arity, argc)));
+        }
+        if self.frames.len() == FRAMES_MAX {
+            return Err(VmError::new("Stack overflow"));
+        }
+        let base = self.stack.len() - argc - 1;
+        self.frames.push(CallFrame { closure, function, ip: 0, base });
+        Ok(())
+    }
+
+    /// Calls the method `name` on the receiver below the arguments without creating a bound method.
+    fn invoke(&mut self, name: &Rc<str>, argc: usize) -> Result<()> {
+        let receiver = self.peek(argc);
+        let (class, field) = match receiver.as_obj().map(|r| self.heap.get(r)) {
+            Some(Obj::Instance(instance)) => (instance.class, instance.fields.get(name).copied()),
+            _ => return Err(VmError::new(format!("Only instances have methods, got {}", self.heap.display(receiver))))
+        };
+        match field {
+            Some(v) => {
+                let base = self.stack.len() - argc - 1;
+                self.stack[base] = v;
+                self.call_value(v, argc)
+            },
+            None => self.invoke_from_class(class, name, argc)
+        }
+    }
+
+    fn invoke_from_class(&mut self, class: ObjRef, name: &Rc<str>, argc: usize) -> Result<()> {
+        match self.heap.class(class).methods.get(name).copied() {
+            Some(method) => self.call(method, argc),
+            None => Err(VmError::new(format!("Undefined property '{}'", name)))
+        }
+    }
+
+    /// Replaces the instance on top of the stack with its method `name` bound to it.
+    fn bind_method(&mut self, class: ObjRef, name: &Rc<str>) -> Result<()> {
+        let method = match self.heap.class(class).methods.get(name).copied() {
+            Some(method) => method,
+            None => return Err(VmError::new(format!("Undefined property '{}'", name)))
+        };
+        let receiver = self.peek(0);
+        let bound = self.alloc(Obj::BoundMethod(BoundMethod { receiver, method }));
+        self.pop();
+        self.push(Value::Obj(bound));
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let heap = &self.heap;
+        let existing = self.open_upvalues.iter()
+            .find(|u| matches!(heap.get(**u), Obj::Upvalue(Upvalue::Open(s)) if *s == slot));
+        if let Some(upvalue) = existing {
+            return *upvalue;
+        }
+        let upvalue = self.alloc(Obj::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    /// Moves every captured variable at or above `from` off the stack and into its upvalue.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        let heap = &mut self.heap;
+        self.open_upvalues.retain(|u| match heap.get_mut(*u) {
+            Obj::Upvalue(upvalue) => match upvalue {
+                Upvalue::Open(slot) if *slot >= from => {
+                    let v = stack[*slot];
+                    *upvalue = Upvalue::Closed(v);
+                    false
+                },
+                _ => true
+            },
+            _ => true
+        });
+    }
+
+    fn arithmetic(&mut self, operator: &str, f: fn(f64, f64) -> f64) -> Result<()> {
+        let b = self.pop();
+        let a = self.pop();
+        match (a, b) {
+            (Value::Number(n), Value::Number(m)) => {
+                self.push(Value::Number(f(n, m)));
+                Ok(())
+            },
+            _ => Err(self.operands_error(operator, "two numbers", a, b))
+        }
+    }
+
+    fn comparison(&mut self, operator: &str, f: fn(f64, f64) -> bool) -> Result<()> {
+        let b = self.pop();
+        let a = self.pop();
+        match (a, b) {
+            (Value::Number(n), Value::Number(m)) => {
+                self.push(Value::Bool(f(n, m)));
+                Ok(())
+            },
+            _ => Err(self.operands_error(operator, "two numbers", a, b))
+        }
+    }
+
+    fn map_key(&self, key: Value) -> Result<MapKey> {
+        self.heap.map_key(key).ok_or_else(map_key_error)
+    }
+
+    fn operands_error(&self, operator: &str, expected: &str, a: Value, b: Value) -> VmError {
+        VmError::new(format!("Operator {} expects {}, got {} and {}", operator, expected, self.heap.type_name(a), self.heap.type_name(b)))
+    }
+
+    fn constant(&self, c: u16) -> Value {
+        self.frame().function.chunk.constants[c as usize]
+    }
+
+    fn constant_name(&self, c: u16) -> Rc<str> {
+        let r = self.constant(c).as_obj().expect("name constant is not a string");
+        self.heap.string(r).clone()
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("no call frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("no call frame")
+    }
+
+    fn push(&mut self, v: Value) {
+        self.stack.push(v);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+}
+
+fn map_key_error() -> VmError {
+    VmError::new("Map keys must be nil, booleans, numbers or strings, and not NaN")
+}
+
+/// Checks that `index` can index a list of `len` elements and returns it as a position.
+fn list_index(heap: &Heap, index: Value, len: usize) -> Result<usize> {
+    match index {
+        Value::Number(n) if n.fract() != 0.0 => Err(VmError::new(format!("List index must be a whole number, got {}", n))),
+        Value::Number(n) if n < 0.0 || n >= len as f64 => {
+            Err(VmError::new(format!("Index {} out of bounds for list of length {}", n, len)))
+        },
+        Value::Number(n) => Ok(n as usize),
+        _ => Err(VmError::new(format!("List index must be a number, got {}", heap.type_name(index))))
+    }
+}
+
+/// Like `list_index`, but also accepts `len` itself: the position just past the last element,
+/// where a new one may be inserted or a slice may end.
+fn list_position(heap: &Heap, index: Value, len: usize) -> Result<usize> {
+    match index {
+        Value::Number(n) if n == len as f64 => Ok(len),
+        _ => list_index(heap, index, len)
+    }
+}
+
+#[derive(Debug)]
+pub struct VmError {
+    msg: String,
+    span: Option<Span>,
+    notes: Vec<String>
+}
+
+impl VmError {
+    pub fn new<B : ToString>(msg:B) -> VmError {
+        VmError { msg: msg.to_string(), span: None, notes: Vec::new() }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        self.notes.iter().fold(Diagnostic::error("E0001", &self.msg).with_span(self.span), |d, n| d.with_note(n))
+    }
+
+    fn with_note(mut self, note: String) -> VmError {
+        self.notes.push(note);
+        self
+    }
+
+    fn with_span(mut self, span: Span) -> VmError {
+        self.span = Some(span);
+        self
+    }
+}
+impl Error for VmError {}
+impl Display for VmError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        if let Some(span) = self.span {
+            write!(f, "{}: ", span)?;
+        }
+        write!(f, "{}", self.msg)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compiler, GcConfig, Span, Vm, VmError};
+
+    fn run(source: &str) -> Vm {
+        let mut vm = Vm::with_gc_config(GcConfig::stress());
+        run_in(&mut vm, source).expect("failed to run");
+        vm
+    }
+
+    fn run_in(vm: &mut Vm, source: &str) -> super::Result<()> {
+        let tokens = vm.scanner.scan(source).expect("failed to scan");
+        vm.parser.add_tokens(*tokens);
+        let stmts = vm.parser.parse().expect("failed to parse");
+        let script = Compiler::new(&mut vm.heap).compile(&stmts).expect("failed to compile");
+        vm.run_script(script)
+    }
+
+    fn global(vm: &Vm, name: &str) -> String {
+        vm.heap.display(*vm.globals.get(name).expect("undefined global"))
+    }
+
+    #[test]
+    fn it_runs_recursive_functions() {
+        let vm = run("fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+                      var result = fib(10);");
+        assert_eq!("55.00", global(&vm, "result"));
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    fn it_keeps_captured_variables_alive_after_their_scope_ends() {
+        let vm = run("fun counter() { var i = 0; fun inc() { i = i + 1; return i; } return inc; }
+                      var a = counter();
+                      var b = counter();
+                      a(); a();
+                      var x = a();
+                      var y = b();");
+        assert_eq!("3.00", global(&vm, "x"));
+        assert_eq!("1.00", global(&vm, "y"));
+    }
+
+    #[test]
+    fn it_dispatches_methods_through_initializers_and_superclasses() {
+        let vm = run("class A { init(n) { this.n = n; } get() { return this.n; } name() { return \"A\"; } }
+                      class B < A { name() { return super.name() + \"B\"; } }
+                      var b = B(7);
+                      var n = b.get();
+                      var s = b.name();
+                      var m = b.name;
+                      var t = m();");
+        assert_eq!("7.00", global(&vm, "n"));
+        assert_eq!("AB", global(&vm, "s"));
+        assert_eq!("AB", global(&vm, "t"));
+        assert_eq!("B instance", global(&vm, "b"));
+    }
+
+    #[test]
+    fn it_breaks_out_of_and_continues_loops_discarding_their_locals() {
+        let vm = run("var evens = 0;
+                      for (var i = 0; i < 10; i = i + 1) {
+                          if (i == 7) break;
+                          var x = i;
+                          fun odd() { return x == 1 or x == 3 or x == 5; }
+                          if (odd()) continue;
+                          evens = evens + 1;
+                      }
+                      var last;
+                      while (true) { var i = 0; fun get() { return i; } last = get; break; }");
+        assert_eq!("4.00", global(&vm, "evens"));
+        assert_eq!("<fn get>", global(&vm, "last"));
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    fn it_builds_and_indexes_lists() {
+        let vm = run("var xs = [1, \"two\", [3]];
+                      xs[0] = xs[2][0] + 1;
+                      var first = xs[0];
+                      xs[2][0] = xs;");
+        assert_eq!("4.00", global(&vm, "first"));
+        assert_eq!("[4.00, two, [[...]]]", global(&vm, "xs"));
+
+        let mut vm = Vm::default();
+        let err = run_in(&mut vm, "var xs = [1];\nxs[1];").expect_err("expected an error");
+        assert_eq!("2:1: Index 1 out of bounds for list of length 1", err.to_string());
+    }
+
+    #[test]
+    fn it_builds_maps_and_hashes_string_keys_by_contents() {
+        let vm = run("var key = \"a\" + \"b\";
+                      var m = {\"ab\": 1, 2: [], nil: true};
+                      m[key] = m[key] + 1;
+                      m[-0] = m;
+                      var ab = m[\"ab\"];");
+        assert_eq!("2.00", global(&vm, "ab"));
+        assert_eq!("{ab: 2.00, 2.00: [], nil: true, 0.00: {...}}", global(&vm, "m"));
+
+        let mut vm = Vm::default();
+        let err = run_in(&mut vm, "var m = {};\nm[m] = 1;").expect_err("expected an error");
+        assert!(err.to_string().starts_with("2:1: Map keys must be"));
+    }
+
+    #[test]
+    fn it_interpolates_values_into_new_strings() {
+        let vm = run("var n = 2;
+                      fun f() { return \"${n} items\"; }
+                      var s = \"got ${f()}: ${[n, nil]}\";
+                      var same = s == \"got 2.00 items: [2.00, nil]\";");
+        assert_eq!("got 2.00 items: [2.00, nil]", global(&vm, "s"));
+        assert_eq!("true", global(&vm, "same"));
+    }
+
+    #[test]
+    fn it_calls_natives_that_allocate_and_call_back_into_lox() {
+        let vm = run("var xs = [3, 1];
+                      push(xs, 2);
+                      var total = 0;
+                      fun add(x) { total = total + x; push(xs, x * 10); }
+                      each(slice(xs, 0, 3), add);
+                      var m = {\"a\": 1, \"b\": 2};
+                      var removed = remove(m, \"a\");
+                      var shown = str(keys(m)) + type(m) + str(len(xs));");
+        assert_eq!("6.00", global(&vm, "total"));
+        assert_eq!("[3.00, 1.00, 2.00, 30.00, 10.00, 20.00]", global(&vm, "xs"));
+        assert_eq!("1.00", global(&vm, "removed"));
+        assert_eq!("[b]map6.00", global(&vm, "shown"));
+        assert!(vm.stack.is_empty() && vm.frames.is_empty());
+
+        let mut vm = Vm::default();
+        let err = run_in(&mut vm, "pop([]);").expect_err("expected an error");
+        assert_eq!("1:1: pop: the list is empty", err.to_string());
+        run_in(&mut vm, "exit(3); print 1;").expect_err("expected exit to unwind");
+        assert_eq!(Some(3), vm.exit_code());
+    }
+
+    #[test]
+    fn it_reports_the_location_of_runtime_errors() {
+        let mut vm = Vm::default();
+        let err: VmError = run_in(&mut vm, "fun f(a) {\n  return a - \"x\";\n}\nf(1);").expect_err("expected an error");
+        assert_eq!(Some(Span::new(2, 10, 2, 16)), err.span());
+        assert_eq!(vec!["called from 4:1".to_string()], err.notes);
+        assert!(vm.stack.is_empty() && vm.frames.is_empty());
+    }
+
+    #[test]
+    fn it_collects_on_every_allocation_in_stress_mode() {
+        let vm = run("fun wrap(s) { fun get() { return s + \"!\"; } return get; }
+                      var w = wrap(\"a\" + \"b\");
+                      var out = w();");
+        assert_eq!("ab!", global(&vm, "out"));
+        assert!(vm.gc_stats().collections >= 4);
+    }
+
+    #[test]
+    fn it_frees_instances_that_reference_themselves_through_closures() {
+        let mut vm = Vm::with_gc_config(GcConfig { initial_threshold: 4096, ..GcConfig::default() });
+        run_in(&mut vm, "class Node { init() { var node = this; fun get() { return node; } this.get = get; } }
+                         for (var i = 0; i < 500; i = i + 1) { Node(); }").expect("failed to run");
+        assert!(vm.gc_stats().objects_freed > 0);
+        vm.collect_garbage();
+        // only the natives, the class, its initializer and the compiled code survive
+        let natives = super::natives::core_library().len();
+        assert!(vm.heap.live() < natives + 20, "{} objects still live", vm.heap.live());
+    }
+}
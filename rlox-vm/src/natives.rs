@@ -0,0 +1,193 @@
+use super::{Vm, VmError};
+use crate::object::{Map, Obj};
+use crate::value::Value;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type NativeFn = fn(&mut Vm, &[Value]) -> super::Result<Value>;
+
+/// A function implemented in Rust. Its arguments stay on the stack while it runs, so it may
+/// allocate without them being collected.
+#[derive(Clone, Copy)]
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: NativeFn
+}
+
+impl std::fmt::Debug for NativeFunction {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "<native fn {}/{}>", self.name, self.arity)
+    }
+}
+
+fn native(name: &'static str, arity: usize, function: NativeFn) -> NativeFunction {
+    NativeFunction { name, arity, function }
+}
+
+/// The functions every VM starts with: the same core library the tree-walking interpreter has.
+pub fn core_library() -> Vec<NativeFunction> {
+    vec![
+        native("clock", 0, |_, _| {
+            let elapsed = SystemTime::now().duration_since(UNIX_EPOCH)
+                .map_err(|e| VmError::new(format!("clock: {}", e)))?;
+            Ok(Value::Number(elapsed.as_secs_f64()))
+        }),
+        native("str", 1, |vm, args| {
+            let shown = vm.heap.display(args[0]);
+            Ok(vm.string(shown))
+        }),
+        native("num", 1, |vm, args| match args[0] {
+            Value::Number(n) => Ok(Value::Number(n)),
+            v => {
+                let contents = vm.heap.as_string(v).ok_or_else(|| type_error(vm, "num", "a number or a string", v))?;
+                contents.trim().parse().map(Value::Number)
+                    .map_err(|_| VmError::new(format!("num: can't convert '{}' to a number", contents)))
+            }
+        }),
+        native("len", 1, |vm, args| {
+            let len = match args[0].as_obj().map(|r| vm.heap.get(r)) {
+                Some(Obj::String(s)) => s.chars().count(),
+                Some(Obj::List(elements)) => elements.len(),
+                Some(Obj::Map(map)) => map.len(),
+                _ => return Err(type_error(vm, "len", "a string, a list or a map", args[0]))
+            };
+            Ok(Value::Number(len as f64))
+        }),
+        native("type", 1, |vm, args| {
+            let name = vm.heap.type_name(args[0]);
+            Ok(vm.string(name))
+        }),
+        native("input", 1, |vm, args| {
+            let prompt = vm.heap.display(args[0]);
+            write!(vm.out, "{}", prompt).and_then(|_| vm.out.flush()).map_err(|e| VmError::new(format!("input: {}", e)))?;
+            let mut line = String::new();
+            let read = std::io::stdin().lock().read_line(&mut line)
+                .map_err(|e| VmError::new(format!("input: {}", e)))?;
+            if read == 0 {
+                return Ok(Value::Nil);
+            }
+            Ok(vm.string(line.trim_end_matches(&['\r', '\n'][..])))
+        }),
+        native("sqrt", 1, |vm, args| Ok(Value::Number(number(vm, "sqrt", args[0])?.sqrt()))),
+        native("floor", 1, |vm, args| Ok(Value::Number(number(vm, "floor", args[0])?.floor()))),
+        native("abs", 1, |vm, args| Ok(Value::Number(number(vm, "abs", args[0])?.abs()))),
+        native("pow", 2, |vm, args| {
+            Ok(Value::Number(number(vm, "pow", args[0])?.powf(number(vm, "pow", args[1])?)))
+        }),
+        native("push", 2, |vm, args| {
+            list(vm, "push", args[0])?.push(args[1]);
+            Ok(Value::Nil)
+        }),
+        native("pop", 1, |vm, args| {
+            list(vm, "pop", args[0])?.pop().ok_or_else(|| VmError::new("pop: the list is empty"))
+        }),
+        native("insert", 3, |vm, args| {
+            let len = list(vm, "insert", args[0])?.len();
+            // inserting at the end is allowed
+            let i = super::list_position(&vm.heap, args[1], len)?;
+            list(vm, "insert", args[0])?.insert(i, args[2]);
+            Ok(Value::Nil)
+        }),
+        native("remove", 2, |vm, args| {
+            if let Some(Obj::Map(_)) = args[0].as_obj().map(|r| vm.heap.get(r)) {
+                let key = vm.map_key(args[1])?;
+                return Ok(map(vm, "remove", args[0])?.remove(&key).unwrap_or(Value::Nil));
+            }
+            let len = list(vm, "remove", args[0])?.len();
+            let i = super::list_index(&vm.heap, args[1], len)?;
+            Ok(list(vm, "remove", args[0])?.remove(i))
+        }),
+        native("slice", 3, |vm, args| {
+            let len = list(vm, "slice", args[0])?.len();
+            let start = super::list_position(&vm.heap, args[1], len)?;
+            let end = super::list_position(&vm.heap, args[2], len)?;
+            if start > end {
+                return Err(VmError::new(format!("slice: start {} is after end {}", start, end)));
+            }
+            let sliced = list(vm, "slice", args[0])?[start..end].to_vec();
+            Ok(Value::Obj(vm.alloc(Obj::List(sliced))))
+        }),
+        native("each", 2, |vm, args| {
+            list(vm, "each", args[0])?;
+            // look the list up again on every step, so the callback may change it
+            for i in 0.. {
+                let v = match list(vm, "each", args[0])?.get(i) {
+                    Some(v) => *v,
+                    None => break
+                };
+                vm.call_from_native(args[1], &[v])?;
+            }
+            Ok(Value::Nil)
+        }),
+        native("keys", 1, |vm, args| {
+            let keys = map(vm, "keys", args[0])?.entries.iter().map(|(k, _)| *k).collect();
+            Ok(Value::Obj(vm.alloc(Obj::List(keys))))
+        }),
+        native("values", 1, |vm, args| {
+            let values = map(vm, "values", args[0])?.entries.iter().map(|(_, v)| *v).collect();
+            Ok(Value::Obj(vm.alloc(Obj::List(values))))
+        }),
+        native("has", 2, |vm, args| {
+            let key = vm.map_key(args[1])?;
+            Ok(Value::Bool(map(vm, "has", args[0])?.contains(&key)))
+        }),
+        native("exit", 1, |vm, args| {
+            let code = exit_code(number(vm, "exit", args[0])?)?;
+            vm.exit_code = Some(code);
+            // unwinds the running program; execute_source sees the exit code and stops quietly
+            Err(VmError::new(format!("exit({})", code)))
+        })
+    ]
+}
+
+impl Vm {
+    /// Allocates a string, for natives to return.
+    fn string<B>(&mut self, s: B) -> Value where B : AsRef<str> {
+        Value::Obj(self.alloc(Obj::String(Rc::from(s.as_ref()))))
+    }
+}
+
+/// Checks that `code` is something a process can exit with.
+fn exit_code(code: f64) -> super::Result<i32> {
+    if code.fract() != 0.0 {
+        Err(VmError::new(format!("exit: the code must be a whole number, got {}", code)))
+    } else if !(0.0..=255.0).contains(&code) {
+        Err(VmError::new(format!("exit: the code must be from 0 to 255, got {}", code)))
+    } else {
+        Ok(code as i32)
+    }
+}
+
+fn number(vm: &Vm, name: &str, v: Value) -> super::Result<f64> {
+    match v {
+        Value::Number(n) => Ok(n),
+        _ => Err(type_error(vm, name, "a number", v))
+    }
+}
+
+fn list<'a>(vm: &'a mut Vm, name: &str, v: Value) -> super::Result<&'a mut Vec<Value>> {
+    match v.as_obj() {
+        Some(r) if matches!(vm.heap.get(r), Obj::List(_)) => match vm.heap.get_mut(r) {
+            Obj::List(elements) => Ok(elements),
+            _ => unreachable!()
+        },
+        _ => Err(type_error(vm, name, "a list", v))
+    }
+}
+
+fn map<'a>(vm: &'a mut Vm, name: &str, v: Value) -> super::Result<&'a mut Map> {
+    match v.as_obj() {
+        Some(r) if matches!(vm.heap.get(r), Obj::Map(_)) => match vm.heap.get_mut(r) {
+            Obj::Map(map) => Ok(map),
+            _ => unreachable!()
+        },
+        _ => Err(type_error(vm, name, "a map", v))
+    }
+}
+
+fn type_error(vm: &Vm, name: &str, expected: &str, got: Value) -> VmError {
+    VmError::new(format!("{} expects {}, got {}", name, expected, vm.heap.type_name(got)))
+}
@@ -0,0 +1,515 @@
+use crate::chunk::{Chunk, OpCode};
+use crate::natives::NativeFunction;
+use crate::value::Value;
+use log::{debug, trace};
+use rlox_contract::Span;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::rc::Rc;
+
+/// A handle to an object stored in the [`Heap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(usize);
+
+#[derive(Debug)]
+pub enum Obj {
+    String(Rc<str>),
+    Function(Rc<Function>),
+    Closure(Closure),
+    Upvalue(Upvalue),
+    Class(Class),
+    Instance(Instance),
+    BoundMethod(BoundMethod),
+    List(Vec<Value>),
+    Map(Map),
+    Native(NativeFunction)
+}
+
+/// A compiled function. Functions are immutable once compiled, so call frames share them
+/// through an `Rc` rather than going back to the heap for every instruction.
+#[derive(Debug)]
+pub struct Function {
+    pub name: Option<Rc<str>>,
+    pub arity: usize,
+    pub upvalues: Vec<UpvalueDescriptor>,
+    pub chunk: Chunk
+}
+
+/// Where a closure finds a captured variable when it is created: a local slot of the enclosing
+/// function, or one of the enclosing closure's own upvalues.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpvalueDescriptor {
+    pub is_local: bool,
+    pub index: u8
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: ObjRef,
+    pub upvalues: Vec<ObjRef>
+}
+
+/// A captured variable. It points into the stack while the variable is still live and holds
+/// the value itself once the variable goes out of scope.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value)
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: Rc<str>,
+    pub methods: HashMap<Rc<str>, ObjRef>
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: ObjRef,
+    pub fields: HashMap<Rc<str>, Value>
+}
+
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef
+}
+
+/// The hashable form of a value used as a map key. Strings are keyed by their contents, and
+/// numbers by value so that `0` and `-0` are the same key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Nil,
+    Bool(bool),
+    Number(u64),
+    String(Rc<str>)
+}
+
+/// An associative array that keeps its entries in the order their keys were first inserted.
+#[derive(Debug, Default)]
+pub struct Map {
+    pub entries: Vec<(Value, Value)>,
+    index: HashMap<MapKey, usize>
+}
+
+impl Map {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<Value> {
+        self.index.get(key).map(|i| self.entries[*i].1)
+    }
+
+    pub fn contains(&self, key: &MapKey) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Removes `key` and returns its value, keeping the order of the other entries.
+    pub fn remove(&mut self, key: &MapKey) -> Option<Value> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for position in self.index.values_mut() {
+            if *position > i {
+                *position -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    /// Sets the value stored under `hashed`, keeping the entry's position if it is already present.
+    pub fn insert(&mut self, hashed: MapKey, key: Value, value: Value) {
+        match self.index.get(&hashed) {
+            Some(i) => self.entries[*i].1 = value,
+            None => {
+                self.index.insert(hashed, self.entries.len());
+                // keep the key the way it is hashed, so -0 is shown as 0
+                let key = match key {
+                    Value::Number(n) => Value::Number(n + 0.0),
+                    k => k
+                };
+                self.entries.push((key, value));
+            }
+        }
+    }
+}
+
+/// Controls when the heap is collected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GcConfig {
+    /// Bytes allocated before the first collection.
+    pub initial_threshold: usize,
+    /// After a collection, the next one happens once the heap has grown to this multiple of
+    /// what survived.
+    pub growth_factor: usize,
+    /// Collect before every allocation the VM makes, to shake out values that aren't rooted.
+    pub stress: bool
+}
+
+impl Default for GcConfig {
+    fn default() -> GcConfig {
+        GcConfig { initial_threshold: 1024 * 1024, growth_factor: 2, stress: false }
+    }
+}
+
+impl GcConfig {
+    pub fn stress() -> GcConfig {
+        GcConfig { stress: true, ..GcConfig::default() }
+    }
+}
+
+/// Running totals over every collection a heap has done.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    pub collections: usize,
+    pub objects_freed: usize,
+    pub bytes_freed: usize
+}
+
+/// Owns every object created by the compiler and the VM, and frees those that are no longer
+/// reachable with a mark-and-sweep collection. Slots of freed objects are reused, so a handle is
+/// only valid while the object it names is reachable.
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<Option<Obj>>,
+    /// Approximate size of each object when it was allocated.
+    sizes: Vec<usize>,
+    marks: Vec<bool>,
+    free: Vec<usize>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    config: GcConfig,
+    stats: GcStats
+}
+
+impl Default for Heap {
+    fn default() -> Heap {
+        Heap::with_config(GcConfig::default())
+    }
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap::default()
+    }
+
+    pub fn with_config(config: GcConfig) -> Heap {
+        Heap {
+            objects: Vec::new(),
+            sizes: Vec::new(),
+            marks: Vec::new(),
+            free: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: config.initial_threshold,
+            config,
+            stats: GcStats::default()
+        }
+    }
+
+    /// Stores `obj` on the heap. This never collects; owners of roots decide when to do that
+    /// through [`Heap::should_collect`] and [`Heap::collect`].
+    pub fn alloc(&mut self, obj: Obj) -> ObjRef {
+        let size = size_of_obj(&obj);
+        self.bytes_allocated += size;
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index] = Some(obj);
+                self.sizes[index] = size;
+                ObjRef(index)
+            },
+            None => {
+                self.objects.push(Some(obj));
+                self.sizes.push(size);
+                self.marks.push(false);
+                ObjRef(self.objects.len() - 1)
+            }
+        }
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.config.stress || self.bytes_allocated > self.next_gc
+    }
+
+    /// Frees every object not reachable from `roots`.
+    pub fn collect(&mut self, roots: &[Value]) {
+        trace!("gc: begin with {} bytes allocated", self.bytes_allocated);
+        let before = self.bytes_allocated;
+
+        let mut gray = Vec::new();
+        for root in roots {
+            mark_value(&mut self.marks, &mut gray, *root);
+        }
+        // trace through everything reachable from the roots
+        while let Some(r) = gray.pop() {
+            let marks = &mut self.marks;
+            match self.objects[r.0].as_ref().expect("marked a freed object") {
+                Obj::String(_) | Obj::Native(_) | Obj::Upvalue(Upvalue::Open(_)) => (),
+                Obj::Upvalue(Upvalue::Closed(v)) => mark_value(marks, &mut gray, *v),
+                Obj::Function(f) => {
+                    for c in &f.chunk.constants {
+                        mark_value(marks, &mut gray, *c);
+                    }
+                },
+                Obj::Closure(c) => {
+                    mark_object(marks, &mut gray, c.function);
+                    for u in &c.upvalues {
+                        mark_object(marks, &mut gray, *u);
+                    }
+                },
+                Obj::Class(c) => {
+                    for m in c.methods.values() {
+                        mark_object(marks, &mut gray, *m);
+                    }
+                },
+                Obj::Instance(i) => {
+                    mark_object(marks, &mut gray, i.class);
+                    for v in i.fields.values() {
+                        mark_value(marks, &mut gray, *v);
+                    }
+                },
+                Obj::BoundMethod(b) => {
+                    mark_value(marks, &mut gray, b.receiver);
+                    mark_object(marks, &mut gray, b.method);
+                },
+                Obj::List(elements) => {
+                    for v in elements {
+                        mark_value(marks, &mut gray, *v);
+                    }
+                },
+                Obj::Map(map) => {
+                    for (k, v) in &map.entries {
+                        mark_value(marks, &mut gray, *k);
+                        mark_value(marks, &mut gray, *v);
+                    }
+                }
+            }
+        }
+
+        let mut freed = 0;
+        for index in 0..self.objects.len() {
+            if self.marks[index] {
+                self.marks[index] = false;
+            } else if self.objects[index].is_some() {
+                self.objects[index] = None;
+                self.bytes_allocated -= self.sizes[index];
+                self.free.push(index);
+                freed += 1;
+            }
+        }
+
+        self.next_gc = (self.bytes_allocated * self.config.growth_factor).max(self.config.initial_threshold);
+        self.stats.collections += 1;
+        self.stats.objects_freed += freed;
+        self.stats.bytes_freed += before - self.bytes_allocated;
+        debug!("gc: freed {} objects ({} bytes), {} objects ({} bytes) live, next collection at {} bytes",
+            freed, before - self.bytes_allocated, self.live(), self.bytes_allocated, self.next_gc);
+    }
+
+    /// Number of objects currently on the heap.
+    pub fn live(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    pub fn get(&self, r: ObjRef) -> &Obj {
+        self.objects[r.0].as_ref().expect("dangling object reference")
+    }
+
+    pub fn get_mut(&mut self, r: ObjRef) -> &mut Obj {
+        self.objects[r.0].as_mut().expect("dangling object reference")
+    }
+
+    pub fn string(&self, r: ObjRef) -> &Rc<str> {
+        match self.get(r) {
+            Obj::String(s) => s,
+            o => unreachable!("expected a string, got {:?}", o)
+        }
+    }
+
+    pub fn function(&self, r: ObjRef) -> &Rc<Function> {
+        match self.get(r) {
+            Obj::Function(f) => f,
+            o => unreachable!("expected a function, got {:?}", o)
+        }
+    }
+
+    pub fn closure(&self, r: ObjRef) -> &Closure {
+        match self.get(r) {
+            Obj::Closure(c) => c,
+            o => unreachable!("expected a closure, got {:?}", o)
+        }
+    }
+
+    pub fn class(&self, r: ObjRef) -> &Class {
+        match self.get(r) {
+            Obj::Class(c) => c,
+            o => unreachable!("expected a class, got {:?}", o)
+        }
+    }
+
+    /// Returns the string held by `value`, if it is one.
+    pub fn as_string(&self, value: Value) -> Option<&Rc<str>> {
+        match value {
+            Value::Obj(r) => match self.get(r) {
+                Obj::String(s) => Some(s),
+                _ => None
+            },
+            _ => None
+        }
+    }
+
+    /// Returns the key `value` is stored under in a map, or `None` if it can't be a key.
+    pub fn map_key(&self, value: Value) -> Option<MapKey> {
+        match value {
+            Value::Nil => Some(MapKey::Nil),
+            Value::Bool(b) => Some(MapKey::Bool(b)),
+            Value::Number(n) if n.is_nan() => None,
+            Value::Number(n) => Some(MapKey::Number((n + 0.0).to_bits())),
+            Value::Obj(_) => self.as_string(value).map(|s| MapKey::String(s.clone()))
+        }
+    }
+
+    /// The name `type()` gives the kind of `value`.
+    pub fn type_name(&self, value: Value) -> &'static str {
+        match value {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::Obj(r) => match self.get(r) {
+                Obj::String(_) => "string",
+                Obj::Function(_) | Obj::Closure(_) | Obj::BoundMethod(_) | Obj::Native(_) => "function",
+                Obj::Class(_) => "class",
+                Obj::Instance(_) => "instance",
+                Obj::List(_) => "list",
+                Obj::Map(_) => "map",
+                Obj::Upvalue(_) => "upvalue"
+            }
+        }
+    }
+
+    /// Strings compare by contents, every other object by identity.
+    pub fn values_equal(&self, a: Value, b: Value) -> bool {
+        match (self.as_string(a), self.as_string(b)) {
+            (Some(l), Some(r)) => l == r,
+            _ => a == b
+        }
+    }
+
+    pub fn display(&self, value: Value) -> String {
+        self.display_nested(value, &mut Vec::new())
+    }
+
+    /// Displays `value`, printing lists that contain themselves as `[...]`.
+    fn display_nested(&self, value: Value, seen: &mut Vec<ObjRef>) -> String {
+        match value {
+            Value::Nil => "nil".to_string(),
+            Value::Bool(b) => format!("{}", b),
+            Value::Number(n) => format!("{:.2}", n),
+            Value::Obj(r) => match self.get(r) {
+                Obj::String(s) => s.to_string(),
+                Obj::Function(f) => display_function(f),
+                Obj::Closure(c) => display_function(self.function(c.function)),
+                Obj::Upvalue(_) => "upvalue".to_string(),
+                Obj::Class(c) => c.name.to_string(),
+                Obj::Instance(i) => format!("{} instance", self.class(i.class).name),
+                Obj::BoundMethod(b) => self.display(Value::Obj(b.method)),
+                Obj::Native(n) => format!("<native fn {}>", n.name),
+                Obj::List(_) | Obj::Map(_) if seen.contains(&r) => {
+                    if let Obj::List(_) = self.get(r) { "[...]" } else { "{...}" }.to_string()
+                },
+                Obj::List(elements) => {
+                    seen.push(r);
+                    let shown: Vec<String> = elements.iter().map(|v| self.display_nested(*v, seen)).collect();
+                    seen.pop();
+                    format!("[{}]", shown.join(", "))
+                },
+                Obj::Map(map) => {
+                    seen.push(r);
+                    let shown: Vec<String> = map.entries.iter()
+                        .map(|(k, v)| format!("{}: {}", self.display(*k), self.display_nested(*v, seen)))
+                        .collect();
+                    seen.pop();
+                    format!("{{{}}}", shown.join(", "))
+                }
+            }
+        }
+    }
+}
+
+fn mark_value(marks: &mut [bool], gray: &mut Vec<ObjRef>, v: Value) {
+    if let Value::Obj(r) = v {
+        mark_object(marks, gray, r);
+    }
+}
+
+fn mark_object(marks: &mut [bool], gray: &mut Vec<ObjRef>, r: ObjRef) {
+    if !marks[r.0] {
+        marks[r.0] = true;
+        gray.push(r);
+    }
+}
+
+/// Estimates how much memory `obj` holds. Fields added to an instance after it is allocated are
+/// not counted, which only makes collections a little later than they would otherwise be.
+fn size_of_obj(obj: &Obj) -> usize {
+    size_of::<Obj>() + match obj {
+        Obj::String(s) => s.len(),
+        Obj::Function(f) => size_of::<Function>()
+            + f.chunk.code.len() * size_of::<OpCode>()
+            + f.chunk.spans.len() * size_of::<Span>()
+            + f.chunk.constants.len() * size_of::<Value>(),
+        Obj::Closure(c) => c.upvalues.len() * size_of::<ObjRef>(),
+        Obj::Class(c) => c.name.len() + c.methods.len() * size_of::<(Rc<str>, ObjRef)>(),
+        Obj::Instance(i) => i.fields.len() * size_of::<(Rc<str>, Value)>(),
+        Obj::List(elements) => elements.len() * size_of::<Value>(),
+        Obj::Map(map) => map.entries.len() * (size_of::<(Value, Value)>() + size_of::<(MapKey, usize)>()),
+        Obj::Upvalue(_) | Obj::BoundMethod(_) | Obj::Native(_) => 0
+    }
+}
+
+fn display_function(f: &Function) -> String {
+    match &f.name {
+        Some(name) => format!("<fn {}>", name),
+        None => "<script>".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Heap, Instance, Obj};
+    use crate::value::Value;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[test]
+    fn it_frees_unreachable_objects_and_reuses_their_slots() {
+        let mut heap = Heap::new();
+        let kept = heap.alloc(Obj::String(Rc::from("kept")));
+        let dropped = heap.alloc(Obj::String(Rc::from("dropped")));
+        let instance = heap.alloc(Obj::Instance(Instance { class: kept, fields: HashMap::new() }));
+        if let Obj::Instance(i) = heap.get_mut(instance) {
+            // an instance that refers to itself is still garbage once nothing else does
+            i.fields.insert(Rc::from("me"), Value::Obj(instance));
+        }
+
+        heap.collect(&[Value::Obj(kept)]);
+        assert_eq!(1, heap.live());
+        assert_eq!(2, heap.stats().objects_freed);
+        assert_eq!("kept", heap.string(kept).as_ref());
+
+        let reused = heap.alloc(Obj::String(Rc::from("new")));
+        assert!(reused == dropped || reused == instance);
+        assert_eq!(2, heap.live());
+    }
+}
@@ -0,0 +1,24 @@
+use crate::object::ObjRef;
+
+/// A value on the VM stack. Anything larger than a number lives on the heap and is referred to
+/// by handle, so values are cheap to copy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Obj(ObjRef)
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn as_obj(&self) -> Option<ObjRef> {
+        match self {
+            Value::Obj(r) => Some(*r),
+            _ => None
+        }
+    }
+}
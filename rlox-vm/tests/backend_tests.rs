@@ -0,0 +1,196 @@
+extern crate rlox_vm;
+extern crate rlox_interpreter;
+
+
+#[cfg(test)]
+mod backend_tests {
+    use rlox_interpreter::{BufferSink, Interpreter};
+    use rlox_vm::Vm;
+
+    /// What running `source` on one backend printed to stdout and stderr, and whether it failed.
+    #[derive(Debug, PartialEq)]
+    struct Run {
+        out: String,
+        err: String,
+        failed: bool
+    }
+
+    fn tree(source: &str) -> Run {
+        let (out, err) = (BufferSink::new(), BufferSink::new());
+        let failed = Interpreter::with_output(out.clone(), err.clone()).execute_source(source).is_err();
+        Run { out: out.contents(), err: err.contents(), failed }
+    }
+
+    fn vm(source: &str) -> Run {
+        let (out, err) = (BufferSink::new(), BufferSink::new());
+        let failed = Vm::with_output(out.clone(), err.clone()).execute_source(source).is_err();
+        Run { out: out.contents(), err: err.contents(), failed }
+    }
+
+    /// Runs `source` on both backends, checks they behaved the same and returns what happened.
+    fn both(source: &str) -> Run {
+        let run = tree(source);
+        assert_eq!(run, vm(source), "backends disagree on:\n{}", source);
+        run
+    }
+
+    #[test]
+    fn it_runs_the_examples_the_same_on_both_backends() {
+        for source in [
+            include_str!("../../rlox-cli/examples/example_p.lox"),
+            include_str!("../../rlox-cli/examples/fib.lox"),
+            include_str!("../../rlox-cli/examples/for_loop.lox"),
+            include_str!("../../rlox-cli/examples/function_ex.lox"),
+            include_str!("../../rlox-cli/examples/interpolation.lox")
+        ] {
+            let run = both(source);
+            assert!(!run.out.is_empty() && !run.failed, "{:?}", run);
+        }
+    }
+
+    #[test]
+    fn it_gives_assignments_the_assigned_value() {
+        let run = both("var a; var b; a = b = 3; print a; print b;
+                        var c = [0]; var d = c[0] = 4; print d;
+                        class P {} var p = P(); print p.x = 5;");
+        assert_eq!("3.00\n3.00\n4.00\n5.00\n", run.out);
+    }
+
+    #[test]
+    fn it_prints_only_what_print_statements_print() {
+        let run = both("fun c() { return 1; } c(); 1 + 2; \"quiet\"; print \"loud\";");
+        assert_eq!("loud\n", run.out);
+    }
+
+    #[test]
+    fn it_fails_reading_undefined_globals() {
+        let run = both("print \"before\";\nprint missing;\nprint \"after\";");
+        assert_eq!("before\n", run.out);
+        assert!(run.failed);
+        assert!(run.err.contains("Undefined variable missing"), "{}", run.err);
+    }
+
+    #[test]
+    fn it_reports_the_real_length_of_lists_indexed_out_of_bounds() {
+        for (source, message) in [
+            ("slice([1, 2, 3], 0, 10);", "Index 10 out of bounds for list of length 3"),
+            ("slice([1, 2, 3], 4, 3);", "Index 4 out of bounds for list of length 3"),
+            ("slice([1, 2, 3], 2, 1);", "slice: start 2 is after end 1"),
+            ("insert([1], 5, 2);", "Index 5 out of bounds for list of length 1"),
+            ("remove([1], 1);", "Index 1 out of bounds for list of length 1")
+        ] {
+            let run = both(source);
+            assert!(run.failed && run.err.contains(message), "{}: {}", source, run.err);
+        }
+        let run = both("var xs = [1, 2]; insert(xs, 2, 3); print slice(xs, 3, 3); print slice(xs, 0, 3);");
+        assert_eq!("[]\n[1.00, 2.00, 3.00]\n", run.out);
+    }
+
+    #[test]
+    fn it_reports_the_same_runtime_errors() {
+        for (source, message) in [
+            ("print \"a\" - 1;", "Operator MINUS expects two numbers, got string and number"),
+            ("print 1 + nil;", "Operator PLUS expects two numbers or two strings, got number and nil"),
+            ("print [] * 2;", "Operator STAR expects two numbers, got list and number"),
+            ("print true / 2;", "Operator SLASH expects two numbers, got bool and number"),
+            ("print 1 > \"b\";", "Operator GREATER expects two numbers, got number and string"),
+            ("print 1 >= nil;", "Operator GREATEREQUAL expects two numbers, got number and nil"),
+            ("fun f() {} print f < 1;", "Operator LESS expects two numbers, got function and number"),
+            ("class C {} print C <= 1;", "Operator LESSEQUAL expects two numbers, got class and number"),
+            ("print -\"x\";", "Expected number, got string"),
+            ("print [1][\"0\"];", "List index must be a number, got string"),
+            ("var xs = [1]; xs[nil] = 2;", "List index must be a number, got nil"),
+            ("print 1[0];", "Only lists and maps can be indexed, got number")
+        ] {
+            let run = both(source);
+            assert!(run.failed && run.err.contains(message), "{}: {}", source, run.err);
+        }
+    }
+
+    #[test]
+    fn it_only_exits_with_codes_a_process_can_have() {
+        for (source, message) in [
+            ("exit(1.5);", "exit: the code must be a whole number, got 1.5"),
+            ("exit(-1);", "exit: the code must be from 0 to 255, got -1"),
+            ("exit(256);", "exit: the code must be from 0 to 255, got 256"),
+            ("exit(sqrt(-1));", "exit: the code must be a whole number, got NaN"),
+            ("exit(pow(2, 32));", "exit: the code must be from 0 to 255, got 4294967296")
+        ] {
+            let run = both(source);
+            assert!(run.failed && run.err.contains(message), "{}: {}", source, run.err);
+        }
+    }
+
+    #[test]
+    fn it_lets_globals_be_declared_again() {
+        let run = both("var a = 1; var a = 2; print a;
+                        fun f() { return 1; } fun f() { return 2; } print f();
+                        class C {} var C = 3; print C;");
+        assert_eq!("2.00\n2.00\n3.00\n", run.out);
+        assert!(!run.failed, "{}", run.err);
+    }
+
+    #[test]
+    fn it_runs_closures_classes_and_collections_the_same() {
+        let run = both(r#"
+            fun counter() { var i = 0; fun inc() { i = i + 1; return i; } return inc; }
+            var next = counter();
+            next(); print next();
+            class A { init(n) { this.n = n; } show() { return "A${this.n}"; } }
+            class B < A { show() { return super.show() + "B"; } }
+            print B(1).show();
+            var xs = [1, "two", [nil, true]];
+            xs[2][0] = xs[0] + 1;
+            print xs;
+            var m = {"a": 1, 2: "b"};
+            m["c"] = m["a"] + 1;
+            print m;
+            for (var i = 0; i < 5; i = i + 1) { if (i == 1) continue; if (i == 3) break; print i; }
+        "#);
+        assert_eq!("2.00\nA1.00B\n[1.00, two, [2.00, true]]\n{a: 1.00, 2.00: b, c: 2.00}\n0.00\n2.00\n", run.out);
+    }
+
+    #[test]
+    fn it_runs_the_list_map_and_clock_examples_the_same() {
+        let lists = both(r#"
+            var xs = [1, 2, 3];
+            xs[0] = "one";
+            push(xs, 4);
+            print xs;
+            insert(xs, 0, 0);
+            print remove(xs, 1);
+            print pop(xs);
+            print slice(xs, 1, 3);
+        "#);
+        assert_eq!("[one, 2.00, 3.00, 4.00]\none\n4.00\n[2.00, 3.00]\n", lists.out);
+
+        let lists = both(r#"
+            var xs = [1, 2, 3];
+            fun show(x) { print "item ${x}"; }
+            each(xs, show);
+            print len(xs) + len("héllo");
+            print xs[4];
+        "#);
+        assert!(lists.failed);
+        assert!(lists.err.contains("Index 4 out of bounds for list of length 3"), "{}", lists.err);
+        assert_eq!("item 1.00\nitem 2.00\nitem 3.00\n8.00\n", lists.out);
+
+        let maps = both(r#"
+            var config = {"name": "rlox", "version": 2};
+            config["debug"] = true;
+            print config["name"];
+            print keys(config);
+            print values(config);
+            print has(config, "debug");
+            print remove(config, "name");
+            print remove(config, "missing");
+            print config;
+            print type(config) + " " + type(keys) + " " + str(num("4.5") + 1);
+        "#);
+        assert_eq!("rlox\n[name, version, debug]\n[rlox, 2.00, true]\ntrue\nrlox\nnil\n{version: 2.00, debug: true}\nmap function 5.50\n",
+            maps.out);
+
+        let clock = both("var start = clock(); print type(start); print clock() >= start; print floor(pow(2, 3) + sqrt(abs(-4)));");
+        assert_eq!("number\ntrue\n10.00\n", clock.out);
+    }
+}